@@ -5,12 +5,16 @@
 //! stream() a RiveScript document (or several). It contains all of the useful
 //! inner contents of the RiveScript brain, including global bot variables,
 //! substitutions and other configuration that was defined via RiveScript.
+//!
+//! With the `serde` feature enabled, the AST and its parts can be serialized
+//! (for example to JSON) and deserialized back again.
 
-use std::{collections::HashMap, sync::RwLock};
+use std::{collections::HashMap, fmt, sync::RwLock};
 
 /// Root of the "abstract syntax tree" representing a RiveScript
 /// source document and its useful contents.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AST {
     // Configuration fields typically found in 'begin.rive'
     pub version: f32,                         // ! version
@@ -36,11 +40,52 @@ pub struct AST {
 /// in their current topic, or any triggers that are 'included' or
 /// 'inherited' into their current topic.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Topic {
     pub name: String,
     pub triggers: Vec<Trigger>,
     pub includes: HashMap<String, bool>,
     pub inherits: HashMap<String, bool>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub location: Location,
+}
+
+/// Location records where a piece of the AST was defined in the source code.
+///
+/// The `start` and `end` are the (inclusive) range of line numbers, counting
+/// from 1, that the item spans. For example a trigger's location starts at its
+/// `+ Trigger` line and ends with its last `- Reply` (or `^ Continue`) line.
+///
+/// Items that were not parsed from RiveScript source (such as the implicit
+/// "random" topic) have a default location with an empty filename.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    pub filename: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Location {
+    pub fn new(filename: &str, start: usize, end: usize) -> Self {
+        Self {
+            filename: filename.to_string(),
+            start,
+            end,
+        }
+    }
+
+    /// Returns true if this location refers to a line of source code.
+    pub fn is_known(&self) -> bool {
+        !self.filename.is_empty()
+    }
+}
+
+/// Locations display like `eg/brain/rpg.rive:42`
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.filename, self.start)
+    }
 }
 
 impl AST {
@@ -86,6 +131,11 @@ impl AST {
             match self.topics.get_mut(&name) {
                 Some(mine) => {
                     mine.triggers.extend(topic.triggers);
+                    mine.includes.extend(topic.includes);
+                    mine.inherits.extend(topic.inherits);
+                    if !mine.location.is_known() {
+                        mine.location = topic.location;
+                    }
                 }
                 None => {
                     self.topics.insert(name, topic);
@@ -100,15 +150,7 @@ impl AST {
             return;
         }
 
-        self.topics.insert(
-            name.to_string(),
-            Topic {
-                name: name.to_string(),
-                triggers: Vec::new(),
-                includes: HashMap::new(),
-                inherits: HashMap::new(),
-            },
-        );
+        self.topics.insert(name.to_string(), Topic::new(name));
     }
 
     /// Returns true if a >begin section exists.
@@ -153,6 +195,16 @@ impl AST {
 }
 
 impl Topic {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            triggers: Vec::new(),
+            includes: HashMap::new(),
+            inherits: HashMap::new(),
+            location: Location::default(),
+        }
+    }
+
     pub fn set_includes(&mut self, includes: String) {
         self.includes.insert(includes.to_string(), true);
     }
@@ -173,12 +225,15 @@ impl Topic {
 /// pair a set of replies (multiple OK, which will be chosen at random)
 /// to be sent when that trigger is matched.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Trigger {
     pub trigger: String,
     pub reply: Vec<String>,
     pub condition: Vec<Condition>,
     pub redirect: String,
     pub previous: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub location: Location,
 }
 
 impl Trigger {
@@ -189,6 +244,7 @@ impl Trigger {
             condition: Vec::new(),
             redirect: String::from(""),
             previous: String::from(""),
+            location: Location::default(),
         }
     }
 
@@ -199,11 +255,28 @@ impl Trigger {
 
 /// Condition holds the contents of a *Condition command in RiveScript.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Condition {
     pub left: String,
     pub operator: String,
     pub right: String,
     pub reply: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub location: Location,
+}
+
+impl Condition {
+    /// A condition like `* <get name> == undefined => What's your name?`, which would be
+    /// `Condition::new("<get name>", "==", "undefined", "What's your name?")`.
+    pub fn new(left: &str, operator: &str, right: &str, reply: &str) -> Self {
+        Self {
+            left: left.to_string(),
+            operator: operator.to_string(),
+            right: right.to_string(),
+            reply: reply.to_string(),
+            location: Location::default(),
+        }
+    }
 }
 
 /// Object represents a parsed object macro from a RiveScript source document.
@@ -212,11 +285,18 @@ pub struct Condition {
 /// source code as defined in the RiveScript document. It is up to the
 /// interpreter program to understand how to parse an object macro and make
 /// it executable.
+///
+/// The source code lines are kept exactly as they appear in the document, with
+/// their indentation and any blank lines, so that indentation-sensitive languages
+/// (such as Python) can be loaded correctly.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Object {
     pub name: String,
     pub language: String,
     pub code: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub location: Location,
 }
 
 impl Object {
@@ -225,6 +305,18 @@ impl Object {
             name: name.to_string(),
             language: language.to_string(),
             code,
+            location: Location::default(),
         }
     }
+
+    /// Iterate over the lines of source code along with their line numbers.
+    ///
+    /// The line numbers are relative to the object's location, so they point into
+    /// the original RiveScript document if the object was parsed from one.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.code
+            .iter()
+            .enumerate()
+            .map(|(i, line)| (self.location.start + i + 1, line.as_str()))
+    }
 }
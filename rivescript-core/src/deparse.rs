@@ -0,0 +1,230 @@
+//! # Deparse
+//!
+//! The deparser is the opposite of the Parser: it turns an AST back into the
+//! text of a RiveScript source document. This lets you build (or modify) a bot
+//! brain in code and save it back to disk as a `.rive` file.
+//!
+//! Parsing the deparsed source code again gives an equivalent AST (aside from
+//! the source code locations of its items, which now point into the new file,
+//! and whitespace at the end of a value, which the parser strips from each line).
+//! Empty arrays are left out with a warning, as RiveScript can't write them.
+
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::ast::{Topic, Trigger, AST};
+
+/// Convert an AST into RiveScript source code.
+///
+/// The output is deterministic: definitions and topics are written in sorted
+/// order, while the triggers of each topic keep their original order.
+pub fn deparse(ast: &AST) -> String {
+    let mut writer = Writer::new();
+
+    // ! definitions
+    if ast.version != 0.0 {
+        writer.line("", "!", &format!("version = {}", format_version(ast.version)));
+        writer.blank();
+    }
+
+    let globals = ast.globals.read().expect("RwLock poisoned");
+    let vars = ast.vars.read().expect("RwLock poisoned");
+    writer.definitions("global", &globals);
+    writer.definitions("var", &vars);
+    writer.definitions("sub", &ast.subs);
+    writer.definitions("person", &ast.person);
+
+    if !ast.arrays.is_empty() {
+        for name in sorted_keys(&ast.arrays) {
+            if ast.arrays[name].is_empty() {
+                warn!("Skipping the empty array '{name}', which can't be written in RiveScript");
+                continue;
+            }
+            let items: Vec<String> = ast.arrays[name]
+                .iter()
+                .map(|item| item.replace(' ', "\\s"))
+                .collect();
+
+            // An item of "//" between spaces would start an inline comment, so use pipes.
+            let separator = if items.iter().any(|item| item == "//") { "|" } else { " " };
+            writer.line("", "!", &format!("array {name} = {}", items.join(separator)));
+        }
+        writer.blank();
+    }
+
+    // > begin
+    if let Some(topic) = ast.topics.get(crate::BEGIN_TOPIC) {
+        writer.topic(topic);
+    }
+
+    // The "random" topic doesn't need a label, unless it includes or inherits others.
+    if let Some(topic) = ast.topics.get(crate::DEFAULT_TOPIC) {
+        if topic.includes.is_empty() && topic.inherits.is_empty() {
+            for trigger in topic.triggers.iter() {
+                writer.trigger("", trigger);
+            }
+        } else {
+            writer.topic(topic);
+        }
+    }
+
+    // All the other topics.
+    for name in sorted_keys(&ast.topics) {
+        if name == crate::BEGIN_TOPIC || name == crate::DEFAULT_TOPIC {
+            continue;
+        }
+        writer.topic(&ast.topics[name]);
+    }
+
+    // > object macros
+    for name in sorted_keys(&ast.objects) {
+        let object = &ast.objects[name];
+        writer.line("", ">", format!("object {} {}", object.name, object.language).trim());
+        for code in object.code.iter() {
+            writer.out.push_str(code);
+            writer.out.push('\n');
+        }
+        writer.line("", "<", "object");
+        writer.blank();
+    }
+
+    writer.out.trim_end().to_string() + "\n"
+}
+
+/// The output buffer for the deparser.
+struct Writer {
+    out: String,
+
+    // The `! local concat` mode that ^Continue lines are being joined with.
+    concat: &'static str,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            out: String::new(),
+            concat: "none",
+        }
+    }
+
+    /// Write a command, splitting multi-line values into ^Continue lines.
+    ///
+    /// There's no way to escape an inline `// comment`, so a value with ` // ` in it is
+    /// split into ^Continue lines that are joined back together with no concat mode.
+    fn line(&mut self, indent: &str, cmd: &str, value: &str) {
+        let mut parts: Vec<String> = value.split('\n').map(str::to_string).collect();
+        let comment = parts.iter().any(|part| part.contains(" // "));
+
+        if parts.len() > 1 {
+            if comment {
+                // Only replies can escape the space, with \s.
+                warn!("The value '{value}' has both line breaks and ' // ' in it, which can't be written exactly");
+                for part in parts.iter_mut() {
+                    *part = part.replace(" // ", "\\s// ");
+                }
+            }
+            self.concat(indent, "newline");
+        } else if comment {
+            let (first, rest) = value.split_once(" // ").unwrap();
+            parts = vec![format!("{first} /"), format!("/ {rest}")];
+            self.concat(indent, "none");
+        }
+
+        for (i, part) in parts.iter().enumerate() {
+            let cmd = if i == 0 { cmd } else { "^" };
+            self.out.push_str(format!("{indent}{cmd} {part}").trim_end());
+            self.out.push('\n');
+        }
+    }
+
+    /// Switch the concat mode for the ^Continue lines that follow.
+    fn concat(&mut self, indent: &str, mode: &'static str) {
+        if self.concat != mode {
+            self.concat = mode;
+            self.out.push_str(&format!("{indent}! local concat = {mode}\n"));
+        }
+    }
+
+    fn blank(&mut self) {
+        self.out.push('\n');
+    }
+
+    /// Write a group of `! kind name = value` definitions.
+    fn definitions(&mut self, kind: &str, map: &HashMap<String, String>) {
+        if map.is_empty() {
+            return;
+        }
+        for name in sorted_keys(map) {
+            self.line("", "!", &format!("{kind} {name} = {}", map[name]));
+        }
+        self.blank();
+    }
+
+    /// Write a `> topic` block (or `> begin`) with its triggers.
+    fn topic(&mut self, topic: &Topic) {
+        let mut label = if topic.name == crate::BEGIN_TOPIC && topic.includes.is_empty() && topic.inherits.is_empty() {
+            String::from("begin")
+        } else {
+            format!("topic {}", topic.name)
+        };
+
+        if !topic.includes.is_empty() {
+            label.push_str(" includes ");
+            label.push_str(&sorted_keys(&topic.includes).join(" "));
+        }
+        if !topic.inherits.is_empty() {
+            label.push_str(" inherits ");
+            label.push_str(&sorted_keys(&topic.inherits).join(" "));
+        }
+
+        self.line("", ">", &label);
+        self.blank();
+        for trigger in topic.triggers.iter() {
+            self.trigger("\t", trigger);
+        }
+
+        let closer = if label == "begin" { "begin" } else { "topic" };
+        self.line("", "<", closer);
+        self.blank();
+    }
+
+    /// Write a +Trigger with all of its replies.
+    fn trigger(&mut self, indent: &str, trigger: &Trigger) {
+        self.line(indent, "+", &trigger.trigger);
+        if !trigger.previous.is_empty() {
+            self.line(indent, "%", &trigger.previous);
+        }
+        if !trigger.redirect.is_empty() {
+            self.line(indent, "@", &trigger.redirect);
+        }
+        for condition in trigger.condition.iter() {
+            self.line(
+                indent,
+                "*",
+                &format!("{} {} {} => {}", condition.left, condition.operator, condition.right, condition.reply),
+            );
+        }
+        for reply in trigger.reply.iter() {
+            self.line(indent, "-", reply);
+        }
+        self.blank();
+    }
+}
+
+/// Format the `! version` number, always with a decimal point.
+fn format_version(version: f32) -> String {
+    let version = version.to_string();
+    if version.contains('.') {
+        version
+    } else {
+        format!("{version}.0")
+    }
+}
+
+/// Get the keys of a HashMap in sorted order.
+fn sorted_keys<V>(map: &HashMap<String, V>) -> Vec<&str> {
+    let mut keys: Vec<&str> = map.keys().map(|k| k.as_str()).collect();
+    keys.sort();
+    keys
+}
@@ -0,0 +1,230 @@
+//! # Parse Errors and Diagnostics
+//!
+//! When the Parser reads a RiveScript document, it may find problems with the
+//! source code: some are fatal (such as an unsupported `! version` number) and
+//! others are merely suspicious (such as an unknown `! definition` type, which
+//! the parser will skip over).
+//!
+//! Each problem is described by a Diagnostic, which carries the filename, line
+//! and column where it was found along with a machine-readable ErrorCode, so
+//! that editor tooling and CI pipelines can point authors to the exact line
+//! that needs fixing. Fatal problems are returned from the parser wrapped in
+//! a ParseError.
+
+use std::error::Error;
+use std::fmt;
+
+/// How serious a Diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The parser could continue, but probably not the way the author intended.
+    Warning,
+
+    /// The document could not be parsed.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Machine-readable codes for each kind of problem the parser (or the linter) can report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The `! version` value was not a number.
+    BadVersion,
+
+    /// The `! version` is newer than the RiveScript spec this parser supports.
+    UnsupportedVersion,
+
+    /// A line of code consisted of only a single character.
+    WeirdLine,
+
+    /// A `! definition` was missing its variable name.
+    MissingName,
+
+    /// A `! definition` was missing its value.
+    MissingValue,
+
+    /// An unknown kind of `! definition` (not global, var, sub, etc.)
+    UnknownDefinition,
+
+    /// An invalid value for `! local concat`.
+    InvalidConcat,
+
+    /// An unknown kind of `> label` (not begin, topic or object).
+    UnknownLabel,
+
+    /// A `> object` macro without a programming language.
+    MissingLanguage,
+
+    /// A `* condition` that could not be parsed.
+    MalformedCondition,
+
+    /// A line beginning with an unknown command symbol.
+    UnknownCommand,
+
+    /// The source code couldn't be read (e.g. an I/O error or invalid UTF-8).
+    ReadError,
+
+    /// An `! include`d document couldn't be found or read.
+    IncludeError,
+
+    /// An `! include`d document includes itself, directly or indirectly.
+    IncludeCycle,
+
+    /// Lint: a trigger can never match because a higher-priority trigger always matches first.
+    ShadowedTrigger,
+
+    /// Lint: an `@ redirect` or `{@ redirect}` that no trigger can match.
+    UnmatchedRedirect,
+
+    /// Lint: a `{topic=name}` tag pointing to a topic that doesn't exist.
+    UnknownTopic,
+
+    /// Lint: an `@array` or `(@array)` reference to an array that isn't defined.
+    UndefinedArray,
+
+    /// Lint: a `<bot name>` tag for a bot variable that is never defined.
+    UndefinedBotVar,
+
+    /// Lint: a `<call>` to an object macro with no subroutine or loaded handler.
+    UnknownMacro,
+
+    /// Lint: a trigger pattern that can't be compiled into a regular expression.
+    InvalidPattern,
+}
+
+impl ErrorCode {
+    /// The stable string identifier of this error code, e.g. "unknown-definition".
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadVersion => "bad-version",
+            ErrorCode::UnsupportedVersion => "unsupported-version",
+            ErrorCode::WeirdLine => "weird-line",
+            ErrorCode::MissingName => "missing-name",
+            ErrorCode::MissingValue => "missing-value",
+            ErrorCode::UnknownDefinition => "unknown-definition",
+            ErrorCode::InvalidConcat => "invalid-concat",
+            ErrorCode::UnknownLabel => "unknown-label",
+            ErrorCode::MissingLanguage => "missing-language",
+            ErrorCode::MalformedCondition => "malformed-condition",
+            ErrorCode::UnknownCommand => "unknown-command",
+            ErrorCode::ReadError => "read-error",
+            ErrorCode::IncludeError => "include-error",
+            ErrorCode::IncludeCycle => "include-cycle",
+            ErrorCode::ShadowedTrigger => "shadowed-trigger",
+            ErrorCode::UnmatchedRedirect => "unmatched-redirect",
+            ErrorCode::UnknownTopic => "unknown-topic",
+            ErrorCode::UndefinedArray => "undefined-array",
+            ErrorCode::UndefinedBotVar => "undefined-bot-var",
+            ErrorCode::UnknownMacro => "unknown-macro",
+            ErrorCode::InvalidPattern => "invalid-pattern",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Diagnostic describes a single problem found in a RiveScript document.
+///
+/// Line and column numbers start counting from 1. The `text` holds the
+/// offending line of source code (with surrounding whitespace trimmed).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub filename: String,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub text: String,
+}
+
+impl Diagnostic {
+    /// Create a new warning diagnostic, to be placed with `at()`.
+    pub fn warning(code: ErrorCode, message: &str) -> Self {
+        Self::new(Severity::Warning, code, message)
+    }
+
+    /// Create a new error diagnostic, to be placed with `at()`.
+    pub fn error(code: ErrorCode, message: &str) -> Self {
+        Self::new(Severity::Error, code, message)
+    }
+
+    fn new(severity: Severity, code: ErrorCode, message: &str) -> Self {
+        Self {
+            filename: String::new(),
+            line: 0,
+            column: 0,
+            severity,
+            code,
+            message: message.to_string(),
+            text: String::new(),
+        }
+    }
+
+    /// Set the location in the source document that this diagnostic refers to.
+    pub fn at(mut self, filename: &str, line: usize, column: usize, text: &str) -> Self {
+        self.filename = filename.to_string();
+        self.line = line;
+        self.column = column;
+        self.text = text.trim().to_string();
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {} [{}]: {}",
+            self.filename, self.line, self.column, self.severity, self.code, self.message,
+        )
+    }
+}
+
+/// ParseError is returned when a RiveScript document could not be parsed.
+///
+/// It carries one or more Diagnostics explaining what went wrong and where.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ParseError {
+    /// Create a ParseError from a single diagnostic.
+    pub fn new(diagnostic: Diagnostic) -> Self {
+        Self {
+            diagnostics: vec![diagnostic],
+        }
+    }
+
+    /// Create a ParseError from a list of diagnostics.
+    pub fn from_diagnostics(diagnostics: Vec<Diagnostic>) -> Self {
+        Self { diagnostics }
+    }
+
+    /// All of the diagnostics that caused this error.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.diagnostics.iter().map(|d| d.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl Error for ParseError {}
@@ -6,10 +6,13 @@
 //! RiveScript crate and external implementations can both reference it.
 
 pub mod ast;
+pub mod deparse;
+pub mod errors;
 pub mod macros;
 pub mod parser;
 pub mod regex;
 pub mod sessions;
+pub mod sources;
 
 pub const DEFAULT_TOPIC: &str = "random";
 pub const BEGIN_TOPIC: &str = "__begin__";
@@ -76,8 +76,14 @@ pub struct SubroutineResult {
 /// the `<call>` tag in a RiveScript reply. The `name` is the name of the object
 /// macro and the `args` are the parameters (using shell-style quoting rules, so
 /// a "quoted string" would come as one item of the `Vec<String>`).
+///
+/// The unload() function is called when an object macro is removed from the bot's
+/// brain (for example, when the file that defined it was unloaded), so that your
+/// LanguageLoader can forget about it. It does nothing by default.
 #[async_trait]
 pub trait LanguageLoader: Send + Sync {
     fn load(&mut self, name: &str, code: Vec<String>) -> Result<bool, String>;
     async fn call(&self, proxy: &dyn Proxy, name: &str, args: Vec<String>) -> Result<SubroutineResult, String>;
+
+    fn unload(&mut self, _name: &str) {}
 }
\ No newline at end of file
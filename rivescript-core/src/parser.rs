@@ -1,7 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
 
-use crate::ast::{Object, Trigger, AST};
+use crate::ast::{Location, Object, Trigger, AST};
+use crate::errors::{Diagnostic, ErrorCode, ParseError, Severity};
 use log::{debug, warn};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 use Result::Ok;
 
 /// The version of the RiveScript spec we support.
@@ -12,7 +18,44 @@ use Result::Ok;
 const RIVESCRIPT_SPEC_VERSION: f32 = 2.0;
 
 /// The RiveScript language parser.
-pub struct Parser {}
+pub struct Parser {
+    /// Strict mode: reject the whole document if any warnings were found in it.
+    ///
+    /// By default the parser skips over lines that it doesn't understand (such as
+    /// unknown `! definition` types or malformed `* conditions`) and reports them as
+    /// warnings. In strict mode, these warnings are promoted into a ParseError instead.
+    pub strict: bool,
+
+    /// Enable the `! include` directive, which parses another RiveScript document in place.
+    ///
+    /// ```rivescript
+    /// ! include shared/substitutions.rive
+    /// ```
+    ///
+    /// The path is relative to the directory of the including document, and the included
+    /// document's contents are merged in at that point (so definitions that come after the
+    /// `! include` take priority over the included ones). Included files are always read
+    /// from the filesystem with blocking calls, even when parsing from an async reader (see
+    /// `parse_async_reader`). A document that (directly or indirectly) includes itself is a
+    /// ParseError.
+    ///
+    /// This is off by default, and `! include` lines are skipped with a warning.
+    pub includes: bool,
+}
+
+/// A parsed RiveScript document, from `parse_document` or `read_document`.
+#[derive(Debug)]
+pub struct Document {
+    pub ast: AST,
+
+    /// The problems that the parser skipped over, as with `parse_with_warnings`.
+    pub warnings: Vec<Diagnostic>,
+
+    /// The files that the document `! include`d (and the files that those included, and
+    /// so on), with their contents, in the order they were read. The bot's source hash
+    /// covers these, so that a brain cache goes stale when an included file changes.
+    pub includes: Vec<(String, String)>,
+}
 
 enum ConcatMode {
     None,
@@ -42,471 +85,781 @@ impl ConcatMode {
 impl Parser {
     /// Create a new instance of the parser. It takes no parameters.
     pub fn new() -> Self {
-        Self {}
+        Self {
+            strict: false,
+            includes: false,
+        }
     }
 
     /// Parse RiveScript source code and return the Abstract Syntax Tree.
     ///
     /// The filename is used only for syntax error reporting (so the filename and line number
     /// can be included in the error).
-    pub fn parse(&self, filename: &str, contents: String) -> Result<AST, String> {
-        debug!("BEGIN PARSE ON FILENAME: {}", filename);
+    ///
+    /// Fatal problems are returned as a ParseError. Non-fatal problems (such as unknown
+    /// commands, which are skipped over) are logged as warnings. Use `parse_with_warnings`
+    /// to collect the warnings instead.
+    pub fn parse(&self, filename: &str, contents: String) -> Result<AST, ParseError> {
+        let (ast, warnings) = self.parse_with_warnings(filename, contents)?;
+        for diagnostic in warnings {
+            warn!("{diagnostic}");
+        }
+        Ok(ast)
+    }
 
-        // Start building an AST parsed from these files.
-        let mut ast = AST::new();
+    /// Parse RiveScript source code and return the AST along with any warnings.
+    ///
+    /// In strict mode, the warnings are returned as a ParseError instead.
+    pub fn parse_with_warnings(&self, filename: &str, contents: String) -> Result<(AST, Vec<Diagnostic>), ParseError> {
+        self.parse_document(filename, &contents).map(|document| (document.ast, document.warnings))
+    }
 
-        // Local (file-scoped) parser options.
-        let mut local_options: HashMap<String, String> = HashMap::new();
-        local_options.insert("concat".to_string(), "none".to_string());
-        let mut concat_mode = ConcatMode::None;
+    /// Parse RiveScript source code into a Document: the AST and warnings, along with the
+    /// files that it included.
+    pub fn parse_document(&self, filename: &str, contents: &str) -> Result<Document, ParseError> {
+        let mut state = ParseState::new(self, filename);
+        for line in contents.lines() {
+            state.push(line.to_string())?;
+        }
+        state.finish()
+    }
 
-        // Some temporary state variables as we parse this file.
-        let mut topic = String::from(crate::DEFAULT_TOPIC);
-        let mut current_trigger = Trigger::new("");
-        let mut lineno: usize = 0;
-        let mut in_comment = false;
-        let mut in_object = false;
-        let mut object_name = String::from("");
-        let mut object_language = String::from("");
-        let mut object_buffer: Vec<String> = Vec::new();
+    /// Parse RiveScript source code from a reader, such as a file or a socket.
+    ///
+    /// The source is read one line at a time, and only the lines needed to look ahead
+    /// for `^Continue` commands are held in memory at once. The AST is returned along
+    /// with any warnings, as with `parse_with_warnings`. Errors reading from the
+    /// reader (including invalid UTF-8) are returned as a ParseError.
+    pub fn parse_reader(&self, filename: &str, reader: impl BufRead) -> Result<(AST, Vec<Diagnostic>), ParseError> {
+        self.read_document(filename, reader).map(|document| (document.ast, document.warnings))
+    }
 
-        // Initialize the "random" topic.
-        ast.init_topic(&topic);
+    /// Parse RiveScript source code from a reader into a Document, like `parse_document`.
+    pub fn read_document(&self, filename: &str, reader: impl BufRead) -> Result<Document, ParseError> {
+        let mut state = ParseState::new(self, filename);
+        for line in reader.lines() {
+            match line {
+                Ok(line) => state.push(line)?,
+                Err(e) => return Err(state.read_error(e)),
+            }
+        }
+        state.finish()
+    }
 
-        // Go through the lines of code.
-        // let mut lines = contents.lines();
-        let lines: Vec<String> = contents.lines().map(|s| s.to_string()).collect();
+    /// Parse RiveScript source code from an async reader.
+    ///
+    /// This is the async version of `parse_reader`. Only the reader is async: with `includes`
+    /// enabled, the files that the document `! include`s are read with blocking filesystem
+    /// calls, which hold up the async runtime's thread while they're read. If that matters,
+    /// run the parse on a blocking thread (e.g. with `tokio::task::spawn_blocking` and
+    /// `parse_reader`) instead.
+    ///
+    /// This needs the `async` feature, which the **rivescript** crate turns on.
+    #[cfg(feature = "async")]
+    pub async fn parse_async_reader(&self, filename: &str, reader: impl AsyncBufRead + Unpin) -> Result<(AST, Vec<Diagnostic>), ParseError> {
+        let mut state = ParseState::new(self, filename);
+        let mut lines = reader.lines();
         loop {
-            if lineno >= lines.len() {
-                break;
+            match lines.next_line().await {
+                Ok(Some(line)) => state.push(line)?,
+                Ok(None) => break,
+                Err(e) => return Err(state.read_error(e)),
             }
+        }
+        state.finish().map(|document| (document.ast, document.warnings))
+    }
+}
 
-            let mut line = lines[lineno].to_string();
-            lineno += 1;
+/// The state of the parser as it works through one RiveScript document.
+///
+/// Lines of source code are pushed in one at a time. A line can't be processed
+/// until we've seen the lines after it, in case they are ^Continue commands, so
+/// lines are buffered in `pending` only until the next line that isn't one.
+struct ParseState<'a> {
+    strict: bool,
+    includes: bool,
+    filename: &'a str,
+    pending: VecDeque<String>,
+
+    // The files being parsed, from the outermost document down to this one, for
+    // detecting `! include` cycles.
+    include_stack: Vec<PathBuf>,
+
+    // The files that were included, with their contents.
+    included: Vec<(String, String)>,
+
+    // Start building an AST parsed from these files.
+    ast: AST,
+
+    // Local (file-scoped) parser options.
+    local_options: HashMap<String, String>,
+    concat_mode: ConcatMode,
+
+    // Some temporary state variables as we parse this file.
+    topic: String,
+    current_trigger: Trigger,
+    lineno: usize,
+    in_comment: bool,
+    in_object: bool,
+    object_name: String,
+    object_language: String,
+    object_buffer: Vec<String>,
+    object_start: usize,
+    diagnostics: Vec<Diagnostic>,
+}
 
-            // Strip the line (skip empty lines).
-            line = line.trim().to_string();
-            if line.len() == 0 {
-                continue;
-            }
+impl<'a> ParseState<'a> {
+    fn new(parser: &Parser, filename: &'a str) -> Self {
+        debug!("BEGIN PARSE ON FILENAME: {}", filename);
 
-            // Are we inside of a `> object` macro?
-            if in_object {
-                // Have we reached the end?
-                if line.contains("< object") || line.contains("<object") {
-                    if object_name.len() > 0 {
-                        let new_object =
-                            Object::new(&object_name, &object_language, object_buffer.to_owned());
-                        ast.objects.insert(object_name.to_string(), new_object);
-                        in_object = false;
-                    }
-                } else {
-                    object_buffer.push(line);
-                }
-                continue;
+        let mut state = Self {
+            strict: parser.strict,
+            includes: parser.includes,
+            filename,
+            pending: VecDeque::new(),
+            include_stack: Vec::new(),
+            included: Vec::new(),
+            ast: AST::new(),
+            local_options: HashMap::from([("concat".to_string(), "none".to_string())]),
+            concat_mode: ConcatMode::None,
+            topic: String::from(crate::DEFAULT_TOPIC),
+            current_trigger: Trigger::new(""),
+            lineno: 0,
+            in_comment: false,
+            in_object: false,
+            object_name: String::from(""),
+            object_language: String::from(""),
+            object_buffer: Vec::new(),
+            object_start: 0,
+            diagnostics: Vec::new(),
+        };
+
+        // The top-level document is only looked up on disk if it can include other files.
+        // It may not be a file at all (e.g. for `stream()`), and if it isn't, a file that
+        // happens to have the same name shouldn't count towards the include cycles.
+        if state.includes {
+            state.include_stack.extend(fs::canonicalize(filename));
+        }
+
+        // Initialize the "random" topic.
+        state.ast.init_topic(&state.topic);
+        state
+    }
+
+    /// Add the next line of source code.
+    ///
+    /// Once a line arrives that isn't a ^Continue, all of the lines before it have
+    /// their full lookahead available and can be processed.
+    fn push(&mut self, line: String) -> Result<(), ParseError> {
+        let ends_lookahead = {
+            let line = line.trim();
+            line.len() >= 2 && (!line.starts_with('^') || line[1..].trim().len() == 0)
+        };
+
+        self.pending.push_back(line);
+        if ends_lookahead {
+            while self.pending.len() > 1 {
+                let raw = self.pending.pop_front().unwrap();
+                self.process_line(&raw)?;
             }
+        }
+        Ok(())
+    }
 
-            // Handle and ignore comments.
-            if line.starts_with("//") {
-                continue; // single-line comment.
-            } else if line.starts_with("/*") {
-                // Start of a multi-line comment block.
-                if line.contains("*/") {
-                    continue; // The end is on the same line!
-                }
+    /// Process the remaining lines at the end of the document and return the AST.
+    fn finish(mut self) -> Result<Document, ParseError> {
+        while let Some(raw) = self.pending.pop_front() {
+            self.process_line(&raw)?;
+        }
+
+        // If we had a final trigger ready to go, add it to the AST.
+        self.commit_trigger();
 
-                // Now inside a comment block.
-                in_comment = true;
-                continue;
-            } else if line.contains("*/") {
-                // End of a multi-line comment block.
-                in_comment = false;
-                continue;
-            } else if in_comment {
-                continue;
+        // In strict mode, any warnings reject the whole document.
+        if self.strict && !self.diagnostics.is_empty() {
+            for diagnostic in self.diagnostics.iter_mut() {
+                diagnostic.severity = Severity::Error;
             }
+            return Err(ParseError::from_diagnostics(self.diagnostics));
+        }
+
+        Ok(Document {
+            ast: self.ast,
+            warnings: self.diagnostics,
+            includes: self.included,
+        })
+    }
 
-            debug!("Line #{}: {}", lineno, line);
+    /// Build a ParseError for a failure to read the next line of source code.
+    fn read_error(&self, err: std::io::Error) -> ParseError {
+        let line = self.lineno + self.pending.len() + 1;
+        ParseError::new(
+            Diagnostic::error(ErrorCode::ReadError, &format!("Couldn't read the source: {err}"))
+                .at(self.filename, line, 1, ""),
+        )
+    }
 
-            // Separate the command from its data.
-            if line.len() < 2 {
-                warn!(
-                    "Weird single-character line '{}' found at {} line {}",
-                    line, filename, lineno,
-                );
-                continue;
+    /// Parse an `! include`d document and merge it into the AST.
+    fn include(&mut self, path: &str, column: usize, raw: &str) -> Result<(), ParseError> {
+        // Resolve the path relative to the including document.
+        let path = Path::new(self.filename).parent().unwrap_or(Path::new("")).join(path);
+        let filename = path.to_string_lossy().to_string();
+        let error = |code: ErrorCode, message: String| {
+            ParseError::new(Diagnostic::error(code, &message).at(self.filename, self.lineno, column, raw))
+        };
+
+        let canonical = fs::canonicalize(&path)
+            .map_err(|e| error(ErrorCode::IncludeError, format!("Couldn't include '{filename}': {e}")))?;
+        if self.include_stack.contains(&canonical) {
+            let chain: Vec<String> = self.include_stack.iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(error(
+                ErrorCode::IncludeCycle,
+                format!("Including '{filename}' would create a cycle: {}", chain.join(" -> ")),
+            ));
+        }
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| error(ErrorCode::IncludeError, format!("Couldn't include '{filename}': {e}")))?;
+
+        debug!("Including file {}", filename);
+        let parser = Parser {
+            strict: self.strict,
+            includes: self.includes,
+        };
+        let mut state = ParseState::new(&parser, &filename);
+        state.include_stack = self.include_stack.clone();
+        state.include_stack.push(canonical);
+        for line in contents.lines() {
+            state.push(line.to_string())?;
+        }
+        let document = state.finish()?;
+
+        // Finish off our current trigger so it stays ahead of the included ones.
+        self.commit_trigger();
+        self.ast.extend(document.ast);
+        self.diagnostics.extend(document.warnings);
+        self.included.push((filename, contents));
+        self.included.extend(document.includes);
+        Ok(())
+    }
+
+    /// Add the current trigger (if we were working on one) to the current topic.
+    fn commit_trigger(&mut self) {
+        if self.current_trigger.is_populated() {
+            let trigger = std::mem::replace(&mut self.current_trigger, Trigger::new(""));
+            let t = self.ast.topics.get_mut(&self.topic).expect("or else");
+            t.add_trigger(trigger);
+        }
+    }
+
+    /// Process a single line of RiveScript source code.
+    ///
+    /// The lines that follow it (for ^Continue lookahead) are in `pending`.
+    fn process_line(&mut self, raw: &str) -> Result<(), ParseError> {
+        self.lineno += 1;
+
+        // Are we inside of a `> object` macro? Its source code is kept exactly
+        // as written, including indentation and blank lines.
+        if self.in_object {
+            // Have we reached the end?
+            if is_object_end(raw) {
+                if self.object_name.len() > 0 {
+                    let mut new_object =
+                        Object::new(&self.object_name, &self.object_language, self.object_buffer.to_owned());
+                    new_object.location = Location::new(self.filename, self.object_start, self.lineno);
+                    self.ast.objects.insert(self.object_name.to_string(), new_object);
+                    self.in_object = false;
+                }
+            } else {
+                self.object_buffer.push(raw.to_string());
             }
-            let cmd = &line[..1];
-            let mut line = line[1..].trim().to_string();
+            return Ok(());
+        }
+
+        // Strip the line (skip empty lines).
+        let line = raw.trim().to_string();
+        if line.len() == 0 {
+            return Ok(());
+        }
 
-            // Ignore inline comments at the end of the line.
-            if line.contains(" // ") {
-                let mut splitter = line.splitn(2, " // ");
-                line = splitter.next().unwrap_or("").to_string();
+        // The column where the command begins, for diagnostics.
+        let column = raw.len() - raw.trim_start().len() + 1;
+
+        // Handle and ignore comments.
+        if line.starts_with("//") {
+            return Ok(()); // single-line comment.
+        } else if line.starts_with("/*") {
+            // Start of a multi-line comment block.
+            if line.contains("*/") {
+                return Ok(()); // The end is on the same line!
             }
 
-            // Do a look-ahead for ^Continue and %Previous commands.
-            if cmd != "^" {
-                let mut li = lineno;
-                loop {
-                    if li >= lines.len() {
-                        break;
-                    }
+            // Now inside a comment block.
+            self.in_comment = true;
+            return Ok(());
+        } else if line.contains("*/") {
+            // End of a multi-line comment block.
+            self.in_comment = false;
+            return Ok(());
+        } else if self.in_comment {
+            return Ok(());
+        }
 
-                    let lookahead = lines[li].trim();
-                    li += 1;
-                    if lookahead.len() < 2 {
-                        continue;
-                    }
+        debug!("Line #{}: {}", self.lineno, line);
+
+        // Separate the command from its data.
+        if line.len() < 2 {
+            self.diagnostics.push(
+                Diagnostic::warning(ErrorCode::WeirdLine, &format!("Weird single-character line '{line}'"))
+                    .at(self.filename, self.lineno, column, raw),
+            );
+            return Ok(());
+        }
+        let cmd = &line[..1];
+        let mut line = line[1..].trim().to_string();
 
-                    let look_cmd = &lookahead[..1];
-                    let lookahead = lookahead[1..].trim();
+        // Ignore inline comments at the end of the line.
+        if line.contains(" // ") {
+            let mut splitter = line.splitn(2, " // ");
+            line = splitter.next().unwrap_or("").trim_end().to_string();
+        }
 
-                    // We only care about a couple of lookahead command types.
-                    if look_cmd != "^" || lookahead.len() == 0 {
-                        break;
-                    }
+        // Do a look-ahead for ^Continue and %Previous commands.
+        // Keep track of the last line number that this command spans.
+        let mut end_line = self.lineno;
+        if cmd != "^" {
+            let mut li = self.lineno;
+            for lookahead in self.pending.iter() {
+                let lookahead = lookahead.trim();
+                li += 1;
+                if lookahead.len() < 2 {
+                    continue;
+                }
 
-                    // If our parent command is a ! and the next command(s) are ^,
-                    // we'll tack each extension on as a "fake line break" (which
-                    // is useful information for !arrays especially)
-                    if cmd == "!" {
-                        if look_cmd == "^" {
-                            line.push_str("<crlf>");
-                            line.push_str(lookahead);
-                        }
-                        continue;
-                    }
+                let look_cmd = &lookahead[..1];
+                let lookahead = lookahead[1..].trim();
+
+                // We only care about a couple of lookahead command types.
+                if look_cmd != "^" || lookahead.len() == 0 {
+                    break;
+                }
 
-                    // Concatenate ^Continue lines with the current concat mode characters.
-                    if cmd != "^" && look_cmd == "^" {
-                        line = format!("{line}{}{lookahead}", concat_mode.to_string());
+                // If our parent command is a ! and the next command(s) are ^,
+                // we'll tack each extension on as a "fake line break" (which
+                // is useful information for !arrays especially)
+                if cmd == "!" {
+                    if look_cmd == "^" {
+                        line.push_str("<crlf>");
+                        line.push_str(lookahead);
+                        end_line = li;
                     }
+                    continue;
+                }
+
+                // Concatenate ^Continue lines with the current concat mode characters.
+                if cmd != "^" && look_cmd == "^" {
+                    line = format!("{line}{}{lookahead}", self.concat_mode.to_string());
+                    end_line = li;
                 }
             }
+        }
 
-            // Handle the types of RiveScript commands.
-            match cmd {
-                // !Definition
-                "!" => {
-                    warn!("Found a !DEFINITION");
-
-                    // The command looks like:
-                    // ! version = 2.0
-                    // ! global depth = 50
-                    // ! var name = Chatbot
-                    // ! sub who's = who is
-                    let mut halves = line.splitn(2, "=");
-                    let left = halves.next().unwrap_or("").trim();
-                    let right = halves.next().unwrap_or("").trim();
-                    let mut value = String::from("");
-                    let mut kind = ""; // global, var, sub, ...
-                    let mut name = "";
-
-                    if right.len() > 0 {
-                        // The right half of the = sign is always the value.
-                        value.push_str(right);
-                    }
-                    if left.len() >= 1 {
-                        // The left half has the kind and maybe the name.
-                        // If `! version` there is only the kind=version,
-                        // everything else has a name.
-                        if left.contains(" ") {
-                            let mut halves = left.splitn(2, " ");
-                            kind = halves.next().unwrap_or("").trim();
-                            name = halves.next().unwrap_or("").trim();
-                        } else {
-                            kind = left;
-                        }
+        // Handle the types of RiveScript commands.
+        match cmd {
+            // !Definition
+            "!" => {
+                warn!("Found a !DEFINITION");
+
+                // The command looks like:
+                // ! version = 2.0
+                // ! global depth = 50
+                // ! var name = Chatbot
+                // ! sub who's = who is
+                let mut halves = line.splitn(2, "=");
+                let left = halves.next().unwrap_or("").trim();
+                let right = halves.next().unwrap_or("").trim();
+                let mut value = String::from("");
+                let mut kind = ""; // global, var, sub, ...
+                let mut name = "";
+
+                if right.len() > 0 {
+                    // The right half of the = sign is always the value.
+                    value.push_str(right);
+                }
+                if left.len() >= 1 {
+                    // The left half has the kind and maybe the name.
+                    // If `! version` there is only the kind=version,
+                    // everything else has a name.
+                    if left.contains(" ") {
+                        let mut halves = left.splitn(2, " ");
+                        kind = halves.next().unwrap_or("").trim();
+                        name = halves.next().unwrap_or("").trim();
+                    } else {
+                        kind = left;
                     }
+                }
 
-                    // Remove 'fake' line breaks unless this is an array.
-                    if kind != "array" {
-                        value = value.replace("<crlf>", concat_mode.to_string());
-                    }
+                // Remove 'fake' line breaks unless this is an array.
+                if kind != "array" {
+                    value = value.replace("<crlf>", self.concat_mode.to_string());
+                }
 
-                    // Handle RiveScript specification version checks.
-                    if kind == "version" {
-                        warn!("Found a version str: {}", value);
-                        let version = value.parse::<f32>().unwrap_or(0.0);
-                        if version == 0.0 {
-                            return Err(
-                                "Didn't parse version string; was it a properly formatted number?".to_string(),
-                            );
-                        } else if version > RIVESCRIPT_SPEC_VERSION {
-                            return Err(
-                                "This RiveScript document declares a `! version` number higher than we support".to_string(),
-                            );
-                        } else {
-                            ast.version = version;
-                        }
-                        continue;
+                // Handle RiveScript specification version checks.
+                if kind == "version" {
+                    warn!("Found a version str: {}", value);
+                    let version = value.parse::<f32>().unwrap_or(0.0);
+                    if version == 0.0 {
+                        return Err(ParseError::new(
+                            Diagnostic::error(
+                                ErrorCode::BadVersion,
+                                "Didn't parse version string; was it a properly formatted number?",
+                            ).at(self.filename, self.lineno, column, raw),
+                        ));
+                    } else if version > RIVESCRIPT_SPEC_VERSION {
+                        return Err(ParseError::new(
+                            Diagnostic::error(
+                                ErrorCode::UnsupportedVersion,
+                                "This RiveScript document declares a `! version` number higher than we support",
+                            ).at(self.filename, self.lineno, column, raw),
+                        ));
+                    } else {
+                        self.ast.version = version;
                     }
+                    return Ok(());
+                }
 
-                    // All other types of defines require a value and a name.
-                    if name.len() == 0 {
-                        warn!("Undefined variable name at {} line {}", filename, lineno);
-                        continue;
-                    } else if value.len() == 0 {
-                        warn!("Undefined variable value at {} line {}", filename, lineno);
-                        continue;
+                // `! include path/to/file.rive` has a path instead of a name and value.
+                if kind == "include" {
+                    if !self.includes {
+                        self.diagnostics.push(
+                            Diagnostic::warning(
+                                ErrorCode::UnknownDefinition,
+                                "The `! include` directive is not enabled",
+                            ).at(self.filename, self.lineno, column, raw),
+                        );
+                        return Ok(());
+                    } else if name.is_empty() {
+                        self.diagnostics.push(
+                            Diagnostic::warning(ErrorCode::MissingName, "Missing path to include")
+                                .at(self.filename, self.lineno, column, raw),
+                        );
+                        return Ok(());
                     }
+                    return self.include(name, column, raw);
+                }
 
-                    // Handle the rest of the !Define types.
-                    match kind {
-                        "local" => {
-                            debug!("\tSet local parser option {} = {}", name, value);
-                            local_options.insert(name.to_string(), value.to_string());
-
-                            // Changing the ^Continue concatenation mode?
-                            if name == "concat" {
-                                if let Some(v) = ConcatMode::parse(&value) {
-                                    concat_mode = v;
-                                } else {
-                                    warn!("Invalid value for '! local concat': '{value}'");
-                                    concat_mode = ConcatMode::None;
-                                }
+                // All other types of defines require a value and a name.
+                if name.len() == 0 {
+                    self.diagnostics.push(
+                        Diagnostic::warning(ErrorCode::MissingName, "Undefined variable name")
+                            .at(self.filename, self.lineno, column, raw),
+                    );
+                    return Ok(());
+                } else if value.len() == 0 {
+                    self.diagnostics.push(
+                        Diagnostic::warning(ErrorCode::MissingValue, "Undefined variable value")
+                            .at(self.filename, self.lineno, column, raw),
+                    );
+                    return Ok(());
+                }
+
+                // Handle the rest of the !Define types.
+                match kind {
+                    "local" => {
+                        debug!("\tSet local parser option {} = {}", name, value);
+                        self.local_options.insert(name.to_string(), value.to_string());
+
+                        // Changing the ^Continue concatenation mode?
+                        if name == "concat" {
+                            if let Some(v) = ConcatMode::parse(&value) {
+                                self.concat_mode = v;
+                            } else {
+                                self.diagnostics.push(
+                                    Diagnostic::warning(
+                                        ErrorCode::InvalidConcat,
+                                        &format!("Invalid value for '! local concat': '{value}'"),
+                                    ).at(self.filename, self.lineno, column, raw),
+                                );
+                                self.concat_mode = ConcatMode::None;
                             }
                         }
-                        "global" => {
-                            debug!("\tSet global {} = {}", name, value);
-                            ast.set_global(name, &value);
-                        }
-                        "var" => {
-                            debug!("\tSet bot variable {} = {}", name, value);
-                            ast.set_bot_var(name, &value);
-                        }
-                        "sub" => {
-                            debug!("\tSet substitution {} => {}", name, value);
-                            ast.subs.insert(name.to_string(), value.to_string());
-                        }
-                        "person" => {
-                            debug!("\tSet person substitution {} => {}", name, value);
-                            ast.person.insert(name.to_string(), value.to_string());
-                        }
-                        "array" => {
-                            debug!("\tSet array {} = {}", name, value);
-
-                            // Did we have multiple parts to this array? (^Continues)
-                            let parts = value.split("<crlf>");
-
-                            // Process each row of array data independently.
-                            let mut fields: Vec<String> = Vec::new();
-                            for val in parts {
-                                if val.contains("|") {
-                                    // Pipe-separated array (so the words can have spaces)
-                                    let mut other: Vec<String> =
-                                        val.split("|").map(str::to_string).collect();
-                                    fields.append(&mut other);
-                                } else {
-                                    let mut other: Vec<String> =
-                                        val.split_whitespace().map(str::to_string).collect();
-                                    fields.append(&mut other);
-                                }
-                            }
-
-                            // Convert any remaining '\s' escape sequences to spaces.
-                            for field in fields.iter_mut() {
-                                *field = field.replace("\\s", " ");
+                    }
+                    "global" => {
+                        debug!("\tSet global {} = {}", name, value);
+                        self.ast.set_global(name, &value);
+                    }
+                    "var" => {
+                        debug!("\tSet bot variable {} = {}", name, value);
+                        self.ast.set_bot_var(name, &value);
+                    }
+                    "sub" => {
+                        debug!("\tSet substitution {} => {}", name, value);
+                        self.ast.subs.insert(name.to_string(), value.to_string());
+                    }
+                    "person" => {
+                        debug!("\tSet person substitution {} => {}", name, value);
+                        self.ast.person.insert(name.to_string(), value.to_string());
+                    }
+                    "array" => {
+                        debug!("\tSet array {} = {}", name, value);
+
+                        // Did we have multiple parts to this array? (^Continues)
+                        let parts = value.split("<crlf>");
+
+                        // Process each row of array data independently.
+                        let mut fields: Vec<String> = Vec::new();
+                        for val in parts {
+                            if val.contains("|") {
+                                // Pipe-separated array (so the words can have spaces)
+                                let mut other: Vec<String> =
+                                    val.split("|").map(str::to_string).collect();
+                                fields.append(&mut other);
+                            } else {
+                                let mut other: Vec<String> =
+                                    val.split_whitespace().map(str::to_string).collect();
+                                fields.append(&mut other);
                             }
-
-                            ast.arrays.insert(name.to_string(), fields);
                         }
-                        &_ => {
-                            warn!(
-                                "Unknown definition type '{}' at {} line {}",
-                                kind, filename, lineno,
-                            );
+
+                        // Convert any remaining '\s' escape sequences to spaces.
+                        for field in fields.iter_mut() {
+                            *field = field.replace("\\s", " ");
                         }
+
+                        self.ast.arrays.insert(name.to_string(), fields);
+                    }
+                    &_ => {
+                        self.diagnostics.push(
+                            Diagnostic::warning(
+                                ErrorCode::UnknownDefinition,
+                                &format!("Unknown definition type '{kind}'"),
+                            ).at(self.filename, self.lineno, column, raw),
+                        );
                     }
                 }
+            }
 
-                // > Label
-                ">" => {
-                    warn!("Found a >LABEL");
-
-                    // The command looks like:
-                    // > begin
-                    // > topic random
-                    // > object something perl
-                    let mut fields: Vec<String> =
-                        line.split_whitespace().map(str::to_string).collect();
-                    if fields.len() == 0 {
-                        continue;
-                    }
+            // > Label
+            ">" => {
+                warn!("Found a >LABEL");
+
+                // The command looks like:
+                // > begin
+                // > topic random
+                // > object something perl
+                let mut fields: Vec<String> =
+                    line.split_whitespace().map(str::to_string).collect();
+                if fields.len() == 0 {
+                    return Ok(());
+                }
 
-                    // First field is always the kind (begin, topic, object)
-                    let mut kind = fields.remove(0);
+                // First field is always the kind (begin, topic, object)
+                let mut kind = fields.remove(0);
 
-                    // Next field may be the name (of topic or object)
-                    let mut name = String::from("");
-                    if fields.len() > 0 {
-                        name = fields.remove(0);
-                    }
+                // Next field may be the name (of topic or object)
+                let mut name = String::from("");
+                if fields.len() > 0 {
+                    name = fields.remove(0);
+                }
 
-                    // BEGIN is a type of topic.
-                    if kind == "begin" {
-                        kind = String::from("topic");
-                        name = String::from(crate::BEGIN_TOPIC);
-                    }
+                // BEGIN is a type of topic.
+                if kind == "begin" {
+                    kind = String::from("topic");
+                    name = String::from(crate::BEGIN_TOPIC);
+                }
 
-                    // Handle the kinds of labels.
-                    match kind.as_str() {
-                        "topic" => {
-                            ast.init_topic(&name);
-
-                            // If we parsed a last trigger, commit and flush it
-                            // ahead of the topic change.
-                            if current_trigger.is_populated() {
-                                debug!("Starting a new topic, commit the current trigger to topic {topic}: {:?}", current_trigger);
-                                let t = ast.topics.get_mut(&topic).expect("or else");
-                                t.add_trigger(current_trigger);
-                            }
-                            current_trigger = Trigger::new("");
-
-                            // Set the pointer for triggers to enter this topic.
-                            topic = name.to_string();
-
-                            // Does this topic inherit or include another?
-                            let mut mode = String::from("");
-                            if fields.len() > 0 {
-                                for field in fields {
-                                    if field == "includes" || field == "inherits" {
-                                        mode = field.to_string();
-                                    } else if mode == "includes" {
-                                        let t = ast.topics.get_mut(&topic).expect("or else");
-                                        t.set_includes(field.to_string());
-                                    } else if mode == "inherits" {
-                                        let t = ast.topics.get_mut(&topic).expect("or else");
-                                        t.set_inherits(field.to_string());
-                                    }
-                                }
-                            }
+                // Handle the kinds of labels.
+                match kind.as_str() {
+                    "topic" => {
+                        self.ast.init_topic(&name);
+
+                        // Record where the topic was first defined.
+                        let t = self.ast.topics.get_mut(&name).expect("or else");
+                        if !t.location.is_known() {
+                            t.location = Location::new(self.filename, self.lineno, end_line);
                         }
-                        "object" => {
-                            // Start of an object macro definition.
-                            let mut language = String::from("");
-                            if fields.len() > 0 {
-                                language = fields.remove(0).to_lowercase();
-                            }
 
-                            // No language defined?
-                            if language.len() == 0 {
-                                warn!(
-                                    "No programming language defined for object '{}' at {} line {}",
-                                    name, filename, lineno,
-                                );
-                                in_object = true;
-                                object_name = name;
-                                object_language = language;
-                                continue;
+                        // If we parsed a last trigger, commit and flush it
+                        // ahead of the topic change.
+                        if self.current_trigger.is_populated() {
+                            debug!("Starting a new topic, commit the current trigger to topic {}: {:?}", self.topic, self.current_trigger);
+                        }
+                        self.commit_trigger();
+
+                        // Set the pointer for triggers to enter this topic.
+                        self.topic = name.to_string();
+
+                        // Does this topic inherit or include another?
+                        let mut mode = String::from("");
+                        if fields.len() > 0 {
+                            for field in fields {
+                                if field == "includes" || field == "inherits" {
+                                    mode = field.to_string();
+                                } else if mode == "includes" {
+                                    let t = self.ast.topics.get_mut(&self.topic).expect("or else");
+                                    t.set_includes(field.to_string());
+                                } else if mode == "inherits" {
+                                    let t = self.ast.topics.get_mut(&self.topic).expect("or else");
+                                    t.set_inherits(field.to_string());
+                                }
                             }
-
-                            // Start reading the object code.
-                            object_name = name;
-                            object_language = language;
-                            object_buffer.truncate(0);
-                            in_object = true;
                         }
-                        &_ => {
-                            warn!(
-                                "Unsupported >LABEL kind '{}' found at {} line {}",
-                                kind, filename, lineno,
+                    }
+                    "object" => {
+                        // Start of an object macro definition.
+                        let mut language = String::from("");
+                        if fields.len() > 0 {
+                            language = fields.remove(0).to_lowercase();
+                        }
+
+                        // No language defined?
+                        if language.len() == 0 {
+                            self.diagnostics.push(
+                                Diagnostic::warning(
+                                    ErrorCode::MissingLanguage,
+                                    &format!("No programming language defined for object '{name}'"),
+                                ).at(self.filename, self.lineno, column, raw),
                             );
+                            self.in_object = true;
+                            self.object_name = name;
+                            self.object_language = language;
+                            self.object_buffer.truncate(0);
+                            self.object_start = self.lineno;
+                            return Ok(());
                         }
+
+                        // Start reading the object code.
+                        self.object_name = name;
+                        self.object_language = language;
+                        self.object_buffer.truncate(0);
+                        self.object_start = self.lineno;
+                        self.in_object = true;
+                    }
+                    &_ => {
+                        self.diagnostics.push(
+                            Diagnostic::warning(
+                                ErrorCode::UnknownLabel,
+                                &format!("Unsupported >LABEL kind '{kind}'"),
+                            ).at(self.filename, self.lineno, column, raw),
+                        );
                     }
                 }
+            }
 
-                // < Label
-                "<" => {
-                    let kind = line;
+            // < Label
+            "<" => {
+                let kind = line;
 
-                    // If we were working on a trigger, commit it to AST now.
-                    if current_trigger.is_populated() {
-                        let t = ast.topics.get_mut(&topic).expect("or else");
-                        t.add_trigger(current_trigger);
-                        current_trigger = Trigger::new(&"");
-                    }
+                // If we were working on a trigger, commit it to AST now.
+                self.commit_trigger();
 
-                    if kind == "begin" || kind == "topic" {
-                        topic = crate::DEFAULT_TOPIC.to_string();
+                if kind == "begin" || kind == "topic" {
+                    // Close off the line range of the topic we were in.
+                    let t = self.ast.topics.get_mut(&self.topic).expect("or else");
+                    if t.location.filename == self.filename && t.location.end < self.lineno {
+                        t.location.end = self.lineno;
                     }
+
+                    self.topic = crate::DEFAULT_TOPIC.to_string();
                 }
+            }
 
-                // + Trigger
-                "+" => {
-                    // Were we working on a previous trigger? If so, give it
-                    // over to the AST and start a new one. We can't give it
-                    // over NOW because we will need to own/modify it to
-                    // add replies/conditions/etc.
-                    if current_trigger.is_populated() {
-                        let t = ast.topics.get_mut(&topic).expect("or else");
-                        t.add_trigger(current_trigger);
-                    }
+            // + Trigger
+            "+" => {
+                // Were we working on a previous trigger? If so, give it
+                // over to the AST and start a new one. We can't give it
+                // over NOW because we will need to own/modify it to
+                // add replies/conditions/etc.
+                self.commit_trigger();
 
-                    current_trigger = Trigger::new(line.as_str());
-                }
+                self.current_trigger = Trigger::new(line.as_str());
+                self.current_trigger.location = Location::new(self.filename, self.lineno, end_line);
+            }
 
-                // % Previous
-                "%" => {
-                    current_trigger.previous = line.to_string();
-                }
+            // % Previous
+            "%" => {
+                self.current_trigger.previous = line.to_string();
+                self.current_trigger.location.end = end_line;
+            }
 
-                // - Response
-                "-" => {
-                    current_trigger.reply.push(line.to_string());
-                }
+            // - Response
+            "-" => {
+                self.current_trigger.reply.push(line.to_string());
+                self.current_trigger.location.end = end_line;
+            }
 
-                // * Condition
-                "*" => {
-                    // Split everything apart.
-                    let parts: Vec<String> = line.splitn(2, "=>").map(|s| s.to_string()).collect();
-                    let condition = parts.get(0).unwrap().trim();
-                    let reply = parts.get(1).map(|s| s.as_str()).unwrap_or("").trim();
-
-                    // Parse the conditional side.
-                    match crate::regex::CONDITION.captures(&condition) {
-                        Some(caps) => {
-                            let left = caps.get(1).unwrap().as_str();
-                            let operator = caps.get(2).unwrap().as_str();
-                            let right = caps.get(3).unwrap().as_str();
-
-                            current_trigger.condition.push(crate::ast::Condition{
-                                left: left.to_string(),
-                                operator: operator.to_string(),
-                                right: right.to_string(),
-                                reply: reply.to_string(),
-                            });
-                        },
-                        None => {
-                            // TODO: raise syntax error.
-                        },
-                    }
+            // * Condition
+            "*" => {
+                // Split everything apart.
+                let parts: Vec<String> = line.splitn(2, "=>").map(|s| s.to_string()).collect();
+                let condition = parts.get(0).unwrap().trim();
+                let reply = parts.get(1).map(|s| s.as_str()).unwrap_or("").trim();
+
+                // Parse the conditional side.
+                match crate::regex::CONDITION.captures(&condition) {
+                    Some(caps) => {
+                        let left = caps.get(1).unwrap().as_str();
+                        let operator = caps.get(2).unwrap().as_str();
+                        let right = caps.get(3).unwrap().as_str();
+
+                        self.current_trigger.condition.push(crate::ast::Condition{
+                            left: left.to_string(),
+                            operator: operator.to_string(),
+                            right: right.to_string(),
+                            reply: reply.to_string(),
+                            location: Location::new(self.filename, self.lineno, end_line),
+                        });
+                        self.current_trigger.location.end = end_line;
+                    },
+                    None => {
+                        self.diagnostics.push(
+                            Diagnostic::warning(
+                                ErrorCode::MalformedCondition,
+                                &format!("Couldn't parse the condition '{condition}'"),
+                            ).at(self.filename, self.lineno, column, raw),
+                        );
+                    },
                 }
+            }
 
-                // @ Redirect
-                "@" => {
-                    current_trigger.redirect = line.to_string();
-                }
+            // @ Redirect
+            "@" => {
+                self.current_trigger.redirect = line.to_string();
+                self.current_trigger.location.end = end_line;
+            }
 
-                // ^ Continue was handled in lookahead above.
-                "^" => continue,
+            // ^ Continue was handled in lookahead above.
+            "^" => return Ok(()),
 
-                &_ => {
-                    warn!(
-                        "Unsupported RiveScript command '{}' found at {} line {}",
-                        cmd, filename, lineno,
-                    );
-                }
+            &_ => {
+                self.diagnostics.push(
+                    Diagnostic::warning(
+                        ErrorCode::UnknownCommand,
+                        &format!("Unsupported RiveScript command '{cmd}'"),
+                    ).at(self.filename, self.lineno, column, raw),
+                );
             }
         }
 
-        // If we had a final trigger ready to go, add it to the AST.
-        if current_trigger.is_populated() {
-            let t = ast.topics.get_mut(&topic).expect("or else");
-            t.add_trigger(current_trigger);
-        }
+        Ok(())
+    }
+}
 
-        Ok(ast)
+/// Check whether a line of source code is the `< object` terminator of an object macro.
+///
+/// Only a line consisting of the terminator itself counts (optionally followed by a
+/// comment), so that object code which merely contains the text `< object` is safe.
+fn is_object_end(line: &str) -> bool {
+    let Some(rest) = line.trim().strip_prefix('<') else {
+        return false;
+    };
+    match rest.trim_start().strip_prefix("object") {
+        Some(rest) => {
+            let rest = rest.trim_start();
+            rest.is_empty() || rest.starts_with("//")
+        }
+        None => false,
     }
 }
@@ -21,6 +21,8 @@ lazy_static! {
     pub static ref BOT_TAG: Regex = Regex::new(r"<bot (.+?)>").unwrap();
     pub static ref USER_VAR_TAG: Regex = Regex::new(r"<get (.+?)>").unwrap();
     pub static ref HISTORY_TAG: Regex = Regex::new(r"<(?:input|reply)(\d+?)>").unwrap();
+    /// Tags in a trigger that depend on the current user: <get>, <input> and <reply>
+    pub static ref TRIGGER_USER_TAGS: Regex = Regex::new(r"<(?:get [^>]+?|input\d*|reply\d*)>").unwrap();
     pub static ref CALL_TAG: Regex = Regex::new(r"<call>(.+?)</call>").unwrap();
     pub static ref NASTIES: Regex = Regex::new(r"[^A-Za-z0-9 ]").unwrap();
     pub static ref META_CHARACTERS: Regex = Regex::new(r"[\\<>]+").unwrap();
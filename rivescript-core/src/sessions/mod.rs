@@ -64,6 +64,7 @@ pub trait SessionManager: Send + Sync {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct History {
     pub input: Vec<String>,
     pub reply: Vec<String>,
@@ -0,0 +1,267 @@
+//! # Source Providers
+//!
+//! A SourceProvider lists and reads RiveScript documents from somewhere: a
+//! directory on disk, an in-memory map of documents, or (with the `zip` and
+//! `tar` features) an archive bundle. `RiveScript::load_from` loads every
+//! document that a provider lists, in order.
+//!
+//! Documents are listed in a stable, sorted order rather than the order the
+//! filesystem or archive happens to return them in. This matters because later
+//! documents win over earlier ones when they define the same `! sub` or `! var`.
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::collections::BTreeMap;
+use std::{fs, io, path::Path, path::PathBuf};
+
+/// A source of RiveScript documents.
+pub trait SourceProvider {
+    /// List the names of the RiveScript documents, in the order they should be loaded.
+    ///
+    /// The name of a document is used as its filename in diagnostics and source locations.
+    fn list(&self) -> io::Result<Vec<String>>;
+
+    /// Read the source code of a document by name.
+    fn read(&mut self, name: &str) -> io::Result<String>;
+}
+
+/// Whether a file has a RiveScript extension: `.rive`, or the legacy `.rs`.
+pub fn is_rivescript(path: &str) -> bool {
+    match Path::new(path).extension() {
+        Some(ext) => ext.eq_ignore_ascii_case("rive") || ext.eq_ignore_ascii_case("rs"),
+        None => false,
+    }
+}
+
+/// Options for loading a directory of RiveScript documents.
+///
+/// Glob patterns are matched against each file's path relative to the directory
+/// being loaded, using `/` as the separator on every platform. A `*` doesn't match
+/// across directories, so use `**` for that: for example `drafts/**` or `**/*.test.rive`.
+///
+/// Example
+/// ```rust
+/// # use rivescript_core::sources::{DirectoryOptions, FileSystem, SourceProvider};
+/// # fn main() {
+///     let options = DirectoryOptions {
+///         recursive: true,
+///         exclude: vec![String::from("**/javascript.rive")],
+///         ..Default::default()
+///     };
+///     let files = FileSystem::with_options("../eg/brain", options).list().unwrap();
+///     assert!(files.iter().all(|f| !f.ends_with("javascript.rive")));
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryOptions {
+    /// Also load documents from subdirectories.
+    pub recursive: bool,
+
+    /// If not empty, only load the documents that match one of these glob patterns.
+    pub include: Vec<String>,
+
+    /// Skip the documents that match any of these glob patterns.
+    pub exclude: Vec<String>,
+}
+
+/// RiveScript documents on the real filesystem: a single file, or a directory of them.
+pub struct FileSystem {
+    path: PathBuf,
+    options: DirectoryOptions,
+}
+
+impl FileSystem {
+    /// Provide the file at this path, or the documents in this directory (but not its
+    /// subdirectories).
+    pub fn new(path: &str) -> Self {
+        Self::with_options(path, DirectoryOptions::default())
+    }
+
+    /// Provide the documents in a directory with options for recursion and filtering.
+    pub fn with_options(path: &str, options: DirectoryOptions) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            options,
+        }
+    }
+}
+
+impl SourceProvider for FileSystem {
+    fn list(&self) -> io::Result<Vec<String>> {
+        if !fs::metadata(&self.path)?.is_dir() {
+            return Ok(vec![self.path.display().to_string()]);
+        }
+
+        let include = glob_set(&self.options.include)?;
+        let exclude = glob_set(&self.options.exclude)?;
+
+        let root = self.path.as_path();
+        let mut files = Vec::new();
+        walk(root, self.options.recursive, &mut files)?;
+        files.sort();
+
+        let mut result = Vec::new();
+        for file in files {
+            let relative = file.strip_prefix(root).unwrap_or(&file);
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            if !self.options.include.is_empty() && !include.is_match(&relative) {
+                continue;
+            }
+            if exclude.is_match(&relative) {
+                continue;
+            }
+            result.push(file.display().to_string());
+        }
+
+        Ok(result)
+    }
+
+    fn read(&mut self, name: &str) -> io::Result<String> {
+        fs::read_to_string(name)
+    }
+}
+
+fn glob_set(patterns: &[String]) -> io::Result<GlobSet> {
+    let invalid = |e: globset::Error| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern).literal_separator(true).build().map_err(invalid)?);
+    }
+    builder.build().map_err(invalid)
+}
+
+/// Collect the RiveScript files in a directory (and its subdirectories, if recursive).
+fn walk(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        // Symlinked directories aren't followed, to avoid loops.
+        if entry.file_type()?.is_dir() {
+            if recursive {
+                walk(&path, recursive, files)?;
+            }
+        } else if is_rivescript(&path.to_string_lossy()) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// RiveScript documents held in memory, listed in sorted order by name.
+///
+/// Example
+/// ```rust
+/// # use rivescript_core::sources::{Memory, SourceProvider};
+/// # fn main() {
+///     let mut sources = Memory::new();
+///     sources.insert("greetings.rive", "+ hello bot\n- Hello human!");
+///     assert_eq!(sources.list().unwrap(), vec!["greetings.rive"]);
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    documents: BTreeMap<String, String>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add (or replace) a document.
+    pub fn insert(&mut self, name: &str, source: &str) {
+        self.documents.insert(name.to_string(), source.to_string());
+    }
+}
+
+impl SourceProvider for Memory {
+    fn list(&self) -> io::Result<Vec<String>> {
+        Ok(self.documents.keys().cloned().collect())
+    }
+
+    fn read(&mut self, name: &str) -> io::Result<String> {
+        match self.documents.get(name) {
+            Some(source) => Ok(source.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("no document named '{name}'"))),
+        }
+    }
+}
+
+/// RiveScript documents in a zip archive, listed in sorted order by their path in the archive.
+///
+/// Only the files with a RiveScript extension are listed. This needs the `zip` feature.
+#[cfg(feature = "zip")]
+pub struct Zip<R> {
+    archive: zip::ZipArchive<R>,
+}
+
+#[cfg(feature = "zip")]
+impl<R: io::Read + io::Seek> Zip<R> {
+    /// Open a zip archive, such as a `File`.
+    pub fn new(reader: R) -> io::Result<Self> {
+        Ok(Self {
+            archive: zip::ZipArchive::new(reader)?,
+        })
+    }
+}
+
+#[cfg(feature = "zip")]
+impl<R: io::Read + io::Seek> SourceProvider for Zip<R> {
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut names: Vec<String> = self.archive
+            .file_names()
+            .filter(|name| !name.ends_with('/') && is_rivescript(name))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn read(&mut self, name: &str) -> io::Result<String> {
+        let mut source = String::new();
+        io::Read::read_to_string(&mut self.archive.by_name(name)?, &mut source)?;
+        Ok(source)
+    }
+}
+
+/// RiveScript documents in a tar archive, listed in sorted order by their path in the archive.
+///
+/// A tar archive can only be read from start to end, so the RiveScript documents are read
+/// into memory when it's opened. For a compressed `.tar.gz` bundle, wrap the reader in a
+/// decompressor first. This needs the `tar` feature.
+#[cfg(feature = "tar")]
+pub struct Tar {
+    documents: Memory,
+}
+
+#[cfg(feature = "tar")]
+impl Tar {
+    /// Read the RiveScript documents from a tar archive.
+    pub fn new(reader: impl io::Read) -> io::Result<Self> {
+        let mut documents = Memory::new();
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().replace('\\', "/");
+            if !entry.header().entry_type().is_file() || !is_rivescript(&name) {
+                continue;
+            }
+
+            let mut source = String::new();
+            io::Read::read_to_string(&mut entry, &mut source)?;
+            documents.insert(&name, &source);
+        }
+        Ok(Self { documents })
+    }
+}
+
+#[cfg(feature = "tar")]
+impl SourceProvider for Tar {
+    fn list(&self) -> io::Result<Vec<String>> {
+        self.documents.list()
+    }
+
+    fn read(&mut self, name: &str) -> io::Result<String> {
+        self.documents.read(name)
+    }
+}
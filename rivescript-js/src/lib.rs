@@ -27,6 +27,10 @@ impl LanguageLoader for JavaScriptLoader {
         Ok(true)
     }
 
+    fn unload(&mut self, name: &str) {
+        self.sources.remove(name);
+    }
+
     async fn call(&self, proxy: &dyn Proxy, name: &str, args: Vec<String>) -> Result<SubroutineResult, String> {
         let code = self.sources.get(name)
             .ok_or_else(|| format!("[ERR: Object '{}' Not Found]", name))?;
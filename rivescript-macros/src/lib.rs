@@ -0,0 +1,311 @@
+//! # RiveScript Macros
+//!
+//! Procedural macros for RiveScript. These are re-exported from the `rivescript`
+//! crate, so use them from there, e.g. `rivescript::embed_brain!`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use rivescript_core::ast::{AST, Condition, Location, Object, Topic, Trigger};
+use rivescript_core::errors::Diagnostic;
+use rivescript_core::parser::Parser;
+use rivescript_core::sources::{DirectoryOptions, FileSystem, SourceProvider};
+use syn::parse::{Parse, ParseStream};
+use syn::{Ident, LitStr, Token};
+
+/// Parse RiveScript documents at compile time and embed the resulting AST.
+///
+/// The path may be a single file or a directory of `.rive` files (loaded in
+/// sorted order, like `RiveScript::load_directory`), relative to the crate's
+/// `Cargo.toml`. It's followed by any of these options:
+///
+/// * `strict` rejects documents with any warnings (see `Parser::strict`).
+/// * `includes` enables the `! include` directive.
+/// * `recursive` loads the subdirectories of a directory too.
+///
+/// Parse errors fail the build. Parser warnings are reported as compiler warnings
+/// (through the `deprecated` lint, which is the only way a macro can warn on
+/// stable Rust), unless `strict` turns them into errors. The macro expands to an
+/// expression of type
+/// `rivescript::ast::AST`, which can be loaded with `RiveScript::load_ast`:
+///
+/// ```ignore
+/// let mut bot = RiveScript::new();
+/// bot.load_ast(rivescript::embed_brain!("brain/", strict)).unwrap();
+/// bot.sort_triggers();
+/// ```
+///
+/// The crate is rebuilt when any of the embedded files change, but not when
+/// new files are added to the directory.
+#[proc_macro]
+pub fn embed_brain(input: TokenStream) -> TokenStream {
+    let args = syn::parse_macro_input!(input as EmbedArgs);
+    match embed(&args) {
+        Ok(tokens) => tokens.into(),
+        Err(message) => syn::Error::new(args.path.span(), message).to_compile_error().into(),
+    }
+}
+
+/// The arguments to `embed_brain!`.
+struct EmbedArgs {
+    path: LitStr,
+    strict: bool,
+    includes: bool,
+    recursive: bool,
+}
+
+impl Parse for EmbedArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Self {
+            path: input.parse()?,
+            strict: false,
+            includes: false,
+            recursive: false,
+        };
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let option: Ident = input.parse()?;
+            match option.to_string().as_str() {
+                "strict" => args.strict = true,
+                "includes" => args.includes = true,
+                "recursive" => args.recursive = true,
+                _ => {
+                    return Err(syn::Error::new(
+                        option.span(),
+                        "unknown option; expected `strict`, `includes` or `recursive`",
+                    ));
+                }
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+fn embed(args: &EmbedArgs) -> Result<TokenStream2, String> {
+    let root = env::var("CARGO_MANIFEST_DIR").map(PathBuf::from).unwrap_or_default();
+    let path = root.join(args.path.value());
+
+    let options = DirectoryOptions {
+        recursive: args.recursive,
+        ..Default::default()
+    };
+    let mut sources = FileSystem::with_options(&path.to_string_lossy(), options);
+    let files = sources.list().map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+
+    // Parse every file, using its full path so that `! include` can find its neighbors.
+    let parser = Parser {
+        strict: args.strict,
+        includes: args.includes,
+    };
+    let mut ast = AST::new();
+    let mut warnings = Vec::new();
+    for filename in files.iter() {
+        let contents = sources.read(filename).map_err(|e| format!("Couldn't read {filename}: {e}"))?;
+        let (parsed, diagnostics) = parser.parse_with_warnings(filename, contents).map_err(|e| e.to_string())?;
+        ast.extend(parsed);
+        warnings.extend(diagnostics);
+    }
+
+    // Have Cargo rebuild the crate when any of the files (or the files they include) change.
+    let mut tracked: BTreeSet<String> = files.into_iter().collect();
+    tracked.extend(locations(&mut ast).map(|location| location.filename.clone()));
+    tracked.remove("");
+
+    // Source locations in the embedded brain are relative to the crate, rather than
+    // to wherever it happened to be built.
+    for location in locations(&mut ast) {
+        location.filename = relative_to(&root, &location.filename);
+    }
+    for diagnostic in warnings.iter_mut() {
+        diagnostic.filename = relative_to(&root, &diagnostic.filename);
+    }
+
+    let warnings = warnings.iter().map(|diagnostic| warning_tokens(args, diagnostic));
+    let ast = ast_tokens(&ast);
+    Ok(quote! {
+        {
+            #( const _: &[u8] = include_bytes!(#tracked); )*
+            #( #warnings )*
+            #ast
+        }
+    })
+}
+
+/// A filename relative to the crate, if it's inside it.
+fn relative_to(root: &Path, filename: &str) -> String {
+    match Path::new(filename).strip_prefix(root) {
+        Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+        Err(_) => filename.to_string(),
+    }
+}
+
+/// Code that makes the compiler print a parser warning, pointing at the macro's path.
+///
+/// Proc macros can't emit warnings of their own on stable Rust, so this uses a
+/// deprecated item instead. `#[allow(deprecated)]` on the macro call hides them.
+fn warning_tokens(args: &EmbedArgs, diagnostic: &Diagnostic) -> TokenStream2 {
+    let note = diagnostic.to_string();
+    quote_spanned! {args.path.span()=>
+        {
+            #[deprecated(note = #note)]
+            struct RiveScriptWarning;
+            let _ = RiveScriptWarning;
+        }
+    }
+}
+
+/// All of the source locations in the AST.
+fn locations(ast: &mut AST) -> impl Iterator<Item = &mut Location> {
+    let topics = ast.topics.values_mut().flat_map(|topic| {
+        std::iter::once(&mut topic.location).chain(topic.triggers.iter_mut().flat_map(|trigger| {
+            std::iter::once(&mut trigger.location).chain(trigger.condition.iter_mut().map(|c| &mut c.location))
+        }))
+    });
+    let objects = ast.objects.values_mut().map(|object| &mut object.location);
+    topics.chain(objects)
+}
+
+// The rest of this file turns the AST into Rust code that builds it again.
+// HashMap entries are written in sorted order, so that the output (and the
+// compiled crate) is the same on every build.
+
+fn ast_tokens(ast: &AST) -> TokenStream2 {
+    let version = ast.version;
+    let globals = string_map(&ast.globals.read().expect("RwLock poisoned"));
+    let vars = string_map(&ast.vars.read().expect("RwLock poisoned"));
+    let subs = string_map(&ast.subs);
+    let person = string_map(&ast.person);
+    let arrays = map(&ast.arrays, |items| {
+        let items = items.iter().map(|item| string(item));
+        quote! { ::std::vec![ #(#items),* ] }
+    });
+    let topics = map(&ast.topics, topic_tokens);
+    let objects = map(&ast.objects, object_tokens);
+
+    quote! {
+        ::rivescript::ast::AST {
+            version: #version,
+            globals: ::std::sync::RwLock::new(#globals),
+            vars: ::std::sync::RwLock::new(#vars),
+            subs: #subs,
+            person: #person,
+            arrays: #arrays,
+            topics: #topics,
+            objects: #objects,
+        }
+    }
+}
+
+fn topic_tokens(topic: &Topic) -> TokenStream2 {
+    let name = string(&topic.name);
+    let triggers = topic.triggers.iter().map(trigger_tokens);
+    let includes = map(&topic.includes, |v| quote! { #v });
+    let inherits = map(&topic.inherits, |v| quote! { #v });
+    let location = location_tokens(&topic.location);
+
+    quote! {
+        ::rivescript::ast::Topic {
+            name: #name,
+            triggers: ::std::vec![ #(#triggers),* ],
+            includes: #includes,
+            inherits: #inherits,
+            location: #location,
+        }
+    }
+}
+
+fn trigger_tokens(trigger: &Trigger) -> TokenStream2 {
+    let pattern = string(&trigger.trigger);
+    let reply = trigger.reply.iter().map(|r| string(r));
+    let condition = trigger.condition.iter().map(condition_tokens);
+    let redirect = string(&trigger.redirect);
+    let previous = string(&trigger.previous);
+    let location = location_tokens(&trigger.location);
+
+    quote! {
+        ::rivescript::ast::Trigger {
+            trigger: #pattern,
+            reply: ::std::vec![ #(#reply),* ],
+            condition: ::std::vec![ #(#condition),* ],
+            redirect: #redirect,
+            previous: #previous,
+            location: #location,
+        }
+    }
+}
+
+fn condition_tokens(condition: &Condition) -> TokenStream2 {
+    let left = string(&condition.left);
+    let operator = string(&condition.operator);
+    let right = string(&condition.right);
+    let reply = string(&condition.reply);
+    let location = location_tokens(&condition.location);
+
+    quote! {
+        ::rivescript::ast::Condition {
+            left: #left,
+            operator: #operator,
+            right: #right,
+            reply: #reply,
+            location: #location,
+        }
+    }
+}
+
+fn object_tokens(object: &Object) -> TokenStream2 {
+    let name = string(&object.name);
+    let language = string(&object.language);
+    let code = object.code.iter().map(|line| string(line));
+    let location = location_tokens(&object.location);
+
+    quote! {
+        ::rivescript::ast::Object {
+            name: #name,
+            language: #language,
+            code: ::std::vec![ #(#code),* ],
+            location: #location,
+        }
+    }
+}
+
+fn location_tokens(location: &Location) -> TokenStream2 {
+    let filename = string(&location.filename);
+    let start = location.start;
+    let end = location.end;
+    quote! {
+        ::rivescript::ast::Location {
+            filename: #filename,
+            start: #start,
+            end: #end,
+        }
+    }
+}
+
+fn string(value: &str) -> TokenStream2 {
+    quote! { ::std::string::String::from(#value) }
+}
+
+fn string_map(values: &HashMap<String, String>) -> TokenStream2 {
+    map(values, |value| string(value))
+}
+
+fn map<V>(values: &HashMap<String, V>, value_tokens: impl Fn(&V) -> TokenStream2) -> TokenStream2 {
+    let mut keys: Vec<&String> = values.keys().collect();
+    keys.sort();
+    let entries = keys.into_iter().map(|key| {
+        let value = value_tokens(&values[key]);
+        let key = string(key);
+        quote! { (#key, #value) }
+    });
+    quote! { ::std::collections::HashMap::from([ #(#entries),* ]) }
+}
@@ -0,0 +1,85 @@
+// Binary brain cache.
+//
+// Parsing and sorting a large brain from source takes a while, so a parsed and
+// sorted brain can be saved to a compact binary file and loaded back in on the
+// next start, skipping both steps.
+//
+// The file begins with a small header: a magic string, the cache format version,
+// a hash of the RiveScript sources that the brain was built from and the files
+// that each of them `! include`d (so that those can be checked too). The rest is
+// the bincode serialization of the AST and the sorted buffers.
+
+use crate::ast::{self, AST};
+use crate::errors::CacheError;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io::Read, io::Write};
+
+/// Magic bytes that begin every brain cache file.
+const MAGIC: &[u8; 8] = b"RSBRAIN\0";
+
+/// Version of the cache file format. Bump this whenever the AST or the sorted
+/// buffers change shape, so that older caches are rejected rather than misread.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The files that each loaded document included, keyed by the document's name.
+pub type Included = HashMap<String, Vec<String>>;
+
+/// The contents of a brain cache file, borrowed from a RiveScript instance.
+#[derive(Serialize)]
+pub struct CachedBrainRef<'a> {
+    pub brain: &'a AST,
+    pub topics: &'a HashMap<String, Vec<ast::Trigger>>,
+    pub thats: &'a HashMap<String, Vec<ast::Trigger>>,
+    pub subs: &'a Vec<String>,
+    pub person: &'a Vec<String>,
+}
+
+/// The contents of a brain cache file, read back from disk.
+#[derive(Deserialize)]
+pub struct CachedBrain {
+    pub brain: AST,
+    pub topics: HashMap<String, Vec<ast::Trigger>>,
+    pub thats: HashMap<String, Vec<ast::Trigger>>,
+    pub subs: Vec<String>,
+    pub person: Vec<String>,
+}
+
+/// Write a brain cache to a writer.
+pub fn write(w: &mut impl Write, hash: u64, included: &Included, cached: &CachedBrainRef) -> Result<(), CacheError> {
+    w.write_all(MAGIC)?;
+    w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    w.write_all(&hash.to_le_bytes())?;
+    bincode::serialize_into(&mut *w, included)?;
+    bincode::serialize_into(w, cached)?;
+    Ok(())
+}
+
+/// Read the header of a brain cache, returning the source hash and the included files.
+pub fn read_header(r: &mut impl Read) -> Result<(u64, Included), CacheError> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(CacheError::new("not a RiveScript brain cache"));
+    }
+
+    let mut version = [0u8; 4];
+    r.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != FORMAT_VERSION {
+        return Err(CacheError::new(&format!(
+            "unsupported cache format version {version} (expected {FORMAT_VERSION})"
+        )));
+    }
+
+    let mut hash = [0u8; 8];
+    r.read_exact(&mut hash)?;
+    let included: Included = bincode::deserialize_from(&mut *r)?;
+    Ok((u64::from_le_bytes(hash), included))
+}
+
+/// Read a whole brain cache, returning its header and contents.
+pub fn read(r: &mut impl Read) -> Result<(u64, Included, CachedBrain), CacheError> {
+    let (hash, included) = read_header(r)?;
+    let cached: CachedBrain = bincode::deserialize_from(r)?;
+    Ok((hash, included, cached))
+}
@@ -0,0 +1,410 @@
+// Structural comparison of two bot brains.
+//
+// A text diff of the .rive files shows what was typed rather than what changed
+// in the bot's behavior: moving a trigger to another file, or rewrapping a reply
+// with ^Continue, looks like a change when it isn't one. This compares the parsed
+// brains instead, trigger by trigger, along with (for sorted bots) the order in
+// which the triggers will be tried.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use rivescript_core::regex;
+
+use crate::ast::{self, AST};
+
+/// The kinds of definitions that are compared between brains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DefinitionKind {
+    Global,
+    Var,
+    Sub,
+    Person,
+    Array,
+    Object,
+}
+
+impl fmt::Display for DefinitionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DefinitionKind::Global => "global",
+            DefinitionKind::Var => "var",
+            DefinitionKind::Sub => "sub",
+            DefinitionKind::Person => "person",
+            DefinitionKind::Array => "array",
+            DefinitionKind::Object => "object",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A change to a trigger that exists in both brains.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TriggerChange {
+    ReplyAdded(String),
+    ReplyRemoved(String),
+
+    /// Conditions are written like `<get name> == undefined => What's your name?`.
+    ConditionAdded(String),
+    ConditionRemoved(String),
+
+    /// The conditions are the same, but they're checked in a different order.
+    ConditionsReordered,
+    Redirect { before: String, after: String },
+}
+
+impl fmt::Display for TriggerChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerChange::ReplyAdded(reply) => write!(f, "added reply {reply:?}"),
+            TriggerChange::ReplyRemoved(reply) => write!(f, "removed reply {reply:?}"),
+            TriggerChange::ConditionAdded(condition) => write!(f, "added condition {condition:?}"),
+            TriggerChange::ConditionRemoved(condition) => write!(f, "removed condition {condition:?}"),
+            TriggerChange::ConditionsReordered => write!(f, "reordered the conditions"),
+            TriggerChange::Redirect { before, after } => write!(f, "redirect {} -> {}", or_none(before), or_none(after)),
+        }
+    }
+}
+
+/// One difference between two brains.
+///
+/// Triggers are identified by their topic, pattern and `%Previous` (which is empty
+/// if they don't have one).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    TopicAdded(String),
+    TopicRemoved(String),
+    Includes { topic: String, before: Vec<String>, after: Vec<String> },
+    Inherits { topic: String, before: Vec<String>, after: Vec<String> },
+    TriggerAdded { topic: String, trigger: String, previous: String },
+    TriggerRemoved { topic: String, trigger: String, previous: String },
+    TriggerChanged { topic: String, trigger: String, previous: String, change: TriggerChange },
+
+    /// A trigger that will be tried at a different point in the match order, relative to
+    /// the triggers around it. The positions count from 1. Triggers that only moved
+    /// because others were added or removed aren't reported.
+    TriggerMoved { topic: String, trigger: String, previous: String, before: usize, after: usize },
+
+    /// A definition was added (`before` is None), removed (`after` is None) or changed.
+    /// Arrays are written with `|` between the items, and object macros as their language.
+    Definition { kind: DefinitionKind, name: String, before: Option<String>, after: Option<String> },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::TopicAdded(topic) => write!(f, "+ > topic {topic}"),
+            Difference::TopicRemoved(topic) => write!(f, "- > topic {topic}"),
+            Difference::Includes { topic, before, after } => {
+                write!(f, "~ > topic {topic} includes: {} -> {}", list(before), list(after))
+            },
+            Difference::Inherits { topic, before, after } => {
+                write!(f, "~ > topic {topic} inherits: {} -> {}", list(before), list(after))
+            },
+            Difference::TriggerAdded { topic, trigger, previous } => {
+                write!(f, "+ [{topic}] {}", label(trigger, previous))
+            },
+            Difference::TriggerRemoved { topic, trigger, previous } => {
+                write!(f, "- [{topic}] {}", label(trigger, previous))
+            },
+            Difference::TriggerChanged { topic, trigger, previous, change } => {
+                write!(f, "~ [{topic}] {}: {change}", label(trigger, previous))
+            },
+            Difference::TriggerMoved { topic, trigger, previous, before, after } => write!(
+                f,
+                "> [{topic}] {} moved in the match order from #{before} to #{after}",
+                label(trigger, previous),
+            ),
+            Difference::Definition { kind: DefinitionKind::Object, name, before, after } => match (before, after) {
+                (None, Some(after)) => write!(f, "+ > object {name} {after}"),
+                (Some(before), None) => write!(f, "- > object {name} {before}"),
+                (Some(before), Some(after)) if before == after => write!(f, "~ > object {name} {after}: changed its code"),
+                _ => write!(f, "~ > object {name}: {} -> {}", before.as_deref().unwrap_or_default(), after.as_deref().unwrap_or_default()),
+            },
+            Difference::Definition { kind, name, before, after } => match (before, after) {
+                (None, Some(after)) => write!(f, "+ ! {kind} {name} = {after}"),
+                (Some(before), None) => write!(f, "- ! {kind} {name} = {before}"),
+                _ => write!(f, "~ ! {kind} {name} = {} -> {}", before.as_deref().unwrap_or_default(), after.as_deref().unwrap_or_default()),
+            },
+        }
+    }
+}
+
+/// The differences between two brains, in a stable order: definitions first, then
+/// each topic by name, then the triggers that moved in the match order.
+///
+/// Displaying it prints one difference per line, marked with `+` (added), `-`
+/// (removed), `~` (changed) or `>` (moved in the match order).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BrainDiff {
+    pub differences: Vec<Difference>,
+}
+
+impl BrainDiff {
+    /// Compare the contents of two brains. This can't tell whether the match order
+    /// changed, because that's only known once the triggers are sorted: use
+    /// `RiveScript::diff` for that.
+    pub fn between(old: &AST, new: &AST) -> Self {
+        let mut differences = Vec::new();
+
+        let globals = (old.globals.read().expect("RwLock poisoned"), new.globals.read().expect("RwLock poisoned"));
+        diff_map(&mut differences, DefinitionKind::Global, &globals.0, &globals.1);
+        let vars = (old.vars.read().expect("RwLock poisoned"), new.vars.read().expect("RwLock poisoned"));
+        diff_map(&mut differences, DefinitionKind::Var, &vars.0, &vars.1);
+        diff_map(&mut differences, DefinitionKind::Sub, &old.subs, &new.subs);
+        diff_map(&mut differences, DefinitionKind::Person, &old.person, &new.person);
+
+        let arrays = |ast: &AST| -> HashMap<String, String> {
+            ast.arrays.iter().map(|(name, items)| (name.clone(), items.join("|"))).collect()
+        };
+        diff_map(&mut differences, DefinitionKind::Array, &arrays(old), &arrays(new));
+
+        // Objects are shown as their language, so one whose code changed shows the same
+        // value on both sides.
+        let names: BTreeSet<&String> = old.objects.keys().chain(new.objects.keys()).collect();
+        for name in names {
+            let (before, after) = (old.objects.get(name), new.objects.get(name));
+            let changed = match (before, after) {
+                (Some(before), Some(after)) => before.language != after.language || before.code != after.code,
+                _ => true,
+            };
+            if changed {
+                differences.push(Difference::Definition {
+                    kind: DefinitionKind::Object,
+                    name: name.clone(),
+                    before: before.map(|object| object.language.clone()),
+                    after: after.map(|object| object.language.clone()),
+                });
+            }
+        }
+
+        let names: BTreeSet<&String> = old.topics.keys().chain(new.topics.keys()).collect();
+        for name in names {
+            diff_topic(&mut differences, name, old.topics.get(name), new.topics.get(name));
+        }
+
+        Self { differences }
+    }
+
+    /// Whether the brains are the same.
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// Add the triggers that moved in the match order of a topic, given its sorted
+    /// triggers in the old and new brain.
+    pub(crate) fn moved(&mut self, topic: &str, old: &[ast::Trigger], new: &[ast::Trigger]) {
+        // Triggers from a topic that inherits others are tagged with their level, which
+        // changes when the inheritance does; compare them without it.
+        let key = |trigger: &ast::Trigger| (regex::INHERITS.replace_all(&trigger.trigger, "").to_string(), trigger.previous.clone());
+
+        // Index each trigger by its first position in the new order.
+        let mut positions: HashMap<(String, String), usize> = HashMap::new();
+        for (i, trigger) in new.iter().enumerate() {
+            positions.entry(key(trigger)).or_insert(i);
+        }
+
+        // The triggers in both, in their old order, with their old and new positions.
+        let mut seen = BTreeSet::new();
+        let mut common = Vec::new();
+        for (i, trigger) in old.iter().enumerate() {
+            let key = key(trigger);
+            if let Some(&after) = positions.get(&key)
+                && seen.insert(key.clone())
+            {
+                common.push((key, i, after));
+            }
+        }
+
+        // The longest run that kept its relative order stayed put; the rest moved.
+        let after: Vec<usize> = common.iter().map(|(_, _, after)| *after).collect();
+        let kept = longest_increasing(&after);
+        for (i, ((trigger, previous), before, after)) in common.into_iter().enumerate() {
+            if !kept.contains(&i) {
+                self.differences.push(Difference::TriggerMoved {
+                    topic: topic.to_string(),
+                    trigger,
+                    previous,
+                    before: before + 1,
+                    after: after + 1,
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Display for BrainDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for difference in self.differences.iter() {
+            writeln!(f, "{difference}")?;
+        }
+        Ok(())
+    }
+}
+
+fn diff_map(differences: &mut Vec<Difference>, kind: DefinitionKind, old: &HashMap<String, String>, new: &HashMap<String, String>) {
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for name in names {
+        let (before, after) = (old.get(name), new.get(name));
+        if before != after {
+            differences.push(Difference::Definition {
+                kind,
+                name: name.clone(),
+                before: before.cloned(),
+                after: after.cloned(),
+            });
+        }
+    }
+}
+
+// A trigger's definitions in the old and new topic.
+type Pair<'a> = (Vec<&'a ast::Trigger>, Vec<&'a ast::Trigger>);
+
+fn diff_topic(differences: &mut Vec<Difference>, name: &str, old: Option<&ast::Topic>, new: Option<&ast::Topic>) {
+    match (old, new) {
+        (None, Some(_)) => differences.push(Difference::TopicAdded(name.to_string())),
+        (Some(_), None) => differences.push(Difference::TopicRemoved(name.to_string())),
+        _ => (),
+    }
+
+    let names = |topic: Option<&ast::Topic>, inherits: bool| -> Vec<String> {
+        let mut names: Vec<String> = topic
+            .map(|topic| if inherits { &topic.inherits } else { &topic.includes })
+            .map(|names| names.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+        names
+    };
+    let (before, after) = (names(old, false), names(new, false));
+    if before != after {
+        differences.push(Difference::Includes { topic: name.to_string(), before, after });
+    }
+    let (before, after) = (names(old, true), names(new, true));
+    if before != after {
+        differences.push(Difference::Inherits { topic: name.to_string(), before, after });
+    }
+
+    // Group the triggers by pattern and %Previous, in the order they first appear. A
+    // trigger that's defined twice is paired up with the other side's in order.
+    let mut order: Vec<(&str, &str)> = Vec::new();
+    let mut triggers: HashMap<(&str, &str), Pair> = HashMap::new();
+    for (side, topic) in [old, new].into_iter().enumerate() {
+        for trigger in topic.map(|topic| topic.triggers.as_slice()).unwrap_or_default() {
+            let key = (trigger.trigger.as_str(), trigger.previous.as_str());
+            let entry = triggers.entry(key).or_insert_with(|| {
+                order.push(key);
+                (Vec::new(), Vec::new())
+            });
+            if side == 0 { entry.0.push(trigger) } else { entry.1.push(trigger) }
+        }
+    }
+
+    for key in order {
+        let (old, new) = &triggers[&key];
+        for i in 0..old.len().max(new.len()) {
+            let (topic, trigger, previous) = (name.to_string(), key.0.to_string(), key.1.to_string());
+            match (old.get(i), new.get(i)) {
+                (Some(_), None) => differences.push(Difference::TriggerRemoved { topic, trigger, previous }),
+                (None, Some(_)) => differences.push(Difference::TriggerAdded { topic, trigger, previous }),
+                (Some(old), Some(new)) => {
+                    for change in diff_trigger(old, new) {
+                        differences.push(Difference::TriggerChanged {
+                            topic: topic.clone(),
+                            trigger: trigger.clone(),
+                            previous: previous.clone(),
+                            change,
+                        });
+                    }
+                },
+                (None, None) => (),
+            }
+        }
+    }
+}
+
+fn diff_trigger(old: &ast::Trigger, new: &ast::Trigger) -> Vec<TriggerChange> {
+    let mut changes = Vec::new();
+
+    // Replies are picked at random, so their order doesn't matter.
+    let (removed, added) = diff_items(&old.reply, &new.reply);
+    changes.extend(removed.into_iter().map(TriggerChange::ReplyRemoved));
+    changes.extend(added.into_iter().map(TriggerChange::ReplyAdded));
+
+    // Conditions are checked in order, so it does for them.
+    let conditions = |trigger: &ast::Trigger| -> Vec<String> {
+        trigger.condition
+            .iter()
+            .map(|c| format!("{} {} {} => {}", c.left, c.operator, c.right, c.reply))
+            .collect()
+    };
+    let (before, after) = (conditions(old), conditions(new));
+    if before != after {
+        let (removed, added) = diff_items(&before, &after);
+        if removed.is_empty() && added.is_empty() {
+            changes.push(TriggerChange::ConditionsReordered);
+        }
+        changes.extend(removed.into_iter().map(TriggerChange::ConditionRemoved));
+        changes.extend(added.into_iter().map(TriggerChange::ConditionAdded));
+    }
+
+    if old.redirect != new.redirect {
+        changes.push(TriggerChange::Redirect { before: old.redirect.clone(), after: new.redirect.clone() });
+    }
+
+    changes
+}
+
+// The items only in `old` and only in `new`, counting duplicates.
+fn diff_items(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut added: Vec<&String> = new.iter().collect();
+    let mut removed = Vec::new();
+    for item in old {
+        match added.iter().position(|other| *other == item) {
+            Some(i) => {
+                added.remove(i);
+            },
+            None => removed.push(item.clone()),
+        }
+    }
+    (removed, added.into_iter().cloned().collect())
+}
+
+// The indexes of a longest strictly increasing subsequence.
+fn longest_increasing(values: &[usize]) -> BTreeSet<usize> {
+    // tails[k] is the index of the smallest value that ends a run of length k + 1.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut parents: Vec<Option<usize>> = vec![None; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        let k = tails.partition_point(|&j| values[j] < *value);
+        parents[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+
+    let mut indexes = BTreeSet::new();
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        indexes.insert(i);
+        next = parents[i];
+    }
+    indexes
+}
+
+fn or_none(value: &str) -> &str {
+    if value.is_empty() { "(none)" } else { value }
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() { String::from("(none)") } else { names.join(" ") }
+}
+
+fn label(trigger: &str, previous: &str) -> String {
+    if previous.is_empty() {
+        format!("+ {trigger}")
+    } else {
+        format!("+ {trigger} % {previous}")
+    }
+}
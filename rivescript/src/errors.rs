@@ -26,3 +26,82 @@ impl Error for ParseError {
         &self.message
     }
 }
+
+/// CacheError is returned when a binary brain cache can't be written or read,
+/// for example because the file is corrupt or was written by an incompatible
+/// version of RiveScript.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct CacheError {
+    message: String,
+}
+
+#[cfg(feature = "serde")]
+impl CacheError {
+    pub fn new(msg: &str) -> CacheError {
+        CacheError {
+            message: msg.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cache error: {}", self.message)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Error for CacheError {}
+
+#[cfg(feature = "serde")]
+impl From<std::io::Error> for CacheError {
+    fn from(err: std::io::Error) -> Self {
+        CacheError::new(&err.to_string())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<bincode::Error> for CacheError {
+    fn from(err: bincode::Error) -> Self {
+        CacheError::new(&err.to_string())
+    }
+}
+
+/// LoadError is returned by the all-or-nothing loaders (`load_all` and `load_files`)
+/// when any of the documents couldn't be read or parsed. It has the error for every
+/// document that failed, and none of the documents were loaded into the bot.
+#[derive(Debug)]
+pub struct LoadError {
+    failures: Vec<(String, Box<dyn Error>)>,
+}
+
+impl LoadError {
+    pub fn new(failures: Vec<(String, Box<dyn Error>)>) -> LoadError {
+        LoadError { failures }
+    }
+
+    /// The name of each document that failed, along with its error. A syntax error
+    /// can be downcast into a [ParseError](rivescript_core::errors::ParseError).
+    pub fn failures(&self) -> &[(String, Box<dyn Error>)] {
+        &self.failures
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "load error: {} document(s) failed, so none were loaded", self.failures.len())?;
+        for (name, err) in self.failures.iter() {
+            // Parse errors already start with the filename.
+            if err.is::<rivescript_core::errors::ParseError>() {
+                write!(f, "\n{err}")?;
+            } else {
+                write!(f, "\n{name}: {err}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for LoadError {}
@@ -0,0 +1,85 @@
+// Hashing the RiveScript sources that a brain is built from.
+//
+// The running hash of every document loaded into the bot is stored in binary
+// brain caches, so that a cache can be thrown away when the sources change.
+
+use std::{io, io::BufRead, io::Read};
+
+/// Seed value for a source hash, before any documents have been loaded.
+pub const EMPTY_HASH: u64 = 0xcbf29ce484222325;
+
+/// Add a RiveScript document to a running source hash.
+///
+/// This is a 64-bit FNV-1a hash over the filename and contents of each document,
+/// in the order they were loaded. It is stable across builds and platforms, so a
+/// cache file written on one machine is valid on another.
+pub fn hash_source(hash: u64, filename: &str, source: &str) -> u64 {
+    let hash = hash_bytes(hash, filename.as_bytes());
+    let hash = hash_bytes(hash, &[0]);
+    let hash = hash_bytes(hash, source.as_bytes());
+    hash_bytes(hash, &[0])
+}
+
+/// Add the parser settings that a document was loaded with to a running source hash,
+/// since the same document can parse into a different brain with different settings.
+pub fn hash_settings(hash: u64, strict: bool, includes: bool) -> u64 {
+    hash_bytes(hash, &[strict as u8, includes as u8])
+}
+
+/// Add the files that a document `! include`d, with their contents, to a running source hash.
+pub fn hash_includes(hash: u64, includes: &[(String, String)]) -> u64 {
+    includes.iter().fold(hash, |hash, (filename, contents)| hash_source(hash, filename, contents))
+}
+
+fn hash_bytes(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// A reader that adds a RiveScript document to a running source hash as it is read.
+///
+/// Once the whole document has been read, `finish` gives the same hash that
+/// `hash_source` would for its contents.
+pub struct HashingReader<R> {
+    inner: R,
+    hash: u64,
+}
+
+impl<R: BufRead> HashingReader<R> {
+    pub fn new(inner: R, hash: u64, filename: &str) -> Self {
+        let hash = hash_bytes(hash, filename.as_bytes());
+        Self {
+            inner,
+            hash: hash_bytes(hash, &[0]),
+        }
+    }
+
+    pub fn finish(self) -> u64 {
+        hash_bytes(self.hash, &[0])
+    }
+}
+
+impl<R: BufRead> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hash = hash_bytes(self.hash, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for HashingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The bytes being consumed are still at the front of the inner buffer.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hash = hash_bytes(self.hash, &buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
@@ -1,6 +1,6 @@
 // Topic inheritance helper functions.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use log::{debug, warn};
 
@@ -79,7 +79,7 @@ fn _get_topic_triggers(brain: &ast::AST, topic: &ast::Topic, thats: bool, depth:
     // Their triggers may be "masked" by duplicates in the local topic.
     if !topic.includes.is_empty() {
 
-        for topic_name in topic.includes.keys() {
+        for topic_name in sorted_keys(&topic.includes) {
             debug!("Topic {} includes {:?}", topic.name, topic_name);
             let subtopic = brain.topics.get(topic_name).unwrap();
 
@@ -102,7 +102,7 @@ fn _get_topic_triggers(brain: &ast::AST, topic: &ast::Topic, thats: bool, depth:
     // 3. Process INHERITS (fallbacks).
     // All of these triggers will have lower priority.
     if does_inherit {
-        for topic_name in topic.inherits.keys() {
+        for topic_name in sorted_keys(&topic.inherits) {
             debug!("Topic {} inherits {:?}", topic.name, topic_name);
             let subtopic = brain.topics.get(topic_name).unwrap();
 
@@ -141,15 +141,23 @@ pub fn get_topic_tree(brain: &ast::AST, topic: &ast::Topic, depth: usize) -> Vec
 
     topics.push(topic.name.clone());
 
-    for includes in topic.includes.keys() {
+    for includes in sorted_keys(&topic.includes) {
         let subtopic = brain.topics.get(includes).unwrap();
         topics.extend(get_topic_tree(brain, subtopic, depth+1));
     }
 
-    for inherits in topic.inherits.keys() {
+    for inherits in sorted_keys(&topic.inherits) {
         let subtopic = brain.topics.get(inherits).unwrap();
         topics.extend(get_topic_tree(brain, subtopic, depth+1));
     }
 
     topics
-}
\ No newline at end of file
+}
+
+/// The names of a topic's includes or inherits in sorted order, so that the same brain
+/// always sorts its triggers the same way.
+fn sorted_keys(topics: &HashMap<String, bool>) -> Vec<&String> {
+    let mut names: Vec<&String> = topics.keys().collect();
+    names.sort();
+    names
+}
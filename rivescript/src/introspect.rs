@@ -0,0 +1,96 @@
+// Read-only views over a loaded bot brain, for dashboards and admin tools.
+//
+// The accessor methods themselves live on RiveScript; this module holds the
+// summary types that they return.
+
+use crate::RiveScript;
+
+/// An object macro that the bot knows about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMacro {
+    pub name: String,
+
+    /// The programming language, e.g. "javascript", or "rust" for a subroutine
+    /// registered with `set_subroutine`.
+    pub language: String,
+
+    /// Whether the macro can be called: a subroutine, or an object that a language
+    /// handler has loaded. Objects in a language without a handler aren't callable.
+    pub callable: bool,
+}
+
+/// Summary statistics about a bot's brain.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BrainStats {
+    /// The number of documents that were loaded from files or sources.
+    pub files: usize,
+    pub topics: usize,
+
+    /// The number of triggers, counting each one once (in the topic that defines it)
+    /// rather than in every topic that includes or inherits it.
+    pub triggers: usize,
+    pub replies: usize,
+    pub conditions: usize,
+    pub redirects: usize,
+
+    /// The number of triggers with a `%Previous`.
+    pub previous: usize,
+    pub arrays: usize,
+    pub substitutions: usize,
+    pub person_substitutions: usize,
+    pub globals: usize,
+    pub variables: usize,
+    pub objects: usize,
+}
+
+/// Count up the contents of the bot's brain.
+pub fn stats(rs: &RiveScript) -> BrainStats {
+    let brain = &rs.brain;
+    let mut stats = BrainStats {
+        files: rs.provenance.filenames().len(),
+        topics: brain.topics.len(),
+        arrays: brain.arrays.len(),
+        substitutions: brain.subs.len(),
+        person_substitutions: brain.person.len(),
+        globals: brain.globals.read().expect("RwLock poisoned").len(),
+        variables: brain.vars.read().expect("RwLock poisoned").len(),
+        objects: objects(rs).len(),
+        ..Default::default()
+    };
+
+    for trigger in brain.topics.values().flat_map(|topic| topic.triggers.iter()) {
+        stats.triggers += 1;
+        stats.replies += trigger.reply.len();
+        stats.conditions += trigger.condition.len();
+        if !trigger.redirect.is_empty() {
+            stats.redirects += 1;
+        }
+        if !trigger.previous.is_empty() {
+            stats.previous += 1;
+        }
+    }
+
+    stats
+}
+
+/// All the object macros and subroutines, sorted by name.
+pub fn objects(rs: &RiveScript) -> Vec<ObjectMacro> {
+    let mut objects: Vec<ObjectMacro> = rs.brain.objects
+        .values()
+        .filter(|object| !rs.subroutines.contains_key(&object.name))
+        .map(|object| ObjectMacro {
+            name: object.name.clone(),
+            language: object.language.clone(),
+            callable: rs.object_langs.contains_key(&object.name),
+        })
+        .collect();
+
+    objects.extend(rs.subroutines.keys().map(|name| ObjectMacro {
+        name: name.clone(),
+        language: String::from("rust"),
+        callable: true,
+    }));
+
+    objects.sort_by(|a, b| a.name.cmp(&b.name));
+    objects
+}
@@ -9,20 +9,49 @@ use crate::ast::AST;
 use crate::macros::proxy::Proxy;
 use log::{debug, warn};
 use rivescript_core::macros::{LanguageLoader, SubroutineResult};
-use rivescript_core::{sessions, parser::Parser};
-use std::{collections::HashMap, error::Error, fs, sync::Arc};
+use rivescript_core::{sessions, parser::Document, parser::Parser};
+use std::{collections::HashMap, collections::HashSet, error::Error, fs, io::BufRead, sync::Arc};
+#[cfg(feature = "serde")]
+use std::io::{BufReader, BufWriter};
 use futures::future::BoxFuture;
 use Result::Ok;
 
-use rivescript_core::{DEFAULT_DEPTH, ast};
+use rivescript_core::DEFAULT_DEPTH;
+pub use rivescript_core::ast;
+pub use rivescript_core::errors::{Diagnostic, ErrorCode, ParseError, Severity};
+pub use rivescript_macros::embed_brain;
+pub use crate::errors::LoadError;
+#[cfg(feature = "serde")]
+pub use crate::errors::CacheError;
+pub use crate::diff::{BrainDiff, DefinitionKind, Difference, TriggerChange};
+pub use crate::introspect::{BrainStats, ObjectMacro};
+pub use rivescript_core::sources::{self, DirectoryOptions, SourceProvider};
+#[cfg(feature = "watch")]
+pub use crate::watch::{Change, Watcher};
+// Lets the code generated by `embed_brain!` (which refers to `::rivescript`) work in our own tests.
+#[cfg(test)]
+extern crate self as rivescript;
+
+#[cfg(feature = "serde")]
+mod cache;
+mod diff;
 mod errors;
+mod hashing;
 mod inheritance;
+mod introspect;
+mod lint;
 mod macros;
+mod patterns;
+mod provenance;
 mod reply;
 mod sorting;
+mod substitutions;
 mod tags;
+mod template;
 mod tests;
 mod utils;
+#[cfg(feature = "watch")]
+mod watch;
 
 /// Rust library version.
 pub const VERSION: &str = "0.3.0";
@@ -35,12 +64,21 @@ pub fn register_default_js_handler(rs: &mut RiveScript) {
 }
 
 
+// A document parsed by `_parse_documents`: its name, the parser's result and the new source hash.
+type ParsedDocument = (String, Result<Document, Box<dyn Error>>, u64);
+
+// The documents that couldn't be loaded, each with its error.
+type Failures = Vec<(String, Box<dyn Error>)>;
+
 /// RiveScript represents a single chatbot personality in memory.
 pub struct RiveScript {
     pub debug: bool,
     pub utf8: bool,
     pub depth: usize,
     pub case_sensitive: bool,
+    pub strict: bool,
+    pub includes: bool,
+    pub parallel: bool,
     unicode_punctuation: ::regex::Regex,
 
     pub sessions: Arc<dyn sessions::SessionManager + Send + Sync>,
@@ -50,13 +88,18 @@ pub struct RiveScript {
     sorted_thats: HashMap<String, Vec<ast::Trigger>>,
     sorted_subs: Vec<String>,
     sorted_person: Vec<String>,
+
+    // The parsed templates for the brain's replies, keyed by their text.
+    templates: HashMap<String, template::Template>,
+    patterns: patterns::Patterns,
+    sub_matcher: substitutions::Substitutions,
+    person_matcher: substitutions::Substitutions,
     macro_handlers: HashMap<String, Box<dyn LanguageLoader>>,
     subroutines: HashMap<String, macros::Subroutine>,
     object_langs: HashMap<String, String>,
-
-    // Runtime (in-reply) variables.
-    in_reply_context: bool,
-    current_username: String,
+    warnings: Vec<Diagnostic>,
+    source_hash: u64,
+    provenance: provenance::Provenance,
 }
 
 impl RiveScript {
@@ -72,6 +115,9 @@ impl RiveScript {
             utf8: false,
             depth: DEFAULT_DEPTH,
             case_sensitive: false,
+            strict: false,
+            includes: false,
+            parallel: false,
             unicode_punctuation: ::regex::Regex::new(r"[.,!?;:]").unwrap(),
 
             sessions: Arc::new(sessions::memory::MemorySession::new()),
@@ -81,12 +127,16 @@ impl RiveScript {
             sorted_thats: HashMap::new(),
             sorted_subs: Vec::new(),
             sorted_person: Vec::new(),
+            templates: HashMap::new(),
+            patterns: patterns::Patterns::default(),
+            sub_matcher: substitutions::Substitutions::default(),
+            person_matcher: substitutions::Substitutions::default(),
             macro_handlers: HashMap::new(),
             subroutines: HashMap::new(),
             object_langs: HashMap::new(),
-
-            in_reply_context: false,
-            current_username: String::new(),
+            warnings: Vec::new(),
+            source_hash: hashing::EMPTY_HASH,
+            provenance: provenance::Provenance::default(),
         }
     }
 
@@ -109,6 +159,10 @@ impl RiveScript {
     }
 
     /// Load a directory of RiveScript documents (.rive or .rs extension) from a folder on disk.
+    ///
+    /// The documents are loaded in sorted order by filename, and the list of files that
+    /// were loaded is returned. Subdirectories are not loaded: see `load_directory_with`
+    /// to load recursively or filter the files with glob patterns.
     /// Example
     /// ```rust
     /// # use rivescript::RiveScript;
@@ -117,31 +171,174 @@ impl RiveScript {
     ///     bot.load_directory("../eg/brain").expect("Couldn't load directory!");
     /// # }
     /// ```
-    pub fn load_directory(&mut self, path: &str) -> Result<bool, Box<dyn Error>> {
+    pub fn load_directory(&mut self, path: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.load_directory_with(path, &DirectoryOptions::default())
+    }
+
+    /// Load a directory of RiveScript documents with options for recursion and filtering.
+    ///
+    /// The documents are loaded in sorted order by their path, so that the same directory
+    /// always gives the same bot (when two documents define the same `! sub` or `! var`, the
+    /// one loaded last wins). Returns the list of files that were loaded.
+    pub fn load_directory_with(&mut self, path: &str, options: &DirectoryOptions) -> Result<Vec<String>, Box<dyn Error>> {
         debug!("load_directory called on: {}", path);
+        self.load_from(&mut sources::FileSystem::with_options(path, options.clone()))
+    }
 
-        let paths = fs::read_dir(path)?;
+    /// Load all the RiveScript documents from a source provider, such as an archive bundle.
+    ///
+    /// The documents are loaded in the order the provider lists them, and the list of
+    /// names that were loaded is returned. See the [sources] module for the providers
+    /// that are available.
+    /// Example
+    /// ```rust
+    /// # use rivescript::{RiveScript, sources::Memory};
+    /// # fn main() {
+    ///     let mut documents = Memory::new();
+    ///     documents.insert("begin.rive", "! var name = Aiden");
+    ///     documents.insert("greetings.rive", "+ hello bot\n- Hello, human!");
+    ///
+    ///     let mut bot = RiveScript::new();
+    ///     bot.load_from(&mut documents).expect("Couldn't load the documents!");
+    /// # }
+    /// ```
+    pub fn load_from(&mut self, provider: &mut impl SourceProvider) -> Result<Vec<String>, Box<dyn Error>> {
+        let names = provider.list()?;
 
-        for filename in paths {
-            let filepath = match filename {
-                Ok(res) => res.path(),
-                Err(err) => return Err(Box::new(err)),
-            };
+        // In parallel mode, parse everything up front and then load it in order, stopping
+        // at the first error just like below.
+        if self.parallel {
+            let documents = names.iter().map(|name| (name.clone(), provider.read(name))).collect();
+            for (name, parsed, source_hash) in self._parse_documents(documents) {
+                self._load_parsed(&name, parsed?, source_hash)?;
+            }
+            return Ok(names);
+        }
 
-            match filepath.extension() {
-                Some(ext) => {
-                    if ext.eq_ignore_ascii_case("rive") || ext.eq_ignore_ascii_case(".rs") {
-                        self.load_file(filepath.as_path().display().to_string().as_str())?;
-                    }
-                }
-                None => continue,
+        for name in names.iter() {
+            debug!("load_from loading: {}", name);
+            let contents = provider.read(name)?;
+            self._stream(name, contents)?;
+        }
+
+        Ok(names)
+    }
+
+    /// Load all the RiveScript documents from a source provider, or none of them.
+    ///
+    /// Unlike `load_from`, every document is read and parsed before any of them are added
+    /// to the bot. If any of them fail, the bot is left exactly as it was and a [LoadError]
+    /// is returned with the error for each document that failed, so a bad deploy never
+    /// leaves a half-loaded brain. To load a directory this way, pass it a
+    /// [sources::FileSystem].
+    ///
+    /// Example
+    /// ```rust
+    /// # use rivescript::{LoadError, RiveScript, sources::Memory};
+    /// # fn main() {
+    ///     let mut documents = Memory::new();
+    ///     documents.insert("good.rive", "+ hello bot\n- Hello, human!");
+    ///     documents.insert("bad.rive", "! version = two");
+    ///
+    ///     let mut bot = RiveScript::new();
+    ///     let err = bot.load_all(&mut documents).unwrap_err();
+    ///     let err = err.downcast_ref::<LoadError>().unwrap();
+    ///     assert_eq!(err.failures()[0].0, "bad.rive");
+    ///     assert!(bot.loaded_files().is_empty());
+    /// # }
+    /// ```
+    pub fn load_all(&mut self, provider: &mut impl SourceProvider) -> Result<Vec<String>, Box<dyn Error>> {
+        let names = provider.list()?;
+        let documents = names.iter().map(|name| (name.clone(), provider.read(name))).collect();
+        self._load_all(documents)?;
+        Ok(names)
+    }
+
+    /// Load a list of RiveScript documents from disk, in order, or none of them.
+    ///
+    /// Like `load_all`, every file is read and parsed first, and if any of them fail the bot
+    /// is left as it was and a [LoadError] lists every file that failed.
+    pub fn load_files(&mut self, paths: &[&str]) -> Result<bool, Box<dyn Error>> {
+        debug!("load_files called on: {:?}", paths);
+        let documents = paths.iter().map(|path| (path.to_string(), fs::read_to_string(path))).collect();
+        self._load_all(documents)
+    }
+
+    // Parse every document, and only load them into the brain if they all parse.
+    fn _load_all(&mut self, documents: Vec<(String, std::io::Result<String>)>) -> Result<bool, Box<dyn Error>> {
+        let mut parsed = Vec::new();
+        let mut failures: Failures = Vec::new();
+        for (name, result, source_hash) in self._parse_documents(documents) {
+            match result {
+                Ok(document) => parsed.push((name, document, source_hash)),
+                Err(e) => failures.push((name, e)),
             }
         }
 
-        return Ok(true);
+        if !failures.is_empty() {
+            return Err(Box::new(LoadError::new(failures)));
+        }
+
+        for (name, document, source_hash) in parsed {
+            self._load_parsed(&name, document, source_hash)?;
+        }
+        Ok(true)
+    }
+
+    // Parse a list of documents (on a thread pool, in parallel mode) without loading them.
+    // The results are in the same order, each with the source hash the bot will have
+    // after loading that document.
+    fn _parse_documents(&mut self, documents: Vec<(String, std::io::Result<String>)>) -> Vec<ParsedDocument> {
+        self.parser.strict = self.strict;
+        self.parser.includes = self.includes;
+
+        let parser = &self.parser;
+        let parse = |(name, contents): (String, std::io::Result<String>)| {
+            let parsed: Result<_, Box<dyn Error + Send + Sync>> = match contents {
+                Ok(contents) => parser
+                    .parse_document(&name, &contents)
+                    .map(|document| (contents, document))
+                    .map_err(|e| e.into()),
+                Err(e) => Err(e.into()),
+            };
+            (name, parsed)
+        };
+
+        let results: Vec<_> = if self.parallel {
+            use rayon::prelude::*;
+            documents.into_par_iter().map(parse).collect()
+        } else {
+            documents.into_iter().map(parse).collect()
+        };
+
+        // Each source hash builds on the one before it, and covers the files that the
+        // document included, so they're worked out in order once everything is parsed.
+        let mut source_hash = self.source_hash;
+        results
+            .into_iter()
+            .map(|(name, parsed)| {
+                let parsed = parsed.map(|(contents, document)| {
+                    source_hash = self._hash_document(source_hash, &name, &contents, &document.includes);
+                    document
+                });
+                (name, parsed.map_err(|e| e as Box<dyn Error>), source_hash)
+            })
+            .collect()
+    }
+
+    // Add a document to a running source hash: the parser settings it was loaded with,
+    // its filename and contents, and the files that it included.
+    fn _hash_document(&self, hash: u64, filename: &str, contents: &str, includes: &[(String, String)]) -> u64 {
+        let hash = hashing::hash_settings(hash, self.strict, self.includes);
+        let hash = hashing::hash_source(hash, filename, contents);
+        hashing::hash_includes(hash, includes)
     }
 
     /// Load a RiveScript document by filename on disk.
+    ///
+    /// If the document has a syntax error, the returned error can be downcast into a
+    /// [ParseError] which carries the filename, line and column of the problem.
+    ///
     /// Example
     /// ```rust
     /// # use rivescript::RiveScript;
@@ -175,9 +372,58 @@ impl RiveScript {
         self._stream("stream()", source)
     }
 
+    /// Load a RiveScript document from a reader, such as a socket or a decompression stream.
+    ///
+    /// The document is parsed as it is read, without holding the whole text in memory.
+    /// The name is used in place of a filename for error messages and source locations.
+    /// Example
+    /// ```rust
+    /// # use rivescript::RiveScript;
+    /// # fn main() {
+    ///     let mut bot = RiveScript::new();
+    ///     let file = std::fs::File::open("../eg/brain/eliza.rive").unwrap();
+    ///     bot.load_reader("eliza.rive", std::io::BufReader::new(file))
+    ///         .expect("Couldn't load from the reader!");
+    /// # }
+    /// ```
+    pub fn load_reader(&mut self, name: &str, reader: impl BufRead) -> Result<bool, Box<dyn Error>> {
+        debug!("load_reader called on: {}", name);
+        self.parser.strict = self.strict;
+        self.parser.includes = self.includes;
+        let source_hash = hashing::hash_settings(self.source_hash, self.strict, self.includes);
+        let mut reader = hashing::HashingReader::new(reader, source_hash, name);
+        let document = self.parser.read_document(name, &mut reader)?;
+        let source_hash = hashing::hash_includes(reader.finish(), &document.includes);
+        self._load_parsed(name, document, source_hash)
+    }
+
     // Internal, centralized funnel to load a RiveScript document.
     fn _stream(&mut self, filename: &str, source: String) -> Result<bool, Box<dyn Error>> {
-        let ast = self.parser.parse(filename, source)?;
+        self.parser.strict = self.strict;
+        self.parser.includes = self.includes;
+        let document = self.parser.parse_document(filename, &source)?;
+        let source_hash = self._hash_document(self.source_hash, filename, &source, &document.includes);
+        self._load_parsed(filename, document, source_hash)
+    }
+
+    // Internal funnel to take the output of the parser, along with the new source hash.
+    fn _load_parsed(&mut self, filename: &str, document: Document, source_hash: u64) -> Result<bool, Box<dyn Error>> {
+        for diagnostic in document.warnings.iter() {
+            warn!("{diagnostic}");
+        }
+        self.warnings.extend(document.warnings);
+        self.source_hash = source_hash;
+
+        // Remember what this document defined, so it can be unloaded later.
+        let ast = document.ast;
+        let source = self.provenance.record(filename, &ast, &document.includes);
+        self.provenance.append_triggers(&self.brain, &source, &ast);
+        self.provenance.push(source);
+        self._load_ast(ast)
+    }
+
+    // Internal funnel to merge a parsed (or deserialized) AST into the brain.
+    fn _load_ast(&mut self, ast: AST) -> Result<bool, Box<dyn Error>> {
         let objects = ast.objects.clone();
         self.brain.extend(ast);
 
@@ -187,26 +433,787 @@ impl RiveScript {
         }
 
         // Load all the parsed object macros.
-        for (name, object) in objects {
-            if !self.macro_handlers.contains_key(&object.language) {
-                debug!("Note: object macro '{}' is written in an unhandled language '{}'; skipping", name, object.language);
-                continue;
+        for (_, object) in objects {
+            self._load_object(object);
+        }
+
+        Ok(true)
+    }
+
+    // Give an object macro to its language handler, if we have one.
+    fn _load_object(&mut self, object: ast::Object) {
+        let name = object.name;
+        if !self.macro_handlers.contains_key(&object.language) {
+            debug!("Note: object macro '{}' is written in an unhandled language '{}'; skipping", name, object.language);
+            return;
+        }
+
+        debug!("Loading object macro {} ({})", name, object.language);
+        let handler: &mut Box<dyn LanguageLoader> = self.macro_handlers.get_mut(&object.language).unwrap();
+        match handler.load(&name, object.code) {
+            Ok(_) => {
+                // Store the language handler for this macro's name.
+                self.object_langs.insert(name, object.language);
+            },
+            Err(e) => warn!("Error parsing object macro '{}': {}", name, e),
+        };
+    }
+
+    // Tell an object macro's language handler that it has been removed from the brain.
+    fn _unload_object(&mut self, object: &ast::Object) {
+        debug!("Unloading object macro {} ({})", object.name, object.language);
+        self.object_langs.remove(&object.name);
+        if let Some(handler) = self.macro_handlers.get_mut(&object.language) {
+            handler.unload(&object.name);
+        }
+    }
+
+    /// Unload a RiveScript document, removing everything it added to the brain.
+    ///
+    /// Its triggers are removed, along with the topics and object macros that only it defined
+    /// (language handlers are told about the removed objects). If another loaded document
+    /// defines the same `! var`, `! sub`, array or object macro, that document's value comes
+    /// back into effect. Only the affected topics are re-sorted.
+    ///
+    /// Anything set on the bot in code since the document was loaded (with `set_variable`,
+    /// `add_trigger`, `load_ast` and so on) is left alone, even if it has the same name as
+    /// something the document defined, and anything removed with the `remove_*` methods
+    /// stays removed.
+    ///
+    /// The filename must match the name it was loaded with (for `load_directory`, one of
+    /// the names it returned). Returns false if no document was loaded with that name.
+    /// Documents from `load_json`, `load_ast` and `load_cache` can't be unloaded.
+    pub fn unload_file(&mut self, path: &str) -> Result<bool, Box<dyn Error>> {
+        debug!("unload_file called on: {}", path);
+        let unloaded = self._replace_source(path, None);
+        if unloaded {
+            self.source_hash = hashing::hash_source(self.source_hash, "unload_file()", path);
+        }
+        Ok(unloaded)
+    }
+
+    /// Reload a RiveScript document from disk, replacing what it added to the brain.
+    ///
+    /// The document keeps its place in the load order, so it still overrides (or is
+    /// overridden by) the same documents as before. If it has a syntax error, the error
+    /// is returned and the brain is left unchanged. Only the affected topics are re-sorted.
+    pub fn reload_file(&mut self, path: &str) -> Result<bool, Box<dyn Error>> {
+        debug!("reload_file called on: {}", path);
+        match self._reload_files(&[path]) {
+            Ok(_) => Ok(true),
+            Err(mut failures) => Err(failures.remove(0).1),
+        }
+    }
+
+    // Reload documents from disk, in order, only if they all parse (and wouldn't break
+    // sorting). Otherwise the brain is left unchanged and every failure is returned.
+    fn _reload_files(&mut self, paths: &[&str]) -> Result<(), Failures> {
+        let documents = paths.iter().map(|path| (path.to_string(), fs::read_to_string(path))).collect();
+        let mut parsed = Vec::new();
+        let mut failures: Failures = Vec::new();
+        for (name, result, source_hash) in self._parse_documents(documents) {
+            match result {
+                Ok(document) => parsed.push((name, document, source_hash)),
+                Err(e) => failures.push((name, e)),
             }
+        }
 
-            debug!("Loading object macro {} ({})", name, object.language);
-            let handler: &mut Box<dyn LanguageLoader> = self.macro_handlers.get_mut(&object.language).unwrap();
-            match handler.load(&name, object.code) {
-                Ok(_) => {
-                    // Store the language handler for this macro's name.
-                    self.object_langs.insert(name, object.language);
-                },
-                Err(e) => warn!("Error parsing object macro '{}': {}", name, e),
-            };
+        // A topic may include one that another of the reloaded documents defines.
+        let asts: Vec<&AST> = parsed.iter().map(|(_, document, _)| &document.ast).collect();
+        for (name, document, _) in parsed.iter() {
+            if let Err(e) = self._check_topic_references(name, &document.ast, &asts) {
+                failures.push((name.clone(), Box::new(e)));
+            }
+        }
+        if !failures.is_empty() {
+            failures.sort_by_key(|(name, _)| paths.iter().position(|path| path == name));
+            return Err(failures);
+        }
+
+        for (name, mut document, source_hash) in parsed {
+            for diagnostic in document.warnings.iter() {
+                warn!("{diagnostic}");
+            }
+            self.warnings.retain(|d| d.filename != name);
+            self.warnings.append(&mut document.warnings);
+            self.source_hash = source_hash;
+            self._replace_source(&name, Some(document));
+        }
+        Ok(())
+    }
+
+    // Sorting the triggers would fail if a topic includes or inherits a topic that
+    // doesn't exist, so check for that before a reloaded document replaces the old one.
+    fn _check_topic_references(&self, path: &str, ast: &AST, reloaded: &[&AST]) -> Result<(), ParseError> {
+        let mut diagnostics = Vec::new();
+        for topic in ast.topics.values() {
+            for name in topic.includes.keys().chain(topic.inherits.keys()) {
+                if reloaded.iter().any(|ast| ast.topics.contains_key(name)) || self.brain.topics.contains_key(name) {
+                    continue;
+                }
+
+                let message = format!("The topic '{}' includes or inherits '{name}', which does not exist", topic.name);
+                let (filename, line) = if topic.location.is_known() {
+                    (topic.location.filename.as_str(), topic.location.start)
+                } else {
+                    (path, 1)
+                };
+                diagnostics.push(Diagnostic::error(ErrorCode::UnknownTopic, &message).at(filename, line, 1, ""));
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(ParseError::from_diagnostics(diagnostics))
         }
+    }
+
+    /// Get the names of the RiveScript documents that have been loaded, in load order.
+    pub fn loaded_files(&self) -> Vec<String> {
+        self.provenance.filenames()
+    }
+
+    // Remove a document from the brain, putting a new version of it in its place.
+    fn _replace_source(&mut self, filename: &str, replacement: Option<Document>) -> bool {
+        let (index, removed) = match self.provenance.remove(filename) {
+            Some(removed) => removed,
+            None if replacement.is_some() => (self.provenance.len(), Vec::new()),
+            None => return false,
+        };
+        let mut topics = self.provenance.remove_triggers(&mut self.brain, &removed);
+
+        // Merge in the new version's triggers. Everything else it defines is restored below.
+        let replaced = replacement.is_some();
+        if let Some(document) = replacement {
+            let ast = document.ast;
+            let source = self.provenance.record(filename, &ast, &document.includes);
+            self.provenance.insert(index, source);
+            topics.extend(self.provenance.insert_triggers(&mut self.brain, index, ast.topics));
+        }
+
+        let mut changed: Vec<&provenance::Source> = removed.iter().collect();
+        if replaced {
+            changed.push(self.provenance.get(index));
+        }
+        let restored = self.provenance.restore(&mut self.brain, &changed);
+
+        if let Ok(depth) = self.brain.get_global("depth").parse() {
+            self.depth = depth;
+        }
+        for object in restored.removed_objects.iter() {
+            self._unload_object(object);
+        }
+        for object in restored.objects {
+            self._load_object(object);
+        }
+
+        topics.extend(restored.topics);
+        for name in restored.removed_topics.iter() {
+            topics.remove(name);
+        }
+        self._resort(&topics, &restored.removed_topics);
+        true
+    }
+
+    // Re-sort only the topics affected by a change to the brain, along with the topics
+    // that include or inherit them. Does nothing if the triggers were never sorted.
+    fn _resort(&mut self, changed: &HashSet<String>, removed: &[String]) {
+        if self.sorted_topics.is_empty() {
+            return;
+        }
+
+        for name in removed {
+            self.sorted_topics.remove(name);
+            self.sorted_thats.remove(name);
+        }
+
+        for topic in self.brain.topics.values() {
+            let tree = inheritance::get_topic_tree(&self.brain, topic, 0);
+            if tree.iter().any(|name| changed.contains(name)) {
+                debug!("Re-sorting topic {}", topic.name);
+                let (triggers, thats) = sorting::sort_topic(&self.brain, topic);
+                self.sorted_topics.insert(topic.name.clone(), triggers);
+                self.sorted_thats.insert(topic.name.clone(), thats);
+            }
+        }
+
+        self.sorted_subs = sorting::sort_list(self.brain.subs.clone());
+        self.sorted_person = sorting::sort_list(self.brain.person.clone());
+        self._compile();
+    }
+
+    // Parse the replies and compile the trigger patterns and substitutions of the sorted
+    // brain ahead of time, so that it isn't done for every message.
+    fn _compile(&mut self) {
+        self.templates = template::compile(&self.brain);
+        self.sub_matcher = substitutions::Substitutions::new(&self.brain.subs, &self.sorted_subs);
+        self.person_matcher = substitutions::Substitutions::new(&self.brain.person, &self.sorted_person);
+        let patterns = std::mem::take(&mut self.patterns);
+        self.patterns = patterns.refresh(self);
+    }
+
+    /// Convert the loaded brain back into RiveScript source code.
+    ///
+    /// This is useful if you've built (or modified) the bot's brain in code and want to
+    /// save it back to disk. Parsing the result again gives an equivalent brain.
+    pub fn deparse(&self) -> String {
+        rivescript_core::deparse::deparse(&self.brain)
+    }
+
+    /// Write the loaded brain to a RiveScript document on disk.
+    /// Example
+    /// ```rust
+    /// # use rivescript::RiveScript;
+    /// # fn main() {
+    ///     let mut bot = RiveScript::new();
+    ///     bot.load_directory("../eg/brain").expect("Couldn't load directory!");
+    ///     bot.write(std::env::temp_dir().join("brain.rive").to_str().unwrap())
+    ///         .expect("Couldn't write file!");
+    /// # }
+    /// ```
+    pub fn write(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        debug!("write called on: {}", path);
+        fs::write(path, self.deparse())?;
+        Ok(true)
+    }
+
+    /// Export the loaded brain as a JSON document.
+    ///
+    /// The JSON mirrors the structure of the [ast::AST]: topics with their triggers,
+    /// bot variables, substitutions, arrays and object macros. It can be loaded back
+    /// in with `load_json`.
+    ///
+    /// This and `load_json` need the `serde` feature, which is enabled by default.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(&self.brain)?)
+    }
+
+    /// Load a brain from a JSON document (as exported by `to_json`).
+    ///
+    /// The brain is merged into any that's already loaded, the same as if it were
+    /// streamed in as RiveScript source code.
+    /// Example
+    /// ```rust
+    /// # use rivescript::RiveScript;
+    /// # fn main() {
+    ///     let mut bot = RiveScript::new();
+    ///     bot.load_directory("../eg/brain").expect("Couldn't load directory!");
+    ///     let json = bot.to_json().expect("Couldn't export JSON!");
+    ///
+    ///     let mut copy = RiveScript::new();
+    ///     copy.load_json(&json).expect("Couldn't load JSON!");
+    /// # }
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn load_json(&mut self, json: &str) -> Result<bool, Box<dyn Error>> {
+        let ast: AST = serde_json::from_str(json)?;
+        self.source_hash = hashing::hash_source(self.source_hash, "load_json()", json);
+        self.provenance.disown_ast(&ast);
+        self._load_ast(ast)
+    }
 
+    /// Load an already-parsed AST into the bot, such as one embedded at compile time.
+    ///
+    /// The `embed_brain!` macro parses RiveScript documents while your program is being
+    /// built (failing the build on syntax errors) and gives their AST, so that the bot
+    /// can start up with no filesystem access or parsing at all.
+    /// Example
+    /// ```rust
+    /// # use rivescript::RiveScript;
+    /// # fn main() {
+    ///     let mut bot = RiveScript::new();
+    ///     bot.load_ast(rivescript::embed_brain!("../eg/brain")).expect("Couldn't load the brain!");
+    ///     bot.sort_triggers();
+    /// # }
+    /// ```
+    pub fn load_ast(&mut self, ast: AST) -> Result<bool, Box<dyn Error>> {
+        self.provenance.disown_ast(&ast);
+        self._load_ast(ast)
+    }
+
+    /// Save the parsed and sorted brain to a binary cache file.
+    ///
+    /// Loading the cache file with `load_cache` is much faster than parsing and sorting the
+    /// RiveScript sources again. The cache records a hash of the sources that the brain was
+    /// built from (see `source_hash`) so that it can be invalidated when they change.
+    ///
+    /// Call this after `sort_triggers`. The brain cache needs the `serde` feature, which
+    /// is enabled by default.
+    #[cfg(feature = "serde")]
+    pub fn save_cache(&self, path: &str) -> Result<bool, Box<dyn Error>> {
+        debug!("save_cache called on: {}", path);
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        cache::write(&mut writer, self.source_hash, &self.provenance.included(), &cache::CachedBrainRef {
+            brain: &self.brain,
+            topics: &self.sorted_topics,
+            thats: &self.sorted_thats,
+            subs: &self.sorted_subs,
+            person: &self.sorted_person,
+        })?;
+        Ok(true)
+    }
+
+    /// Load the brain from a binary cache file written by `save_cache`.
+    ///
+    /// This replaces any brain that was already loaded. The cache holds the sorted buffers too,
+    /// so the bot is ready to reply without needing to call `sort_triggers`. Object macros from
+    /// the cache are given to their language handlers, so set those up beforehand.
+    ///
+    /// If the file was not written by a compatible version of RiveScript, the returned error
+    /// can be downcast into a [CacheError].
+    #[cfg(feature = "serde")]
+    pub fn load_cache(&mut self, path: &str) -> Result<bool, Box<dyn Error>> {
+        debug!("load_cache called on: {}", path);
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        let (source_hash, _, cached) = cache::read(&mut reader)?;
+
+        self.brain = AST::new();
+        self.provenance.clear();
+        self._load_ast(cached.brain)?;
+        self.sorted_topics = cached.topics;
+        self.sorted_thats = cached.thats;
+        self.sorted_subs = cached.subs;
+        self.sorted_person = cached.person;
+        self._compile();
+        self.source_hash = source_hash;
         Ok(true)
     }
 
+    /// Load the brain from a binary cache file, but only if it is up to date.
+    ///
+    /// The `sources` are the RiveScript files and directories that the brain is built from,
+    /// in the order you would load them, and the `options` are the ones you would load the
+    /// directories with (see `load_directory_with`). They are read (but not parsed) to check that they,
+    /// and the files that they `! include`d when the cache was saved, still match the hash
+    /// stored in the cache for the bot's `strict` and `includes` settings. Returns false,
+    /// without loading anything, if the cache file is missing, stale or unreadable, or if
+    /// any of those files can't be read.
+    /// Example
+    /// ```rust
+    /// # use rivescript::{DirectoryOptions, RiveScript};
+    /// # fn main() {
+    ///     let cache = std::env::temp_dir().join("brain-example.bin");
+    ///     let cache = cache.to_str().unwrap();
+    ///
+    ///     let mut bot = RiveScript::new();
+    ///     let options = DirectoryOptions::default();
+    ///     if !bot.load_cache_if_fresh(cache, &["../eg/brain"], &options).unwrap() {
+    ///         bot.load_directory("../eg/brain").expect("Couldn't load directory!");
+    ///         bot.sort_triggers();
+    ///         bot.save_cache(cache).expect("Couldn't save cache!");
+    ///     }
+    /// # }
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn load_cache_if_fresh(&mut self, path: &str, sources: &[&str], options: &DirectoryOptions) -> Result<bool, Box<dyn Error>> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Ok(false),
+        };
+        let (hash, included) = match cache::read_header(&mut BufReader::new(file)) {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("Couldn't read brain cache {}: {}", path, e);
+                return Ok(false);
+            },
+        };
+
+        // Check the files that each document included when the cache was saved. If a
+        // document includes different files now, the document itself has changed.
+        let expect = self._hash_sources(sources, options, |filename, _| {
+            let files = included.get(filename).map(Vec::as_slice).unwrap_or_default();
+            files.iter().map(|file| Ok((file.clone(), fs::read_to_string(file)?))).collect()
+        });
+        match expect {
+            Ok(expect) if expect == hash => {},
+            Ok(_) => {
+                debug!("Brain cache {} is stale", path);
+                return Ok(false);
+            },
+            Err(e) => {
+                debug!("Brain cache {} is stale: {}", path, e);
+                return Ok(false);
+            },
+        };
+
+        match self.load_cache(path) {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                warn!("Couldn't load brain cache {}: {}", path, e);
+                Ok(false)
+            },
+        }
+    }
+
+    /// Get a hash of all the RiveScript sources that have been loaded so far.
+    ///
+    /// The hash covers each document's filename and contents, the files that it
+    /// `! include`d, and the `strict` and `includes` settings it was loaded with. It is
+    /// stored in binary cache files to detect when they are out of date.
+    pub fn source_hash(&self) -> u64 {
+        self.source_hash
+    }
+
+    /// Compute the `source_hash` that loading these files and directories, in order, into
+    /// a new bot with this bot's `strict` and `includes` settings would produce. The
+    /// directories are listed with these options, as with `load_directory_with`.
+    ///
+    /// The files are only read, not parsed, unless `includes` is enabled: then each one
+    /// is parsed to find the files that it includes.
+    pub fn hash_sources(&self, sources: &[&str], options: &DirectoryOptions) -> Result<u64, Box<dyn Error>> {
+        let parser = Parser { strict: false, includes: true };
+        self._hash_sources(sources, options, |filename, contents| {
+            if !self.includes {
+                return Ok(Vec::new());
+            }
+            Ok(parser.parse_document(filename, contents)?.includes)
+        })
+    }
+
+    // Compute a source hash for loading these files and directories, given a way to find
+    // the files (with their contents) that each document includes.
+    fn _hash_sources<F>(&self, sources: &[&str], options: &DirectoryOptions, mut includes: F) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(&str, &str) -> Result<Vec<(String, String)>, Box<dyn Error>>,
+    {
+        let mut hash = hashing::EMPTY_HASH;
+        for source in sources {
+            for filename in sources::FileSystem::with_options(source, options.clone()).list()? {
+                let contents = fs::read_to_string(&filename)?;
+                let included = includes(&filename, &contents)?;
+                hash = self._hash_document(hash, &filename, &contents, &included);
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Check the loaded brain for problems that would silently misbehave at runtime.
+    ///
+    /// This is a static analysis pass over the brain, so call it after `sort_triggers`. It reports:
+    ///
+    /// * Triggers that can never match, because a higher-priority wildcard trigger in the same
+    ///   topic always matches first.
+    /// * `@ redirects` and `{@ redirects}` that no trigger can match.
+    /// * `{topic=name}` tags pointing to topics that don't exist.
+    /// * `@array` and `(@array)` references to undefined arrays.
+    /// * `<bot name>` variables that are never defined with `! var` or a `<bot name=value>` tag.
+    /// * `<call>` tags for object macros with no subroutine or loaded language handler.
+    /// * Trigger patterns that can't be compiled into a regular expression (and so never match).
+    ///
+    /// Bot variables set only by Rust subroutines, and redirects with tags in them, can't be
+    /// known ahead of time and aren't reported.
+    pub fn lint(&self) -> Vec<Diagnostic> {
+        lint::lint(self)
+    }
+
+    /// Get the warnings found while parsing all of the RiveScript sources loaded so far.
+    ///
+    /// These are problems that the parser was able to skip over, such as unknown commands or
+    /// malformed conditions. With `strict` mode enabled, these would have been errors instead.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Get the names of all the topics in the brain, in sorted order.
+    pub fn topics(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.brain.topics.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    /// Look at a topic: its own triggers (in the order they were defined) and the topics
+    /// it includes and inherits.
+    pub fn topic(&self, name: &str) -> Option<&ast::Topic> {
+        self.brain.topics.get(name)
+    }
+
+    /// Get the triggers that a user in this topic can match, in the order they'll be tried.
+    /// Triggers with a `%Previous` are tried before these, and are in `sorted_previous`.
+    ///
+    /// This includes the triggers from any topics that it includes or inherits. When a topic
+    /// inherits others, the triggers start with an `{inherits=N}` tag that ranks them. This
+    /// is empty until `sort_triggers` has been called.
+    pub fn sorted_triggers(&self, topic: &str) -> &[ast::Trigger] {
+        self.sorted_topics.get(topic).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Like `sorted_triggers`, but for the triggers that have a `%Previous`.
+    pub fn sorted_previous(&self, topic: &str) -> &[ast::Trigger] {
+        self.sorted_thats.get(topic).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Get the arrays defined with `! array`.
+    pub fn arrays(&self) -> &HashMap<String, Vec<String>> {
+        &self.brain.arrays
+    }
+
+    /// Get the substitutions defined with `! sub`.
+    pub fn substitutions(&self) -> &HashMap<String, String> {
+        &self.brain.subs
+    }
+
+    /// Get the person substitutions defined with `! person`.
+    pub fn person_substitutions(&self) -> &HashMap<String, String> {
+        &self.brain.person
+    }
+
+    /// Get a copy of the global variables.
+    pub fn globals(&self) -> HashMap<String, String> {
+        self.brain.globals.read().expect("RwLock poisoned").clone()
+    }
+
+    /// Get a copy of the bot variables.
+    pub fn variables(&self) -> HashMap<String, String> {
+        self.brain.vars.read().expect("RwLock poisoned").clone()
+    }
+
+    /// Get the object macros from the loaded documents and the subroutines set in code,
+    /// sorted by name.
+    pub fn objects(&self) -> Vec<ObjectMacro> {
+        introspect::objects(self)
+    }
+
+    /// Count up what's in the brain: topics, triggers, replies and so on.
+    ///
+    /// Example
+    /// ```rust
+    /// # use rivescript::RiveScript;
+    /// # fn main() {
+    ///     let mut bot = RiveScript::new();
+    ///     bot.load_file("../eg/brain/eliza.rive").unwrap();
+    ///     let stats = bot.stats();
+    ///     println!("{} triggers with {} replies", stats.triggers, stats.replies);
+    /// # }
+    /// ```
+    pub fn stats(&self) -> BrainStats {
+        introspect::stats(self)
+    }
+
+    /// Compare this bot's brain with another's, e.g. before and after editing its documents.
+    ///
+    /// This reports the added, removed and changed topics, triggers and definitions. If
+    /// both bots have sorted their triggers, it also reports the triggers that will be tried
+    /// in a different order, which can change which reply wins even when no trigger did.
+    ///
+    /// Example
+    /// ```rust
+    /// # use rivescript::RiveScript;
+    /// # fn main() {
+    ///     let mut old = RiveScript::new();
+    ///     old.stream("+ hello bot\n- Hello, human!".to_string()).unwrap();
+    ///     old.sort_triggers();
+    ///
+    ///     let mut new = RiveScript::new();
+    ///     new.stream("+ hello bot\n- Hello, human!\n- Hi there!".to_string()).unwrap();
+    ///     new.sort_triggers();
+    ///
+    ///     let diff = old.diff(&new);
+    ///     assert_eq!(diff.to_string(), "~ [random] + hello bot: added reply \"Hi there!\"\n");
+    /// # }
+    /// ```
+    pub fn diff(&self, other: &RiveScript) -> BrainDiff {
+        let mut diff = BrainDiff::between(&self.brain, &other.brain);
+
+        let mut topics: Vec<&String> = self.sorted_topics.keys().filter(|name| other.sorted_topics.contains_key(*name)).collect();
+        topics.sort();
+        for topic in topics {
+            diff.moved(topic, &self.sorted_thats[topic], &other.sorted_thats[topic]);
+            diff.moved(topic, &self.sorted_topics[topic], &other.sorted_topics[topic]);
+        }
+        diff
+    }
+
+    /// Add a topic to the brain, or replace the topic with the same name.
+    ///
+    /// The topic's triggers, includes and inherits replace any that a topic of that name
+    /// already had. Call `sort_triggers` after changing the brain for it to take effect.
+    ///
+    /// Example
+    /// ```rust
+    /// # use rivescript::{RiveScript, ast::{Topic, Trigger}};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    ///     let mut bot = RiveScript::new();
+    ///     let mut topic = Topic::new("sales");
+    ///     topic.set_includes(String::from("random"));
+    ///
+    ///     let mut trigger = Trigger::new("how much is the *");
+    ///     trigger.reply.push(String::from("Let me check the price of the <star>."));
+    ///     topic.add_trigger(trigger);
+    ///     bot.set_topic(topic);
+    ///
+    ///     let mut trigger = Trigger::new("hello");
+    ///     trigger.reply.push(String::from("Hi! Are you shopping today?"));
+    ///     bot.add_trigger("random", trigger);
+    ///
+    ///     bot.sort_triggers();
+    ///     assert_eq!(bot.reply("user", "hello").await.unwrap(), "Hi! Are you shopping today?");
+    /// # }
+    /// ```
+    pub fn set_topic(&mut self, topic: ast::Topic) {
+        self.provenance.disown_topic(&topic.name);
+        for name in topic.includes.keys().chain(topic.inherits.keys()) {
+            self.brain.init_topic(name);
+        }
+        self.brain.topics.insert(topic.name.clone(), topic);
+    }
+
+    /// Add a trigger to a topic, creating the topic if it doesn't exist.
+    ///
+    /// If the topic already has a trigger with the same pattern and `%Previous`, it is
+    /// replaced. Call `sort_triggers` after changing the brain for it to take effect.
+    pub fn add_trigger(&mut self, topic: &str, trigger: ast::Trigger) {
+        self.brain.init_topic(&topic.to_string());
+        let topic = self.brain.topics.get_mut(topic).unwrap();
+        match topic.triggers.iter().position(|t| t.trigger == trigger.trigger && t.previous == trigger.previous) {
+            Some(index) => {
+                topic.triggers[index] = trigger;
+                self.provenance.disown_trigger(topic, index);
+            },
+            None => topic.add_trigger(trigger),
+        }
+    }
+
+    /// Set a global variable, as with `! global` in RiveScript.
+    ///
+    /// Setting the `depth` global also changes the recursion limit, as it does in a document.
+    pub fn set_global(&mut self, name: &str, value: &str) {
+        self.provenance.disown(DefinitionKind::Global, name);
+        self.brain.set_global(name, value);
+        if name == "depth" && let Ok(depth) = value.parse() {
+            self.depth = depth;
+        }
+    }
+
+    /// Set a bot variable, as with `! var` in RiveScript.
+    pub fn set_variable(&mut self, name: &str, value: &str) {
+        self.provenance.disown(DefinitionKind::Var, name);
+        self.brain.set_bot_var(name, value);
+    }
+
+    /// Set a substitution for user messages, as with `! sub` in RiveScript.
+    /// Call `sort_triggers` after changing the brain for it to take effect.
+    pub fn set_substitution(&mut self, pattern: &str, replacement: &str) {
+        self.provenance.disown(DefinitionKind::Sub, pattern);
+        self.brain.subs.insert(pattern.to_string(), replacement.to_string());
+    }
+
+    /// Set a person substitution, as with `! person` in RiveScript.
+    /// Call `sort_triggers` after changing the brain for it to take effect.
+    pub fn set_person(&mut self, pattern: &str, replacement: &str) {
+        self.provenance.disown(DefinitionKind::Person, pattern);
+        self.brain.person.insert(pattern.to_string(), replacement.to_string());
+    }
+
+    /// Set an array, as with `! array` in RiveScript. Unlike a document, the items
+    /// aren't split on spaces or pipes, so they may contain either.
+    pub fn set_array(&mut self, name: &str, items: &[&str]) {
+        self.provenance.disown(DefinitionKind::Array, name);
+        self.brain.arrays.insert(name.to_string(), items.iter().map(|item| item.to_string()).collect());
+    }
+
+    /// Remove the triggers with this pattern (with or without a `%Previous`) from a topic.
+    ///
+    /// Unlike adding to the brain, removing from it takes effect right away: if the triggers
+    /// were already sorted, the affected topics are re-sorted. Returns false if the topic had
+    /// no such trigger.
+    pub fn remove_trigger(&mut self, topic: &str, pattern: &str) -> bool {
+        let Some(t) = self.brain.topics.get_mut(topic) else {
+            return false;
+        };
+        if !self.provenance.retain_triggers(t, |trigger, _| trigger.trigger != pattern) {
+            return false;
+        }
+
+        self._resort(&HashSet::from([topic.to_string()]), &[]);
+        true
+    }
+
+    /// Remove a topic and all of its triggers, taking effect right away.
+    ///
+    /// Other topics that include or inherit it stop doing so, and users who were in the
+    /// topic are moved back to the default topic on their next message. The default topic
+    /// itself is never removed, but it is emptied. Returns false if there was no such topic.
+    pub fn remove_topic(&mut self, name: &str) -> bool {
+        if !self.brain.topics.contains_key(name) {
+            return false;
+        }
+        self.provenance.remove_topic(name);
+
+        let mut changed = HashSet::new();
+        for topic in self.brain.topics.values_mut() {
+            let included = topic.includes.remove(name).is_some();
+            let inherited = topic.inherits.remove(name).is_some();
+            if included || inherited {
+                changed.insert(topic.name.clone());
+            }
+        }
+
+        if name == rivescript_core::DEFAULT_TOPIC {
+            self.brain.topics.insert(name.to_string(), ast::Topic::new(name));
+            changed.insert(name.to_string());
+            self._resort(&changed, &[]);
+        } else {
+            self.brain.topics.remove(name);
+            changed.remove(name);
+            self._resort(&changed, &[name.to_string()]);
+        }
+        true
+    }
+
+    /// Remove a substitution, taking effect right away. Returns false if there was no such substitution.
+    pub fn remove_substitution(&mut self, pattern: &str) -> bool {
+        self.provenance.disown(DefinitionKind::Sub, pattern);
+        let removed = self.brain.subs.remove(pattern).is_some();
+        if removed {
+            self._resort(&HashSet::new(), &[]);
+        }
+        removed
+    }
+
+    /// Remove a person substitution, taking effect right away. Returns false if there was no such substitution.
+    pub fn remove_person(&mut self, pattern: &str) -> bool {
+        self.provenance.disown(DefinitionKind::Person, pattern);
+        let removed = self.brain.person.remove(pattern).is_some();
+        if removed {
+            self._resort(&HashSet::new(), &[]);
+        }
+        removed
+    }
+
+    /// Remove an array. Returns false if there was no such array.
+    pub fn remove_array(&mut self, name: &str) -> bool {
+        self.provenance.disown(DefinitionKind::Array, name);
+        let removed = self.brain.arrays.remove(name).is_some();
+        if removed {
+            self._resort(&HashSet::new(), &[]);
+        }
+        removed
+    }
+
+    /// Remove a bot variable. Returns false if there was no such variable.
+    pub fn remove_variable(&mut self, name: &str) -> bool {
+        self.provenance.disown(DefinitionKind::Var, name);
+        self.brain.vars.write().expect("RwLock poisoned").remove(name).is_some()
+    }
+
+    /// Remove an object macro, whether it was defined in a document (in which case its
+    /// language handler is told to unload it) or with `set_subroutine`. Returns false if
+    /// there was no such object macro.
+    pub fn remove_object(&mut self, name: &str) -> bool {
+        self.provenance.disown(DefinitionKind::Object, name);
+        let subroutine = self.subroutines.remove(name).is_some();
+        match self.brain.objects.remove(name) {
+            Some(object) => {
+                self._unload_object(&object);
+                true
+            },
+            None => subroutine,
+        }
+    }
+
     /// Sort the internal data structures for optimal matching.
     pub fn sort_triggers(&mut self) {
         warn!("sort_triggers called, final AST is: {:#?}", self.brain);
@@ -219,6 +1226,7 @@ impl RiveScript {
             },
             Err(_) => (),
         }
+        self._compile();
 
         // DEBUG
         // debug!("sorted_topics: {:#?}", self.sorted_topics);
@@ -228,7 +1236,10 @@ impl RiveScript {
     }
 
     /// Get a reply from the chatbot.
-    pub async fn reply(&mut self, username: &str, message: &str) -> Result<String, String> {
+    ///
+    /// This only needs a shared reference, so one bot can reply to many users at the same
+    /// time: share it between tasks in an `Arc` once its brain is loaded and sorted.
+    pub async fn reply(&self, username: &str, message: &str) -> Result<String, String> {
         // let msg = reply::Message{
         //     username: String::from("username"),
         // }
@@ -257,12 +1268,12 @@ impl RiveScript {
     /// Get the current user's username.
     ///
     /// This is only valid from within a reply context, e.g. from a Rust object macro subroutine.
+    /// When the bot is replying to several users at once, this is the user of the reply that
+    /// is calling it.
     pub fn current_username(&self) -> Result<String, String> {
-        if !self.in_reply_context {
-            Err("current_username is only valid during a reply context".to_string())
-        } else {
-            Ok(self.current_username.to_string())
-        }
+        reply::CURRENT_USERNAME
+            .try_with(|username| username.clone())
+            .map_err(|_| "current_username is only valid during a reply context".to_string())
     }
 
     /// Set a user variable for a user.
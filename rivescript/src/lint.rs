@@ -0,0 +1,295 @@
+// Static analysis ("linting") of a loaded and sorted bot brain.
+//
+// The parser can only see one line of RiveScript at a time. The linter looks at
+// the brain as a whole for things that will silently misbehave at runtime:
+// triggers that can never be matched, and references to topics, arrays, bot
+// variables, object macros or triggers that don't exist.
+
+use std::collections::{HashMap, HashSet};
+
+use ::regex::Regex;
+use rivescript_core::errors::{Diagnostic, ErrorCode};
+use rivescript_core::regex;
+
+use crate::{RiveScript, ast, reply};
+
+/// Run all of the lint checks over the bot's brain.
+pub fn lint(rs: &RiveScript) -> Vec<Diagnostic> {
+    let mut linter = Linter {
+        rs,
+        regexps: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+
+    // Bot variables may be defined with `! var` or set with a <bot name=value> tag.
+    let mut bot_vars: HashSet<String> = rs.brain.vars.read().expect("RwLock poisoned").keys().cloned().collect();
+    for (_, trigger) in linter.triggers() {
+        for text in texts(trigger) {
+            for (_, [name]) in regex::BOT_TAG.captures_iter(text).map(|c| c.extract()) {
+                if let Some((name, _)) = name.split_once('=') {
+                    bot_vars.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    for (topic, trigger) in linter.triggers() {
+        linter.check_patterns(trigger);
+        linter.check_references(trigger, &bot_vars);
+        linter.check_redirects(topic, trigger);
+    }
+    linter.check_shadowed();
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by(|a, b| (&a.filename, a.line, a.code.as_str()).cmp(&(&b.filename, b.line, b.code.as_str())));
+    diagnostics.dedup();
+    diagnostics
+}
+
+struct Linter<'a> {
+    rs: &'a RiveScript,
+
+    // Compiled trigger patterns, or None if they failed to compile.
+    regexps: HashMap<String, Option<Regex>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    /// All of the triggers in the brain along with their topic name, in a stable order.
+    fn triggers(&self) -> Vec<(&'a str, &'a ast::Trigger)> {
+        let brain = &self.rs.brain;
+        let mut names: Vec<&String> = brain.topics.keys().collect();
+        names.sort();
+
+        let mut result = Vec::new();
+        for name in names {
+            for trigger in brain.topics[name].triggers.iter() {
+                result.push((name.as_str(), trigger));
+            }
+        }
+        result
+    }
+
+    fn report(&mut self, diagnostic: Diagnostic, trigger: &ast::Trigger) {
+        let location = &trigger.location;
+        self.diagnostics.push(diagnostic.at(&location.filename, location.start, 1, &trigger.trigger));
+    }
+
+    /// Compile a trigger pattern the way the reply engine would.
+    ///
+    /// Tags that depend on the user (<get>, <input> and <reply>) may be anything
+    /// at runtime, so they are compiled as wildcards.
+    fn compile(&self, pattern: &str) -> Result<Regex, ::regex::Error> {
+        let prepared = reply::trigger_pattern(self.rs, pattern);
+        let prepared = regex::TRIGGER_USER_TAGS.replace_all(&prepared, "(?:.*?)").to_string();
+        reply::compile_trigger_pattern(self.rs, prepared)
+    }
+
+    /// Compile a trigger pattern, caching the result.
+    fn regexp(&mut self, pattern: &str) -> Option<&Regex> {
+        if !self.regexps.contains_key(pattern) {
+            let compiled = self.compile(pattern).ok();
+            self.regexps.insert(pattern.to_string(), compiled);
+        }
+        self.regexps.get(pattern).unwrap().as_ref()
+    }
+
+    /// Triggers and %Previous patterns that can't compile into a regexp would never match.
+    fn check_patterns(&mut self, trigger: &ast::Trigger) {
+        for pattern in [&trigger.trigger, &trigger.previous] {
+            if pattern.is_empty() {
+                continue;
+            }
+
+            if let Err(e) = self.compile(pattern) {
+                let message = format!("The pattern '{pattern}' can't be compiled into a regular expression: {e}");
+                self.report(Diagnostic::error(ErrorCode::InvalidPattern, &message), trigger);
+            }
+        }
+    }
+
+    /// Check the references to arrays, bot variables, topics and object macros.
+    fn check_references(&mut self, trigger: &ast::Trigger, bot_vars: &HashSet<String>) {
+        let brain = &self.rs.brain;
+        let mut found: Vec<Diagnostic> = Vec::new();
+
+        // @arrays in the trigger and %Previous patterns.
+        for pattern in [&trigger.trigger, &trigger.previous] {
+            for (_, [name]) in regex::TRIGGER_ARRAY.captures_iter(pattern).map(|c| c.extract()) {
+                if !brain.arrays.contains_key(name) {
+                    found.push(Diagnostic::warning(
+                        ErrorCode::UndefinedArray,
+                        &format!("The array '@{name}' is not defined"),
+                    ));
+                }
+            }
+        }
+
+        for text in patterns(trigger).chain(texts(trigger)) {
+            // <bot name> variables.
+            for (_, [name]) in regex::BOT_TAG.captures_iter(text).map(|c| c.extract()) {
+                if !name.contains('=') && !name.contains('<') && !bot_vars.contains(name) {
+                    found.push(Diagnostic::warning(
+                        ErrorCode::UndefinedBotVar,
+                        &format!("The bot variable '{name}' is never defined"),
+                    ));
+                }
+            }
+        }
+
+        for text in texts(trigger) {
+            // (@arrays) in replies.
+            for (_, [name]) in regex::REPLY_ARRAY.captures_iter(text).map(|c| c.extract()) {
+                if !brain.arrays.contains_key(name) {
+                    found.push(Diagnostic::warning(
+                        ErrorCode::UndefinedArray,
+                        &format!("The array '(@{name})' is not defined"),
+                    ));
+                }
+            }
+
+            // {topic=name} tags.
+            for (_, [name]) in regex::TOPIC_TAG.captures_iter(text).map(|c| c.extract()) {
+                if !name.contains('<') && name != rivescript_core::DEFAULT_TOPIC && !brain.has_topic(name) {
+                    found.push(Diagnostic::warning(
+                        ErrorCode::UnknownTopic,
+                        &format!("The topic '{name}' does not exist"),
+                    ));
+                }
+            }
+
+            // <call> object macros.
+            for (_, [inner]) in regex::CALL_TAG.captures_iter(text).map(|c| c.extract()) {
+                let name = inner.split_whitespace().next().unwrap_or("");
+                if name.is_empty() || name.contains('<') {
+                    continue;
+                }
+                if self.rs.subroutines.contains_key(name) || self.rs.object_langs.contains_key(name) {
+                    continue;
+                }
+
+                let message = match brain.objects.get(name) {
+                    Some(object) => format!(
+                        "The object macro '{name}' is written in '{}', which has no language handler",
+                        object.language,
+                    ),
+                    None => format!("There is no subroutine or object macro named '{name}'"),
+                };
+                found.push(Diagnostic::warning(ErrorCode::UnknownMacro, &message));
+            }
+        }
+
+        for diagnostic in found {
+            self.report(diagnostic, trigger);
+        }
+    }
+
+    /// Check that `@ redirects` and `{@ redirects}` can be matched by some trigger.
+    fn check_redirects(&mut self, topic: &str, trigger: &ast::Trigger) {
+        // Redirects are matched in the user's current topic. For the begin block,
+        // that is usually the default topic.
+        let topic = if topic == rivescript_core::BEGIN_TOPIC { rivescript_core::DEFAULT_TOPIC } else { topic };
+        let rs = self.rs;
+        let Some(candidates) = rs.sorted_topics.get(topic) else {
+            return;
+        };
+
+        let mut targets: Vec<String> = Vec::new();
+        if !trigger.redirect.is_empty() {
+            targets.push(reply::redirect_target(&trigger.redirect));
+        }
+        for text in texts(trigger) {
+            for (_, [target]) in regex::REDIRECT_TAG.captures_iter(text).map(|c| c.extract()) {
+                targets.push(reply::redirect_target(target));
+            }
+        }
+
+        for target in targets {
+            // Skip redirects with tags in them, which can't be known until runtime.
+            if target.contains('<') || target.contains('{') {
+                continue;
+            }
+
+            let matched = candidates.iter().any(|candidate| match self.regexp(&candidate.trigger) {
+                Some(re) => re.is_match(&target),
+                None => false,
+            });
+            if !matched {
+                let message = format!("No trigger in topic '{topic}' can match the redirect to '{target}'");
+                self.report(Diagnostic::warning(ErrorCode::UnmatchedRedirect, &message), trigger);
+            }
+        }
+    }
+
+    /// Find plain-text triggers that a higher-priority wildcard trigger will always match first.
+    fn check_shadowed(&mut self) {
+        let rs = self.rs;
+        let mut names: Vec<&String> = rs.sorted_topics.keys().collect();
+        names.sort();
+
+        for name in names {
+            let Some(topic) = rs.brain.topics.get(name) else {
+                continue;
+            };
+            let sorted = &rs.sorted_topics[name];
+
+            for (i, trigger) in sorted.iter().enumerate() {
+                let text = strip_priority(&trigger.trigger);
+                if !is_plain(&text) {
+                    continue;
+                }
+
+                // Only report the trigger in the topic it belongs to, rather than
+                // every topic that includes or inherits it.
+                let own = topic.triggers.iter().any(|t| t.location == trigger.location && t.trigger == text);
+                if !own {
+                    continue;
+                }
+
+                for other in sorted[..i].iter() {
+                    let pattern = strip_priority(&other.trigger);
+                    if is_plain(&pattern) || regex::TRIGGER_USER_TAGS.is_match(&pattern) {
+                        continue;
+                    }
+
+                    let shadowed = match self.regexp(&other.trigger) {
+                        Some(re) => re.is_match(&text),
+                        None => false,
+                    };
+                    if shadowed {
+                        let message = format!(
+                            "The trigger '{text}' in topic '{name}' can never match, because '{pattern}' ({}) matches first",
+                            other.location,
+                        );
+                        self.report(Diagnostic::warning(ErrorCode::ShadowedTrigger, &message), trigger);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The trigger and %Previous patterns of a trigger.
+fn patterns(trigger: &ast::Trigger) -> impl Iterator<Item = &str> {
+    [trigger.trigger.as_str(), trigger.previous.as_str()].into_iter()
+}
+
+/// All the texts of a trigger that are processed for tags: its redirect,
+/// replies and conditions.
+fn texts(trigger: &ast::Trigger) -> impl Iterator<Item = &str> {
+    std::iter::once(trigger.redirect.as_str())
+        .chain(trigger.reply.iter().map(|r| r.as_str()))
+        .chain(trigger.condition.iter().flat_map(|c| [c.left.as_str(), c.right.as_str(), c.reply.as_str()]))
+}
+
+/// Remove the {weight} and {inherits} tags from a trigger.
+fn strip_priority(trigger: &str) -> String {
+    let trigger = regex::WEIGHT.replace_all(trigger, "");
+    regex::INHERITS.replace_all(&trigger, "").to_string()
+}
+
+/// Whether a trigger is plain text, with no wildcards, optionals, arrays or tags.
+fn is_plain(trigger: &str) -> bool {
+    !trigger.contains(['*', '#', '_', '[', ']', '(', ')', '|', '@', '<', '>', '{', '}'])
+}
@@ -72,7 +72,7 @@ impl<'a> macros::Proxy for Proxy<'a> {
 
     /// Get all stored variables about the user.
     async fn get_uservars(&self, _username: &str) -> HashMap<String, String> {
-        self.rs.sessions.get_any(&self.rs.current_username().unwrap()).await
+        self.rs.sessions.get_any(&self.username).await
     }
 
     /// Set a bot variable.
@@ -1,9 +1,9 @@
 use env_logger;
 use log::{debug, warn};
-use rivescript::RiveScript;
+use rivescript::{sources, DirectoryOptions, ParseError, RiveScript, Severity, SourceProvider};
 use futures::FutureExt;
 use rivescript_core::macros::Proxy;
-use std::{env, fs, io, io::Write, path::PathBuf, process::exit};
+use std::{env, error::Error, fs, io, io::Write, path::PathBuf, process::exit};
 use structopt::StructOpt;
 
 /// Command-line flags.
@@ -21,6 +21,33 @@ struct Opt {
     #[structopt(short, short, long)]
     utf8: bool,
 
+    /// Enable strict mode: refuse to load RiveScript documents that have
+    /// any syntax warnings.
+    #[structopt(short, long)]
+    strict: bool,
+
+    /// Also load RiveScript documents from the subdirectories of directories.
+    #[structopt(short, long)]
+    recursive: bool,
+
+    /// Enable the `! include` directive in RiveScript documents.
+    #[structopt(long)]
+    includes: bool,
+
+    /// Load the bot's brain from this binary cache file if it's up to date
+    /// with the FILES, or else load the FILES and write the cache for next time.
+    #[structopt(long, parse(from_os_str))]
+    cache: Option<PathBuf>,
+
+    /// Reload RiveScript documents when they change on disk, so you don't need
+    /// to restart the program after each edit. Changes are picked up before the
+    /// next message is sent. A document that fails to parse is not reloaded.
+    #[structopt(short, long)]
+    watch: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
     /// RiveScript source documents (*.rive files) or directories of documents
     /// that make up your bot's personality. Multiple inputs will be loaded in
     /// the order specified on the command line.
@@ -28,52 +55,75 @@ struct Opt {
     files: Vec<PathBuf>,
 }
 
-#[tokio::main]
-async fn main() {
-    let opt = Opt::from_args();
-    println!("{:#?}", opt);
-
-    if opt.files.len() == 0 {
-        println!("Usage: rivescript [options] path/to/brain");
-        println!("See `rivescript --help` for documentation.");
-        exit(1);
-    }
+/// Subcommands of the rivescript program.
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Parse and sort RiveScript documents and save the result to a binary
+    /// brain cache file, which the bot can load with `--cache` for a fast start.
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    Compile {
+        /// Output file for the brain cache.
+        #[structopt(short, long, default_value = "brain.bin", parse(from_os_str))]
+        output: PathBuf,
 
-    // Debug logging mode.
-    if opt.debug {
-        unsafe {
-            env::set_var("RUST_LOG", "debug");
-        }
-    }
+        /// RiveScript source documents or directories to compile.
+        #[structopt(name = "FILES", parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
 
-    // let e.g. RUST_LOG=debug to set debug output.
-    env_logger::init();
+    /// Check RiveScript documents for syntax errors and lint the brain for
+    /// unreachable triggers and broken references. Exits with status 1 if any
+    /// errors (or, with `--strict`, any warnings at all) were found.
+    Check {
+        /// RiveScript source documents or directories to check.
+        #[structopt(name = "FILES", parse(from_os_str))]
+        files: Vec<PathBuf>,
+    },
 
+    /// Compare two versions of a bot's brain (each a RiveScript document or a
+    /// directory of them) and print the triggers, definitions and topics that
+    /// changed, as well as the triggers that moved in the match order. Exits with
+    /// status 1 if there were any differences, or 2 if either brain fails to load.
+    Diff {
+        /// The old version of the brain.
+        #[structopt(name = "OLD", parse(from_os_str))]
+        old: PathBuf,
 
-    println!("      .   .
-     .:...::      RiveScript Interpreter (Rust)
-    .::   ::.     Library Version: v{} (build {})
- ..:;;. ' .;;:..
-    .  '''  .     Type '/quit' to quit.
-     :;,:,;:      Type '/help' for more options.
-     :     :
-Using the RiveScript bot found in: {:?}
-Type a message to the bot and press Return to send it.",
-        rivescript::VERSION,
-        "n/a",
-        opt.files,
-    );
+        /// The new version of the brain.
+        #[structopt(name = "NEW", parse(from_os_str))]
+        new: PathBuf,
+    },
+}
 
-    let mut bot = RiveScript::new();
-    bot.utf8 = opt.utf8;
+/// Load all the input files/directories in order.
+fn load_files(bot: &mut RiveScript, files: &[PathBuf], recursive: bool) -> Result<(), Box<dyn Error>> {
+    let options = DirectoryOptions {
+        recursive,
+        ..Default::default()
+    };
+    for pathbuf in files {
+        let filename = pathbuf.to_str().unwrap();
+        let attr = fs::metadata(filename).map_err(|e| format!("{filename}: {e}"))?;
 
-    warn!("RiveScript-rs v{}", rivescript::VERSION);
+        if attr.is_dir() {
+            bot.load_directory_with(filename, &options)?;
+        } else if attr.is_file() {
+            bot.load_file(filename).map_err(|e| match e.is::<ParseError>() {
+                true => e,
+                false => format!("{filename}: {e}").into(),
+            })?;
+        }
+    }
+    Ok(())
+}
 
+/// Register the object macro handlers and the example Rust subroutines.
+fn register_handlers(bot: &mut RiveScript) {
     // Register the JavaScript handler?
     #[cfg(feature = "javascript")]
     {
         println!("Note: JavaScript object macros enabled.");
-        rivescript::register_default_js_handler(&mut bot);
+        rivescript::register_default_js_handler(bot);
     }
 
     // An example object macro written in Rust.
@@ -109,20 +159,201 @@ Type a message to the bot and press Return to send it.",
             proxy.finish("Usage: rust-set name value".to_string())
         }.boxed()
     });
+}
 
-    // Load all the input files/directories in order.
-    for pathbuf in opt.files {
-        let filename = pathbuf.to_str().unwrap();
-        let attr = fs::metadata(filename).expect(format!("{}: file not found", filename).as_str());
+/// Run the `check` subcommand and return the program's exit status.
+fn check(opt: &Opt, files: &[PathBuf]) -> i32 {
+    let mut bot = RiveScript::new();
+    bot.strict = opt.strict;
+    bot.includes = opt.includes;
+    register_handlers(&mut bot);
+    let options = DirectoryOptions {
+        recursive: opt.recursive,
+        ..Default::default()
+    };
+    let mut errors = 0;
+    let mut checked = 0;
 
-        if attr.is_dir() {
-            bot.load_directory(filename)
-                .expect(format!("Error loading from directory {}", filename).as_str());
-        } else if attr.is_file() {
-            bot.load_file(filename).expect(format!("Error loading file {}", filename).as_str());
+    // Parse every document on its own, reporting (rather than stopping on) the ones that fail.
+    for pathbuf in files {
+        let path = pathbuf.to_str().unwrap();
+        let names = match sources::FileSystem::with_options(path, options.clone()).list() {
+            Ok(names) => names,
+            Err(e) => {
+                println!("{path}: {e}");
+                errors += 1;
+                continue;
+            },
+        };
+        for name in names {
+            match bot.load_file(&name) {
+                Ok(_) => checked += 1,
+                Err(e) if e.is::<ParseError>() => {
+                    println!("{e}");
+                    checked += 1;
+                    errors += 1;
+                },
+                Err(e) => {
+                    println!("{name}: {e}");
+                    errors += 1;
+                },
+            }
+        }
+    }
+    bot.sort_triggers();
+
+    let mut warnings = 0;
+    let mut diagnostics = bot.warnings().to_vec();
+    diagnostics.extend(bot.lint());
+    for diagnostic in diagnostics.iter() {
+        println!("{diagnostic}");
+        match diagnostic.severity {
+            Severity::Error => errors += 1,
+            Severity::Warning => warnings += 1,
+        }
+    }
+
+    println!("Checked {checked} file(s): {errors} error(s), {warnings} warning(s)");
+    if errors > 0 || (opt.strict && warnings > 0) { 1 } else { 0 }
+}
+
+/// Run the `diff` subcommand and return the program's exit status: 1 if the brains
+/// differ, or 2 if either of them couldn't be loaded.
+fn diff(opt: &Opt, old: &PathBuf, new: &PathBuf) -> i32 {
+    let load = |path: &PathBuf| -> Result<RiveScript, Box<dyn Error>> {
+        let mut bot = RiveScript::new();
+        bot.strict = opt.strict;
+        bot.includes = opt.includes;
+        load_files(&mut bot, std::slice::from_ref(path), opt.recursive)?;
+        bot.sort_triggers();
+        Ok(bot)
+    };
+
+    let (old, new) = match (load(old), load(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (old, new) => {
+            for e in [old.err(), new.err()].into_iter().flatten() {
+                println!("{e}");
+            }
+            return 2;
+        },
+    };
+
+    let diff = old.diff(&new);
+    print!("{diff}");
+    if diff.is_empty() { 0 } else { 1 }
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+
+    #[cfg(not(feature = "serde"))]
+    if matches!(opt.cmd, Some(Command::Compile { .. })) || opt.cache.is_some() {
+        println!("The brain cache needs rivescript to be built with the `serde` feature.");
+        exit(1);
+    }
+
+    #[cfg(feature = "serde")]
+    if let Some(Command::Compile { output, files }) = &opt.cmd {
+        let mut bot = RiveScript::new();
+        bot.strict = opt.strict;
+        bot.includes = opt.includes;
+        if let Err(e) = load_files(&mut bot, files, opt.recursive) {
+            println!("{e}");
+            exit(1);
+        }
+        bot.sort_triggers();
+        bot.save_cache(output.to_str().unwrap()).expect("Couldn't write the brain cache");
+        println!("Compiled {} file(s) into {}", files.len(), output.display());
+        return;
+    }
+
+    if let Some(Command::Check { files }) = &opt.cmd {
+        exit(check(&opt, files));
+    }
+
+    if let Some(Command::Diff { old, new }) = &opt.cmd {
+        exit(diff(&opt, old, new));
+    }
+
+    if opt.files.len() == 0 && opt.cache.is_none() {
+        println!("Usage: rivescript [options] path/to/brain");
+        println!("See `rivescript --help` for documentation.");
+        exit(1);
+    }
+
+    // Debug logging mode.
+    if opt.debug {
+        println!("{:#?}", opt);
+        unsafe {
+            env::set_var("RUST_LOG", "debug");
         }
     }
 
+    // let e.g. RUST_LOG=debug to set debug output.
+    env_logger::init();
+
+
+    println!("      .   .
+     .:...::      RiveScript Interpreter (Rust)
+    .::   ::.     Library Version: v{} (build {})
+ ..:;;. ' .;;:..
+    .  '''  .     Type '/quit' to quit.
+     :;,:,;:      Type '/help' for more options.
+     :     :
+Using the RiveScript bot found in: {:?}
+Type a message to the bot and press Return to send it.",
+        rivescript::VERSION,
+        "n/a",
+        opt.files,
+    );
+
+    let mut bot = RiveScript::new();
+    bot.utf8 = opt.utf8;
+    bot.strict = opt.strict;
+    bot.includes = opt.includes;
+
+    warn!("RiveScript-rs v{}", rivescript::VERSION);
+
+    register_handlers(&mut bot);
+
+    #[cfg(not(feature = "watch"))]
+    if opt.watch {
+        println!("The --watch flag needs rivescript to be built with the `watch` feature.");
+        exit(1);
+    }
+
+    // Load the brain from the cache, or from the input files/directories in order.
+    // A cached brain doesn't know which files it came from, so it can't be watched.
+    #[cfg(not(feature = "serde"))]
+    let cached = false;
+    #[cfg(feature = "serde")]
+    let cached = match &opt.cache {
+        Some(_) if opt.watch && !opt.files.is_empty() => {
+            println!("Note: --watch loads the FILES instead of the brain cache.");
+            false
+        },
+        Some(cache) if opt.files.is_empty() => {
+            bot.load_cache(cache.to_str().unwrap()).expect("Error loading the brain cache");
+            true
+        },
+        Some(cache) => {
+            let sources: Vec<&str> = opt.files.iter().map(|f| f.to_str().unwrap()).collect();
+            let options = DirectoryOptions {
+                recursive: opt.recursive,
+                ..Default::default()
+            };
+            bot.load_cache_if_fresh(cache.to_str().unwrap(), &sources, &options)
+                .expect("Error checking the brain cache")
+        },
+        None => false,
+    };
+    if !cached && let Err(e) = load_files(&mut bot, &opt.files, opt.recursive) {
+        println!("{e}");
+        exit(1);
+    }
+
     // bot.load_file("eg/brain/begin.rive").expect("ok");
 
     // bot.stream(String::from("+ stream test\n- stream OK\n! version = 2"))
@@ -130,7 +361,19 @@ Type a message to the bot and press Return to send it.",
 
     // bot.load_file("test.rive").expect("ok");
 
-    bot.sort_triggers();
+    if !cached {
+        bot.sort_triggers();
+        #[cfg(feature = "serde")]
+        if let Some(cache) = &opt.cache {
+            bot.save_cache(cache.to_str().unwrap()).expect("Couldn't write the brain cache");
+        }
+    }
+
+    #[cfg(feature = "watch")]
+    let mut watcher = match opt.watch {
+        true => Some(rivescript::Watcher::new(&bot).expect("Couldn't watch the RiveScript documents")),
+        false => None,
+    };
 
     // Enter main prompt loop.
     loop {
@@ -143,6 +386,13 @@ Type a message to the bot and press Return to send it.",
             .read_line(&mut message)
             .expect("Failed to read line");
 
+        // Pick up any edits to the RiveScript documents before replying.
+        #[cfg(feature = "watch")]
+        if let Some(watcher) = watcher.as_mut() {
+            for change in watcher.reload(&mut bot) {
+                println!("{change}");
+            }
+        }
 
         // Process commands.
         match message.trim() {
@@ -0,0 +1,111 @@
+// Compiled trigger regexps.
+//
+// Turning a trigger into a regexp takes a lot of string processing, and compiling
+// it takes more, so it's done once when the triggers are sorted instead of for
+// every message. Triggers with tags that depend on variables (<bot>, <get>,
+// <input> and <reply>) are prepared up to those tags: the tags are filled in for
+// each message, and the regexps are cached by the filled-in pattern.
+//
+// The arrays and UTF-8 mode can be changed after sorting. Until the triggers are
+// sorted again, the patterns that depend on them are compiled for each message.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use log::debug;
+use regex::Regex;
+
+use crate::{RiveScript, reply};
+
+// How many filled-in patterns to keep before the cache is emptied.
+const MAX_EXPANDED: usize = 10_000;
+
+/// A trigger pattern, compiled as far as it can be ahead of time.
+pub enum Compiled {
+    /// The regexp for a pattern without any dynamic tags.
+    Static(Regex),
+
+    /// A pattern that was prepared up to its dynamic tags.
+    Dynamic(String),
+}
+
+/// The compiled patterns of the sorted triggers and %Previous's, keyed by their text.
+#[derive(Default)]
+pub struct Patterns {
+    compiled: HashMap<String, Compiled>,
+
+    // The arrays and UTF-8 mode that the patterns were compiled with.
+    arrays: HashMap<String, Vec<String>>,
+    utf8: bool,
+
+    // Regexps for dynamic patterns, keyed by the pattern after its tags were filled in.
+    expanded: RwLock<HashMap<String, Regex>>,
+}
+
+impl Patterns {
+    /// Compile the patterns of any sorted triggers that aren't compiled yet, and forget
+    /// the ones that are no longer used. If the arrays or UTF-8 mode changed since the
+    /// last time, everything is compiled again.
+    pub fn refresh(mut self, rs: &RiveScript) -> Self {
+        if self.arrays != rs.brain.arrays || self.utf8 != rs.utf8 {
+            self = Self {
+                arrays: rs.brain.arrays.clone(),
+                utf8: rs.utf8,
+                ..Default::default()
+            };
+        }
+
+        // A pattern that can't compile gets the empty regexp, the same as in trigger_regexp.
+        let empty = Regex::new("").unwrap();
+        let mut used = HashSet::new();
+        let triggers = rs.sorted_topics.values().chain(rs.sorted_thats.values()).flatten();
+        for trigger in triggers {
+            for pattern in [&trigger.trigger, &trigger.previous] {
+                if pattern.is_empty() || !used.insert(pattern) || self.compiled.contains_key(pattern) {
+                    continue;
+                }
+
+                let prepared = reply::prepare_pattern(rs, pattern);
+                let compiled = if rivescript_core::regex::BOT_TAG.is_match(&prepared)
+                    || rivescript_core::regex::TRIGGER_USER_TAGS.is_match(&prepared)
+                {
+                    Compiled::Dynamic(prepared)
+                } else {
+                    Compiled::Static(reply::compile_trigger_pattern(rs, prepared).unwrap_or_else(|_| empty.clone()))
+                };
+                self.compiled.insert(pattern.clone(), compiled);
+            }
+        }
+
+        self.compiled.retain(|pattern, _| used.contains(pattern));
+        debug!("Compiled {} trigger patterns", self.compiled.len());
+        self
+    }
+
+    /// The compiled pattern for a trigger or %Previous.
+    pub fn get(&self, pattern: &str) -> Option<&Compiled> {
+        self.compiled.get(pattern)
+    }
+
+    /// Whether the compiled pattern is out of date, because the UTF-8 mode or (for a
+    /// pattern that uses them) the arrays were changed without sorting the triggers again.
+    pub fn is_stale(&self, rs: &RiveScript, pattern: &str) -> bool {
+        self.utf8 != rs.utf8 || (pattern.contains('@') && self.arrays != rs.brain.arrays)
+    }
+
+    /// The regexp for a dynamic pattern whose tags have been filled in, compiling it
+    /// with `compile` if it isn't cached.
+    pub fn expanded(&self, pattern: &str, compile: impl FnOnce() -> Regex) -> Regex {
+        if let Some(regexp) = self.expanded.read().expect("RwLock poisoned").get(pattern) {
+            return regexp.clone();
+        }
+
+        let regexp = compile();
+        let mut expanded = self.expanded.write().expect("RwLock poisoned");
+        if expanded.len() >= MAX_EXPANDED {
+            expanded.clear();
+        }
+        expanded.insert(pattern.to_string(), regexp.clone());
+        regexp
+    }
+}
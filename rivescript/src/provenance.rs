@@ -0,0 +1,388 @@
+// Tracking which source file contributed each part of the brain.
+//
+// Once a document is merged into the AST by `AST::extend`, its definitions are
+// mixed in with everyone else's. To unload or reload a single file later, we
+// keep a record of what each file defined, in the order the files were loaded.
+// For triggers, we keep the id of the load that each one came from, in step
+// with each topic's list of triggers. `! sub` and other definitions are kept
+// by name, since a later file may have overridden an earlier file's value.
+//
+// Anything set on the bot some other way (`set_variable`, `load_ast` and so on)
+// belongs to no document: those names are dropped from the records, so that
+// unloading or reloading a document leaves them alone.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{AST, Object, Topic, Trigger};
+use crate::diff::DefinitionKind;
+
+// The includes and inherits of a topic.
+type Relations = (HashMap<String, bool>, HashMap<String, bool>);
+
+/// What one loaded document contributed to the brain.
+pub struct Source {
+    pub filename: String,
+
+    // The id that marks the document's triggers as its own.
+    id: usize,
+
+    globals: HashMap<String, String>,
+    vars: HashMap<String, String>,
+    subs: HashMap<String, String>,
+    person: HashMap<String, String>,
+    arrays: HashMap<String, Vec<String>>,
+    objects: HashMap<String, Object>,
+
+    // The includes and inherits of each topic the document mentions.
+    topics: HashMap<String, Relations>,
+
+    // The files that the document `! include`d, in the order they were read.
+    #[cfg_attr(not(any(feature = "watch", feature = "serde")), allow(dead_code))]
+    includes: Vec<String>,
+}
+
+impl Source {
+    fn new(id: usize, filename: &str, ast: &AST, includes: &[(String, String)]) -> Self {
+        Self {
+            filename: filename.to_string(),
+            id,
+            globals: ast.globals.read().expect("RwLock poisoned").clone(),
+            vars: ast.vars.read().expect("RwLock poisoned").clone(),
+            subs: ast.subs.clone(),
+            person: ast.person.clone(),
+            arrays: ast.arrays.clone(),
+            objects: ast.objects.clone(),
+            topics: ast.topics
+                .iter()
+                .map(|(name, topic)| (name.clone(), (topic.includes.clone(), topic.inherits.clone())))
+                .collect(),
+            includes: includes.iter().map(|(filename, _)| filename.clone()).collect(),
+        }
+    }
+}
+
+/// The changes to make outside of the AST after `Provenance::restore`.
+#[derive(Default)]
+pub struct Restored {
+    /// Topics whose triggers, includes or inherits may have changed.
+    pub topics: HashSet<String>,
+
+    /// Topics that no longer exist.
+    pub removed_topics: Vec<String>,
+
+    /// Object macros that were added or replaced, to give to their language handlers.
+    pub objects: Vec<Object>,
+
+    /// Object macros that no longer exist.
+    pub removed_objects: Vec<Object>,
+}
+
+/// The records of every document loaded into the brain, in load order.
+#[derive(Default)]
+pub struct Provenance {
+    sources: Vec<Source>,
+
+    // The id of the last document recorded. Ids start at 1, as 0 means no document.
+    last_id: usize,
+
+    // The id of the document that each trigger in the brain came from, in the same
+    // order as each topic's triggers.
+    owners: HashMap<String, Vec<usize>>,
+}
+
+// The owners of a topic's triggers. Triggers that were added to the end of the topic
+// some other way (such as `add_trigger` or `load_ast`) belong to no document.
+fn owners<'a>(owners: &'a mut HashMap<String, Vec<usize>>, topic: &str, len: usize) -> &'a mut Vec<usize> {
+    let owners = owners.entry(topic.to_string()).or_default();
+    owners.resize(len, 0);
+    owners
+}
+
+impl Provenance {
+    /// Record the contents of a parsed document, before it is merged into the brain.
+    pub fn record(&mut self, filename: &str, ast: &AST, includes: &[(String, String)]) -> Source {
+        self.last_id += 1;
+        Source::new(self.last_id, filename, ast, includes)
+    }
+
+    /// Mark the triggers that a document is about to add to the end of each topic as its own.
+    pub fn append_triggers(&mut self, brain: &AST, source: &Source, ast: &AST) {
+        for (name, topic) in ast.topics.iter() {
+            let len = brain.topics.get(name).map_or(0, |mine| mine.triggers.len());
+            owners(&mut self.owners, name, len).extend(std::iter::repeat_n(source.id, topic.triggers.len()));
+        }
+    }
+
+    pub fn push(&mut self, source: Source) {
+        self.sources.push(source);
+    }
+
+    pub fn insert(&mut self, index: usize, source: Source) {
+        self.sources.insert(index, source);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn get(&self, index: usize) -> &Source {
+        &self.sources[index]
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn clear(&mut self) {
+        self.sources.clear();
+        self.owners.clear();
+    }
+
+    /// The filenames of the loaded documents, in load order.
+    pub fn filenames(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.sources
+            .iter()
+            .filter(|s| seen.insert(s.filename.as_str()))
+            .map(|s| s.filename.clone())
+            .collect()
+    }
+
+    /// The files that each loaded document was read from: the document itself, and any
+    /// files that it `! include`d.
+    #[cfg(feature = "watch")]
+    pub fn files(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut result: Vec<(&str, Vec<&str>)> = Vec::new();
+        for source in self.sources.iter() {
+            let mut files = vec![source.filename.as_str()];
+            files.extend(source.includes.iter().map(String::as_str));
+            result.push((source.filename.as_str(), files));
+        }
+        result
+    }
+
+    /// The files that each loaded document `! include`d, keyed by the document's filename.
+    #[cfg(feature = "serde")]
+    pub fn included(&self) -> HashMap<String, Vec<String>> {
+        self.sources
+            .iter()
+            .filter(|s| !s.includes.is_empty())
+            .map(|s| (s.filename.clone(), s.includes.clone()))
+            .collect()
+    }
+
+    /// Remove the records for a filename, returning where the first one was in the load order.
+    pub fn remove(&mut self, filename: &str) -> Option<(usize, Vec<Source>)> {
+        let index = self.sources.iter().position(|s| s.filename == filename)?;
+        let (removed, kept) = std::mem::take(&mut self.sources)
+            .into_iter()
+            .partition(|s| s.filename == filename);
+        self.sources = kept;
+        Some((index, removed))
+    }
+
+    /// Merge the triggers of a reloaded document (recorded at `index`) into the brain where
+    /// they were before: ahead of the first trigger from a document loaded after it.
+    /// Returns the topics that changed.
+    pub fn insert_triggers(&mut self, brain: &mut AST, index: usize, topics: HashMap<String, Topic>) -> HashSet<String> {
+        let id = self.sources[index].id;
+        let later: HashSet<usize> = self.sources[index+1..].iter().map(|s| s.id).collect();
+
+        let mut changed = HashSet::new();
+        for (name, topic) in topics {
+            brain.init_topic(&name);
+            let mine = brain.topics.get_mut(&name).unwrap();
+            if !mine.location.is_known() {
+                mine.location = topic.location;
+            }
+
+            let owners = owners(&mut self.owners, &name, mine.triggers.len());
+            let at = owners.iter().position(|owner| later.contains(owner)).unwrap_or(owners.len());
+            owners.splice(at..at, std::iter::repeat_n(id, topic.triggers.len()));
+            mine.triggers.splice(at..at, topic.triggers);
+            changed.insert(name);
+        }
+        changed
+    }
+
+    /// Remove the triggers that came from these (already removed) documents from the brain.
+    pub fn remove_triggers(&mut self, brain: &mut AST, removed: &[Source]) -> HashSet<String> {
+        let ids: HashSet<usize> = removed.iter().map(|s| s.id).collect();
+
+        let mut changed = HashSet::new();
+        for (name, topic) in brain.topics.iter_mut() {
+            if self.retain_triggers(topic, |_, owner| !ids.contains(&owner)) {
+                changed.insert(name.clone());
+            }
+        }
+        changed
+    }
+
+    /// Keep only the triggers of a topic (given with the id of the document they came
+    /// from) that `keep` returns true for. Returns whether any were removed.
+    pub fn retain_triggers(&mut self, topic: &mut Topic, mut keep: impl FnMut(&Trigger, usize) -> bool) -> bool {
+        let before = topic.triggers.len();
+        let owners = owners(&mut self.owners, &topic.name, before);
+        let (triggers, kept): (Vec<Trigger>, Vec<usize>) = std::mem::take(&mut topic.triggers)
+            .into_iter()
+            .zip(std::mem::take(owners))
+            .filter(|(trigger, owner)| keep(trigger, *owner))
+            .unzip();
+        topic.triggers = triggers;
+        *owners = kept;
+        topic.triggers.len() != before
+    }
+
+    /// Forget that a document defined the trigger at this index of a topic, because it
+    /// has since been replaced some other way.
+    pub fn disown_trigger(&mut self, topic: &Topic, index: usize) {
+        owners(&mut self.owners, &topic.name, topic.triggers.len())[index] = 0;
+    }
+
+    /// Recompute the parts of the brain that the changed documents defined, from the
+    /// documents that remain: the last one loaded that defines a name wins.
+    pub fn restore(&self, brain: &mut AST, changed: &[&Source]) -> Restored {
+        let mut restored = Restored::default();
+
+        {
+            let mut globals = brain.globals.write().expect("RwLock poisoned");
+            let mut vars = brain.vars.write().expect("RwLock poisoned");
+            self.restore_map(&mut globals, changed, |s| &s.globals);
+            self.restore_map(&mut vars, changed, |s| &s.vars);
+        }
+        self.restore_map(&mut brain.subs, changed, |s| &s.subs);
+        self.restore_map(&mut brain.person, changed, |s| &s.person);
+        self.restore_map(&mut brain.arrays, changed, |s| &s.arrays);
+
+        // Object macros.
+        let names: HashSet<&String> = changed.iter().flat_map(|s| s.objects.keys()).collect();
+        for name in names {
+            match self.sources.iter().rev().find_map(|s| s.objects.get(name)) {
+                Some(object) => {
+                    brain.objects.insert(name.clone(), object.clone());
+                    restored.objects.push(object.clone());
+                }
+                None => {
+                    if let Some(object) = brain.objects.remove(name) {
+                        restored.removed_objects.push(object);
+                    }
+                }
+            }
+        }
+
+        // Topic includes and inherits are the union of what the remaining documents say,
+        // plus any that were added some other way.
+        let names: HashSet<&String> = changed.iter().flat_map(|s| s.topics.keys()).collect();
+        for name in names {
+            let mut includes = HashMap::new();
+            let mut inherits = HashMap::new();
+            let mut defined = false;
+            for source in self.sources.iter() {
+                if let Some((inc, inh)) = source.topics.get(name) {
+                    includes.extend(inc.clone());
+                    inherits.extend(inh.clone());
+                    defined = true;
+                }
+            }
+
+            if let Some(topic) = brain.topics.get_mut(name) {
+                for (inc, inh) in changed.iter().filter_map(|s| s.topics.get(name)) {
+                    topic.includes.retain(|k, _| !inc.contains_key(k));
+                    topic.inherits.retain(|k, _| !inh.contains_key(k));
+                }
+                topic.includes.extend(includes);
+                topic.inherits.extend(inherits);
+                let empty = topic.triggers.is_empty() && topic.includes.is_empty() && topic.inherits.is_empty();
+                if !defined && empty {
+                    restored.removed_topics.push(name.clone());
+                }
+            }
+            restored.topics.insert(name.clone());
+        }
+
+        // Drop the topics that nothing defines anymore, unless another topic still
+        // includes or inherits them (or it's the default topic).
+        restored.removed_topics.retain(|name| {
+            let referenced = brain.topics.values().any(|t| t.includes.contains_key(name) || t.inherits.contains_key(name));
+            name != rivescript_core::DEFAULT_TOPIC && !referenced
+        });
+        for name in restored.removed_topics.iter() {
+            brain.topics.remove(name);
+            restored.topics.remove(name);
+        }
+
+        restored
+    }
+
+    /// Forget that any document defined this name, because it has since been set
+    /// some other way.
+    pub fn disown(&mut self, kind: DefinitionKind, name: &str) {
+        for source in self.sources.iter_mut() {
+            match kind {
+                DefinitionKind::Global => source.globals.remove(name).is_some(),
+                DefinitionKind::Var => source.vars.remove(name).is_some(),
+                DefinitionKind::Sub => source.subs.remove(name).is_some(),
+                DefinitionKind::Person => source.person.remove(name).is_some(),
+                DefinitionKind::Array => source.arrays.remove(name).is_some(),
+                DefinitionKind::Object => source.objects.remove(name).is_some(),
+            };
+        }
+    }
+
+    /// Forget that any document defined this topic's triggers, includes and inherits,
+    /// because the whole topic has since been replaced.
+    pub fn disown_topic(&mut self, name: &str) {
+        for source in self.sources.iter_mut() {
+            source.topics.remove(name);
+        }
+        self.owners.remove(name);
+    }
+
+    /// Forget a topic that has been removed from the brain, along with every document's
+    /// includes and inherits of it, so that unloading or reloading a document doesn't
+    /// bring them back.
+    pub fn remove_topic(&mut self, name: &str) {
+        self.disown_topic(name);
+        for (inc, inh) in self.sources.iter_mut().flat_map(|s| s.topics.values_mut()) {
+            inc.remove(name);
+            inh.remove(name);
+        }
+    }
+
+    /// Forget that any document defined the names in an AST that was merged into the
+    /// brain some other way (such as `load_ast`), since it overrides them.
+    pub fn disown_ast(&mut self, ast: &AST) {
+        let maps = [
+            (DefinitionKind::Global, ast.globals.read().expect("RwLock poisoned").keys().cloned().collect()),
+            (DefinitionKind::Var, ast.vars.read().expect("RwLock poisoned").keys().cloned().collect()),
+            (DefinitionKind::Sub, ast.subs.keys().cloned().collect()),
+            (DefinitionKind::Person, ast.person.keys().cloned().collect()),
+            (DefinitionKind::Array, ast.arrays.keys().cloned().collect()),
+            (DefinitionKind::Object, ast.objects.keys().cloned().collect::<Vec<String>>()),
+        ];
+        for (kind, names) in maps {
+            for name in names {
+                self.disown(kind, &name);
+            }
+        }
+
+        for topic in ast.topics.values() {
+            for (inc, inh) in self.sources.iter_mut().filter_map(|s| s.topics.get_mut(&topic.name)) {
+                inc.retain(|k, _| !topic.includes.contains_key(k));
+                inh.retain(|k, _| !topic.inherits.contains_key(k));
+            }
+        }
+    }
+
+    fn restore_map<V: Clone>(
+        &self,
+        map: &mut HashMap<String, V>,
+        changed: &[&Source],
+        field: impl Fn(&Source) -> &HashMap<String, V>,
+    ) {
+        let names: HashSet<&String> = changed.iter().flat_map(|s| field(s).keys()).collect();
+        for name in names {
+            match self.sources.iter().rev().find_map(|s| field(s).get(name)) {
+                Some(value) => map.insert(name.clone(), value.clone()),
+                None => map.remove(name),
+            };
+        }
+    }
+}
@@ -5,14 +5,23 @@ use log::{debug, warn};
 use ::regex::Regex;
 
 use crate::{RiveScript, ast, inheritance};
+use crate::patterns::Compiled;
 
-/// Get a reply to the username's message.
-pub async fn reply(rs: &mut RiveScript, username: &str, message: &str) -> Result<String, String> {
+tokio::task_local! {
+    // The username that a reply is being found for, for RiveScript::current_username().
+    pub static CURRENT_USERNAME: String;
+}
 
-    // Store the current user's ID.
-    rs.in_reply_context = true;
-    rs.current_username = String::from(username);
+/// Get a reply to the username's message.
+pub async fn reply(rs: &RiveScript, username: &str, message: &str) -> Result<String, String> {
+    // The current user's ID is only set within this reply's future, so replies for
+    // other users can run at the same time, and nothing is left behind if the
+    // future is dropped before it finishes.
+    CURRENT_USERNAME.scope(String::from(username), find_reply(rs, username, message)).await
+}
 
+async fn find_reply(rs: &RiveScript, username: &str, message: &str) -> Result<String, String> {
+    let username = String::from(username);
     let mut msg = String::from(message);
     let mut answer;
 
@@ -24,28 +33,26 @@ pub async fn reply(rs: &mut RiveScript, username: &str, message: &str) -> Result
     // If the BEGIN block exists, consult it first.
     if rs.brain.has_begin_block() {
         debug!("Has a BEGIN block");
-        match get_reply(rs, &rs.current_username, &String::from(rivescript_core::BEGIN_REQUEST), true, 0).await {
+        match get_reply(rs, &username, &String::from(rivescript_core::BEGIN_REQUEST), true, 0).await {
             Ok(begin) => {
                 debug!("Answer to BEGIN request: {begin}");
 
-                // Is it OK to continue?
+                // Is it OK to continue? Then get the real reply, to go in place of the {ok} tag.
+                let mut ok = None;
                 if begin.contains(rivescript_core::TAG_OK) {
-                    // Get the real reply and substitute it in.
-                    match get_reply(rs, &rs.current_username, &msg, false, 0).await {
+                    match get_reply(rs, &username, &msg, false, 0).await {
                         Ok(reply) => {
                             debug!("Answer to reply request: {reply}");
-                            answer = begin.replace(rivescript_core::TAG_OK, &reply);
+                            ok = Some(reply);
                         },
                         Err(e) => {
                             return Err(e);
                         },
                     }
-                } else {
-                    answer = begin;
                 }
 
                 // Run post-reply tags.
-                answer = crate::tags::process(&rs, &rs.current_username, &msg, &answer, Vec::new(), Vec::new(), 0).await;
+                answer = crate::tags::process_begin(rs, &username, &begin, ok.as_deref()).await;
             },
             Err(e) => {
                 return Err(e);
@@ -53,7 +60,7 @@ pub async fn reply(rs: &mut RiveScript, username: &str, message: &str) -> Result
         }
     } else {
         debug!("No BEGIN block");
-        match get_reply(rs, &rs.current_username, &msg, false, 0).await {
+        match get_reply(rs, &username, &msg, false, 0).await {
             Ok(reply) => {
                 debug!("Answer to reply request: {reply}");
                 answer = reply
@@ -69,11 +76,7 @@ pub async fn reply(rs: &mut RiveScript, username: &str, message: &str) -> Result
     }
 
     // Save their message history.
-    rs.sessions.add_history(username, message, &answer).await;
-
-    // Unset the current user's ID.
-    rs.current_username = String::new();
-    rs.in_reply_context = false;
+    rs.sessions.add_history(&username, message, &answer).await;
 
     return Ok(answer);
 }
@@ -224,6 +227,9 @@ pub async fn get_reply(
 
     // Store what trigger they last matched on.
     // TODO
+    if found_match {
+        debug!("Matched trigger '{}' from {}", matched.trigger, matched.location);
+    }
 
     // Did we find a match after all?
     if found_match {
@@ -236,7 +242,7 @@ pub async fn get_reply(
                 debug!("Redirecting us to: {}", matched.redirect);
                 let mut redirect = matched.redirect.clone();
                 redirect = crate::tags::process(&rs, &username, &message, &redirect, stars.clone(), bot_stars.clone(), 0).await;
-                redirect = redirect.to_lowercase();
+                redirect = redirect_target(&redirect);
 
                 debug!("Pretend user said: {redirect}");
                 match get_reply(&rs, &username, &redirect, false, step+1).await {
@@ -364,7 +370,7 @@ pub fn format_message(rs: &RiveScript, msg: &str, is_bot_reply: bool) -> String
     }
 
     // Run substitutions and sanitize what's left.
-    msg = crate::tags::substitute(rs.brain.subs.clone(), rs.sorted_subs.clone(), &msg);
+    msg = rs.sub_matcher.apply(&msg);
 
     // In UTF-8 mode, only strip metacharacters and HTML brackets.
     if rs.utf8 {
@@ -384,9 +390,80 @@ pub fn format_message(rs: &RiveScript, msg: &str, is_bot_reply: bool) -> String
     msg.trim().to_string()
 }
 
-// Prepare a trigger pattern for the regular expression engine.
+// Get the regular expression for a trigger pattern, filling in the tags that depend on the user.
 pub async fn trigger_regexp(rs: &RiveScript, username: &String, pattern: &String) -> Regex {
-    let mut pattern = pattern.clone();
+    // The sorted triggers were compiled ahead of time, or prepared up to their dynamic tags.
+    if rs.patterns.is_stale(rs, pattern) {
+        // Compiled with other arrays or UTF-8 mode, which the cached regexps would be too.
+        let pattern = expand_user_tags(rs, username, trigger_pattern(rs, pattern)).await;
+        return compile_trigger_pattern(rs, pattern).unwrap_or(Regex::new("").unwrap());
+    }
+    let prepared = match rs.patterns.get(pattern) {
+        Some(Compiled::Static(regexp)) => return regexp.clone(),
+        Some(Compiled::Dynamic(prepared)) => prepared.clone(),
+        None => prepare_pattern(rs, pattern),
+    };
+
+    let pattern = expand_user_tags(rs, username, expand_bot_vars(rs, prepared)).await;
+    rs.patterns.expanded(&pattern, || {
+        compile_trigger_pattern(rs, pattern.clone()).unwrap_or(Regex::new("").unwrap())
+    })
+}
+
+// Filter the <get>, <input> and <reply> tags into a prepared trigger pattern.
+async fn expand_user_tags(rs: &RiveScript, username: &str, pattern: String) -> String {
+    let mut pattern = pattern;
+
+    // Filter in <get> user variables.
+    for (m, [name]) in rivescript_core::regex::USER_VAR_TAG.captures_iter(&pattern.clone()).map(|c| c.extract()) {
+        let mut replacement = rs.sessions.get(username, name).await;
+        replacement = strip_nasties(replacement).to_lowercase();
+        pattern = pattern.replace(m, &replacement);
+    }
+
+    // Filter in <input>/<reply> tags.
+    if pattern.contains("<input") || pattern.contains("<reply") {
+        pattern = pattern.replace("<input>", "<input1>");
+        pattern = pattern.replace("<reply>", "<reply1>");
+        let history = rs.sessions.get_history(username).await;
+
+        for (_, [number]) in rivescript_core::regex::HISTORY_TAG.captures_iter(&pattern.clone()).map(|c| c.extract()) {
+            let mut idx: usize = 1;
+            if !number.is_empty() {
+                idx = number.parse().unwrap();
+            }
+
+            let input = history.input.get(idx-1).unwrap();
+            let reply = history.reply.get(idx-1).unwrap();
+
+            // Format the previous inputs for the regexp engine.
+            let input = &format_message(rs, input, true);
+            let reply = &format_message(rs, reply, true);
+
+            pattern = pattern.replace(
+                &String::from(format!("<input{idx}>")),
+                input,
+            );
+            pattern = pattern.replace(
+                &String::from(format!("<reply{idx}>")),
+                reply,
+            );
+        }
+    }
+
+    pattern
+}
+
+// The part of trigger_regexp that doesn't depend on the user: everything but the
+// <get>, <input> and <reply> tags.
+pub fn trigger_pattern(rs: &RiveScript, pattern: &str) -> String {
+    expand_bot_vars(rs, prepare_pattern(rs, pattern))
+}
+
+// The part of trigger_regexp that doesn't depend on any variables: everything but the
+// <bot>, <get>, <input> and <reply> tags.
+pub fn prepare_pattern(rs: &RiveScript, pattern: &str) -> String {
+    let mut pattern = pattern.to_string();
 
     // If the trigger is simply '*' then the * needs to become (.*?)
     // instead of the usual (.+?), to match the blank string too.
@@ -453,6 +530,13 @@ pub async fn trigger_regexp(rs: &RiveScript, username: &String, pattern: &String
         pattern = pattern.replace(m, &replacement);
     }
 
+    pattern
+}
+
+// Filter the <bot> tags into a prepared trigger pattern.
+fn expand_bot_vars(rs: &RiveScript, pattern: String) -> String {
+    let mut pattern = pattern;
+
     // Filter in bot variables.
     for (m, [name]) in rivescript_core::regex::BOT_TAG.captures_iter(&pattern.clone()).map(|c| c.extract()) {
         let mut replacement = rs.brain.get_bot_var(name);
@@ -460,42 +544,12 @@ pub async fn trigger_regexp(rs: &RiveScript, username: &String, pattern: &String
         pattern = pattern.replace(m, &replacement);
     }
 
-    // Filter in <get> user variables.
-    for (m, [name]) in rivescript_core::regex::USER_VAR_TAG.captures_iter(&pattern.clone()).map(|c| c.extract()) {
-        let mut replacement = rs.sessions.get(username, name).await;
-        replacement = strip_nasties(replacement).to_lowercase();
-        pattern = pattern.replace(m, &replacement);
-    }
-
-    // Filter in <input>/<reply> tags.
-    if pattern.contains("<input") || pattern.contains("<reply") {
-        pattern = pattern.replace("<input>", "<input1>");
-        pattern = pattern.replace("<reply>", "<reply1>");
-        let history = rs.sessions.get_history(username).await;
-
-        for (_, [number]) in rivescript_core::regex::HISTORY_TAG.captures_iter(&pattern.clone()).map(|c| c.extract()) {
-            let mut idx: usize = 1;
-            if !number.is_empty() {
-                idx = number.parse().unwrap();
-            }
-
-            let input = history.input.get(idx-1).unwrap();
-            let reply = history.reply.get(idx-1).unwrap();
+    pattern
+}
 
-            // Format the previous inputs for the regexp engine.
-            let input = &format_message(rs, input, true);
-            let reply = &format_message(rs, reply, true);
-
-            pattern = pattern.replace(
-                &String::from(format!("<input{idx}>")),
-                input,
-            );
-            pattern = pattern.replace(
-                &String::from(format!("<reply{idx}>")),
-                reply,
-            );
-        }
-    }
+// Finish preparing a trigger pattern and compile it into a regular expression.
+pub fn compile_trigger_pattern(rs: &RiveScript, pattern: String) -> Result<Regex, regex::Error> {
+    let mut pattern = pattern;
 
     // Recover escaped Unicode symbols (@ signs).
     if rs.utf8 && pattern.contains(r"\u") {
@@ -505,7 +559,12 @@ pub async fn trigger_regexp(rs: &RiveScript, username: &String, pattern: &String
 
     pattern = String::from(format!(r"^{}$", pattern.trim()));
 
-    Regex::new(&pattern).unwrap_or(Regex::new("").unwrap())
+    Regex::new(&pattern)
+}
+
+/// Normalize the target of an `@` or `{@}` redirect into the message that it matches triggers with.
+pub fn redirect_target(target: &str) -> String {
+    target.trim().to_lowercase()
 }
 
 pub fn strip_nasties(msg: String) -> String {
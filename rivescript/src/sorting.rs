@@ -43,20 +43,9 @@ pub fn sort_triggers(brain: &ast::AST) -> Result<SortedResult, ParseError> {
     // Loop through all the topics.
     for name in brain.topics.keys() {
         let topic = brain.topics.get(name).unwrap();
-        debug!("Analyzing topic {}", name);
-
-        // Collect all of the triggers we're going to worry about, including triggers
-        // belonging to an included or inherited topic.
-        let all_triggers = inheritance::get_topic_triggers(brain, topic, false);
-
-        // Sort these triggers.
-        result.topics.insert(name.to_string(), sort_trigger_set(all_triggers.to_vec()));
-
-        // Get all of the %Previous triggers.
-        let that_triggers = inheritance::get_topic_triggers(brain, topic, true);
-
-        // And sort them, too.
-        result.thats.insert(name.to_string(), sort_trigger_set(that_triggers));
+        let (triggers, thats) = sort_topic(brain, topic);
+        result.topics.insert(name.to_string(), triggers);
+        result.thats.insert(name.to_string(), thats);
     }
 
     // Sort the substitution lists.
@@ -66,6 +55,21 @@ pub fn sort_triggers(brain: &ast::AST) -> Result<SortedResult, ParseError> {
     Ok(result)
 }
 
+/// Sort the triggers of a single topic, returning its sorted triggers and %Previous triggers.
+pub fn sort_topic(brain: &ast::AST, topic: &ast::Topic) -> (Vec<ast::Trigger>, Vec<ast::Trigger>) {
+    debug!("Analyzing topic {}", topic.name);
+
+    // Collect all of the triggers we're going to worry about, including triggers
+    // belonging to an included or inherited topic.
+    let all_triggers = inheritance::get_topic_triggers(brain, topic, false);
+
+    // Get all of the %Previous triggers.
+    let that_triggers = inheritance::get_topic_triggers(brain, topic, true);
+
+    // Sort them both.
+    (sort_trigger_set(all_triggers), sort_trigger_set(that_triggers))
+}
+
 /// Sort a group of triggers in an optimal sorting order.
 fn sort_trigger_set(triggers: Vec<ast::Trigger>) -> Vec<ast::Trigger> {
     // The running sort buffer of triggers as we add them.
@@ -288,7 +292,7 @@ fn word_count(pattern: &str, all: bool) -> isize {
 }
 
 /// Sort a list of strings (like substitutions) from a string:string map.
-fn sort_list(dict: HashMap<String, String>) -> Vec<String> {
+pub fn sort_list(dict: HashMap<String, String>) -> Vec<String> {
 
     // Group the list by number of words.
     let mut track: HashMap<isize, Vec<&String>> = HashMap::new();
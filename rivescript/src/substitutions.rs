@@ -0,0 +1,91 @@
+// Substitutions, run over a message in a single pass.
+//
+// All the patterns of the `! sub` (or `! person`) substitutions are built into
+// one automaton when the triggers are sorted, which finds every place that any
+// of them occurs in a message at once. The rules are the same as running the
+// patterns one at a time: a pattern only matches whole words, longer patterns
+// win over shorter ones, and text that was substituted in isn't substituted
+// again.
+
+use std::collections::HashMap;
+
+use aho_corasick::AhoCorasick;
+use log::warn;
+
+/// A set of substitutions, compiled for matching.
+#[derive(Default)]
+pub struct Substitutions {
+    // None if there are no substitutions.
+    matcher: Option<AhoCorasick>,
+
+    // The replacement for each pattern, with the patterns in the order they're tried.
+    replacements: Vec<String>,
+}
+
+impl Substitutions {
+    /// Compile the substitutions in `map`, where `sorted` has their patterns in the
+    /// order to try them (longest first, from `sorting::sort_list`).
+    pub fn new(map: &HashMap<String, String>, sorted: &[String]) -> Self {
+        let (patterns, replacements): (Vec<&String>, Vec<String>) = sorted
+            .iter()
+            .filter(|pattern| !pattern.is_empty())
+            .filter_map(|pattern| map.get(pattern).map(|result| (pattern, result.clone())))
+            .unzip();
+        if patterns.is_empty() {
+            return Self::default();
+        }
+
+        match AhoCorasick::new(patterns) {
+            Ok(matcher) => Self { matcher: Some(matcher), replacements },
+            Err(e) => {
+                warn!("Couldn't compile the substitutions: {e}");
+                Self::default()
+            },
+        }
+    }
+
+    /// Run the substitutions on a message.
+    pub fn apply(&self, message: &str) -> String {
+        let Some(matcher) = &self.matcher else {
+            return message.to_string();
+        };
+
+        // Find where each pattern occurs as a whole word, as (pattern, start, end).
+        let mut found: Vec<(usize, usize, usize)> = matcher
+            .find_overlapping_iter(message)
+            .filter(|m| is_whole_word(message, m.start(), m.end()))
+            .map(|m| (m.pattern().as_usize(), m.start(), m.end()))
+            .collect();
+        if found.is_empty() {
+            return message.to_string();
+        }
+
+        // Take them in the order the patterns are tried, skipping any that overlap
+        // a part of the message that was already substituted.
+        found.sort_unstable();
+        let mut taken: Vec<(usize, usize, usize)> = Vec::new();
+        for (pattern, start, end) in found {
+            if taken.iter().all(|&(_, s, e)| end <= s || start >= e) {
+                taken.push((pattern, start, end));
+            }
+        }
+
+        taken.sort_unstable_by_key(|&(_, start, _)| start);
+        let mut result = String::with_capacity(message.len());
+        let mut last = 0;
+        for (pattern, start, end) in taken {
+            result.push_str(&message[last..start]);
+            result.push_str(&self.replacements[pattern]);
+            last = end;
+        }
+        result.push_str(&message[last..]);
+        result
+    }
+}
+
+// Whether the match is not in the middle of a word, i.e., it has a non-word
+// character (or the end of the message) on each side.
+fn is_whole_word(message: &str, start: usize, end: usize) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    !message[..start].chars().next_back().is_some_and(is_word) && !message[end..].chars().next().is_some_and(is_word)
+}
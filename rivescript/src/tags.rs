@@ -1,12 +1,32 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 use crate::macros::proxy::Proxy;
+use crate::template::{Format, Math, Node, Template};
 
+use async_recursion::async_recursion;
 use rand::seq::IndexedRandom;
-use shell_words;
+use rivescript_core::sessions::History;
 
 use log::debug;
-use regex::Regex;
+
+// The state for evaluating the tags in one reply.
+struct Context<'a> {
+    rs: &'a crate::RiveScript,
+    username: &'a String,
+    stars: Vec<String>,
+    bot_stars: Vec<String>,
+    step: usize,
+
+    // The reply to put in place of {ok}, in the BEGIN block.
+    ok: Option<&'a str>,
+
+    // The user's history, fetched the first time it's needed.
+    history: Option<History>,
+
+    // The {topic}, {@redirect} and <call> tags waiting for the variable tags to finish.
+    deferred: Deferred,
+}
 
 /// Process RiveScript tags in a reply segment.
 pub async fn process(
@@ -18,295 +38,353 @@ pub async fn process(
     bot_stars: Vec<String>,
     step: usize,
 ) -> String {
-    let mut reply = reply.clone();
-
-    // Format the stars (putting 'undefined' for unused star values).
-    let stars = pad_stars(stars);
-    let bot_stars = pad_stars(bot_stars);
-
-    // Turn (@arrays) into randomized sets.
-    for (m, [name]) in rivescript_core::regex::REPLY_ARRAY.captures_iter(&reply.clone()).map(|c| c.extract()) {
-        let result: String;
-        if let Some(entries) = rs.brain.arrays.get(name) {
-            // Substitute it in for a {random} tag.
-            result = format!("{{random}}{}{{/random}}", entries.join("|"));
-        } else {
-            // Dummy out the literal array (it didn't exist) so we can reconstruct it later.
-            result = String::from(format!("\x00@{name}\x00"));
-        }
+    let mut ctx = Context {
+        rs,
+        username,
+        stars: pad_stars(stars),
+        bot_stars: pad_stars(bot_stars),
+        step,
+        ok: None,
+        history: None,
+        deferred: Deferred::default(),
+    };
+    evaluate(&mut ctx, &template(rs, reply)).await
+}
 
-        reply = reply.replacen(m, &result, 1);
+/// Process RiveScript tags in the reply from the BEGIN block, putting the reply to the
+/// user's message (if there is one) in place of the {ok} tag.
+pub async fn process_begin(rs: &crate::RiveScript, username: &String, begin: &String, ok: Option<&str>) -> String {
+    let mut ctx = Context {
+        rs,
+        username,
+        stars: pad_stars(Vec::new()),
+        bot_stars: pad_stars(Vec::new()),
+        step: 0,
+        ok,
+        history: None,
+        deferred: Deferred::default(),
+    };
+    evaluate(&mut ctx, &template(rs, begin)).await
+}
+
+// The parsed template for a reply. The brain's replies were parsed when the triggers were
+// sorted, so this only has to parse text that came from somewhere else.
+fn template<'a>(rs: &'a crate::RiveScript, reply: &String) -> Cow<'a, Template> {
+    match rs.templates.get(reply) {
+        Some(template) => Cow::Borrowed(template),
+        None => Cow::Owned(Template::parse(reply)),
     }
+}
 
-    // Re-insert dummied out (non-existent) arrays from the above block.
-    reply = rivescript_core::regex::REPLY_ARRAY_DUMMIED
-        .replace_all(&reply, String::from("(@$1)"))
-        .to_string();
-
-    // Tag shortcuts.
-    reply = reply.replace("<person>", "{person}<star>{/person}");
-    reply = reply.replace("<@>", "{@<star>}");
-    reply = reply.replace("<formal>", "{formal}<star>{/formal}");
-    reply = reply.replace("<sentence>", "{sentence}<star>{/sentence}");
-    reply = reply.replace("<uppercase>", "{uppercase}<star>{/uppercase}");
-    reply = reply.replace("<lowercase>", "{lowercase}<star>{/lowercase}");
-
-    // Weight and star tags.
-    reply = rivescript_core::regex::WEIGHT.replace_all(&reply, "").to_string();
-    reply = reply.replace("<star>", "<star1>");
-    reply = reply.replace("<botstar>", "<botstar1>");
-    for i in 1..rivescript_core::MAX_STARS {
-        reply = reply.replace(
-            &String::from(format!("<star{i}>")),
-            stars.get(i).unwrap(),
-        );
-        reply = reply.replace(
-            &String::from(format!("<botstar{i}>")),
-            bot_stars.get(i).unwrap(),
-        );
+// Evaluate a reply template. The tags run in phases, as they always have: first the stars,
+// history, arrays, {random} and string formatting, then the variable tags, then {topic},
+// then the {@redirects}, and the <call>s last of all.
+#[async_recursion]
+async fn evaluate<'a>(ctx: &mut Context<'a>, template: &Template) -> String {
+    let rs = ctx.rs;
+    let username = ctx.username;
+
+    let template = expand(ctx, template).await;
+    let mut reply = run_variables(ctx, &template, true).await;
+    let deferred = std::mem::take(&mut ctx.deferred);
+
+    // Topic setter.
+    for topic in deferred.topics {
+        debug!("Change user topic to: {topic}");
+        rs.sessions.set(username, HashMap::from([
+            ("topic".to_string(), topic),
+        ])).await;
     }
 
-    // <input> and <reply>
-    reply = reply.replace("<input>", "<input1>");
-    reply = reply.replace("<reply>", "<reply1>");
-    let history = rs.sessions.get_history(username).await;
-    for i in 1..rivescript_core::MAX_HISTORY+1 {
-        reply = reply.replace(&String::from(format!("<input{i}>")), &history.input.get(i-1).unwrap_or(&rivescript_core::UNDEFINED.to_string()));
-        reply = reply.replace(&String::from(format!("<reply{i}>")), &history.reply.get(i-1).unwrap_or(&rivescript_core::UNDEFINED.to_string()));
+    // Inline redirector.
+    let mut results: Vec<(String, String)> = Vec::new();
+    for (i, pattern) in deferred.redirects.into_iter().enumerate() {
+        debug!("Inline redirection to: {pattern}");
+        let result = match crate::reply::get_reply(rs, username, &crate::reply::redirect_target(&pattern), false, ctx.step+1).await {
+            Ok(subreply) => subreply,
+            Err(_) => format!("{{@{pattern}}}"),
+        };
+        results.push((placeholder('@', i), result));
     }
 
-    // <id> and escape codes.
-    reply = reply.replace("<id>", username);
-    reply = reply.replace(r"\s", " ");
-    reply = reply.replace(r"\n", "\n");
-    reply = reply.replace(r"\#", "#");
-
-    // {random}
-    for (m, [inner]) in rivescript_core::regex::RANDOM_TAG.captures_iter(&reply.clone()).map(|c| c.extract()) {
-        let random: Vec<String> = inner.split("|").map(|s| s.to_string()).collect();
-        let mut rng = rand::rng();
-        if let Some(selection) = &random[..].choose(&mut rng) {
-            reply = reply.replacen(m, &selection.to_string(), 1);
+    // Finally, handle object macros. A <call> runs on its text after the redirects inside it.
+    for (i, body) in deferred.calls.into_iter().enumerate() {
+        let body = fill(&body, &results);
+        results.push((placeholder('c', i), call(rs, username, &body).await));
+    }
+
+    reply = fill(&reply, &results);
+    reply
+}
+
+// The tags that are set aside until after the variable tags have run.
+#[derive(Default)]
+struct Deferred {
+    topics: Vec<String>,
+    redirects: Vec<String>,
+    calls: Vec<String>,
+}
+
+// The placeholder for the result of a deferred {@redirect} or <call>.
+fn placeholder(kind: char, i: usize) -> String {
+    format!("\x00{kind}{i}\x00")
+}
+
+// Put the results of the deferred tags in place of their placeholders.
+fn fill(text: &str, results: &[(String, String)]) -> String {
+    let mut text = text.to_string();
+    for (placeholder, result) in results {
+        text = text.replace(placeholder, result);
+    }
+    text
+}
+
+// The first phase: fill in everything but the variable tags, {topic}, {@redirect} and <call>.
+// The string formatting tags run before the variable tags, so when one has tags inside it,
+// it formats the text of those tags and the result is parsed again.
+#[async_recursion]
+async fn expand<'a>(ctx: &mut Context<'a>, template: &Template) -> Template {
+    let rs = ctx.rs;
+    let mut expanded = Template::default();
+
+    for node in template.nodes.iter() {
+        match node {
+            Node::Text(text) => expanded.push_text(text),
+            Node::Star(i) => expanded.push_text(&ctx.stars[*i]),
+            Node::BotStar(i) => expanded.push_text(&ctx.bot_stars[*i]),
+            Node::Input(i) | Node::Reply(i) => {
+                if ctx.history.is_none() {
+                    ctx.history = Some(rs.sessions.get_history(ctx.username).await);
+                }
+                let history = ctx.history.as_ref().unwrap();
+                let lines = if matches!(node, Node::Input(_)) { &history.input } else { &history.reply };
+                expanded.push_text(lines.get(i - 1).map(|s| s.as_str()).unwrap_or(rivescript_core::UNDEFINED));
+            },
+            Node::Id => expanded.push_text(ctx.username),
+            Node::Ok => expanded.push_text(ctx.ok.unwrap_or(rivescript_core::TAG_OK)),
+            Node::Array(name) => match rs.brain.arrays.get(name) {
+                Some(items) => {
+                    let mut rng = rand::rng();
+                    if let Some(item) = items.choose(&mut rng) {
+                        expanded.push_text(item);
+                    }
+                },
+                // Leave a missing array as it was written.
+                None => expanded.push_text(&format!("(@{name})")),
+            },
+            Node::Random(choices) => {
+                let choice = {
+                    let mut rng = rand::rng();
+                    choices.choose(&mut rng)
+                };
+                if let Some(choice) = choice {
+                    expanded.append(expand(ctx, choice).await);
+                }
+            },
+            Node::Format(format, body) => {
+                let body = expand(ctx, body).await;
+                match body.text() {
+                    Some(text) => expanded.push_text(&format_value(rs, *format, text)),
+                    None => expanded.append(Template::parse(&format_value(rs, *format, &body.source()))),
+                }
+            },
+            Node::Bot(name, value) => {
+                let value = match value {
+                    Some(value) => Some(expand(ctx, value).await),
+                    None => None,
+                };
+                expanded.push(Node::Bot(expand(ctx, name).await, value));
+            },
+            Node::Env(name, value) => {
+                let value = match value {
+                    Some(value) => Some(expand(ctx, value).await),
+                    None => None,
+                };
+                expanded.push(Node::Env(expand(ctx, name).await, value));
+            },
+            Node::Get(name) => expanded.push(Node::Get(expand(ctx, name).await)),
+            Node::Set(name, value) => expanded.push(Node::Set(expand(ctx, name).await, expand(ctx, value).await)),
+            Node::Math(op, name, value) => expanded.push(Node::Math(*op, expand(ctx, name).await, expand(ctx, value).await)),
+            Node::Topic(topic) => expanded.push(Node::Topic(expand(ctx, topic).await)),
+            Node::Redirect(pattern) => expanded.push(Node::Redirect(expand(ctx, pattern).await)),
+            Node::Call(body) => expanded.push(Node::Call(expand(ctx, body).await)),
         }
     }
 
-    // String formatting tags.
-    reply = run_format_tag(rs, "person", &rivescript_core::regex::PERSON_TAG, &reply);
-    reply = run_format_tag(rs, "formal", &rivescript_core::regex::FORMAL_TAG, &reply);
-    reply = run_format_tag(rs, "sentence", &rivescript_core::regex::SENTENCE_TAG, &reply);
-    reply = run_format_tag(rs, "uppercase", &rivescript_core::regex::UPPERCASE_TAG, &reply);
-    reply = run_format_tag(rs, "lowercase", &rivescript_core::regex::LOWERCASE_TAG, &reply);
-
-    // Handle all variable-related tags with an iterative regexp approach to
-    // allow for nesting of tags in arbitrary ways (think <set a=<get b>>).
-    // Move the <call> tags out of the way first.
-    reply = reply.replace("<call>", "{__call__}");
-    reply = reply.replace("</call>", "{/__call__}");
-    loop {
-        match rivescript_core::regex::ANY_TAG.captures(&reply.clone()) {
-            Some(caps) => {
-                let tag_body = caps.get(1).unwrap().as_str();
-
-                let parts: Vec<String> = tag_body.splitn(2, " ").map(|s| s.to_string()).collect();
-                let tag = parts.get(0).unwrap();
-                let data = parts.get(1).map(|s| s.as_str()).unwrap_or("");
-                let mut insert = String::new();
-
-                // Handle the various types of tags.
-                match tag.as_str() {
-                    "bot" | "env" => {
-                        // <bot> and <env> work similarly.
-                        if data.contains("=") {
-                            // Doing an assignment.
-                            let parts: Vec<String> = data.splitn(2, "=").map(|s| s.to_string()).collect();
-                            let name = parts.get(0).unwrap();
-                            let value = parts.get(1).map(|s| s.as_str()).unwrap_or("");
-
-                            if tag == "bot" {
-                                rs.brain.set_bot_var(name, value);
-                            } else {
-                                rs.brain.set_global(name, value);
-                            }
-                        } else {
-                            if tag == "bot" {
-                                insert = rs.brain.get_bot_var(&data);
-                            } else {
-                                insert = rs.brain.get_global(&data);
-                            }
-                        }
-                    },
-                    "set" => {
-                        // <set> a user variable.
-                        let parts: Vec<String> = data.splitn(2, "=").map(|s| s.to_string()).collect();
-                        let name = parts.get(0).unwrap();
-                        let value = parts.get(1).map(|s| s.as_str()).unwrap_or("");
-                        rs.sessions.set(username, HashMap::from([
-                            (name.to_string(), value.to_string()),
-                        ])).await;
-                    },
-                    "add" | "sub" | "mult" | "div" => {
-                        // Math operator tags.
-                        let parts: Vec<String> = data.splitn(2, "=").map(|s| s.to_string()).collect();
-                        let name = parts.get(0).unwrap();
-                        let value_str = parts.get(1).map(|s| s.as_str()).unwrap_or("");
-
-                        // Initialize a numeric value?
-                        let mut orig_str = rs.sessions.get(username, &name).await;
-                        if orig_str == rivescript_core::UNDEFINED {
-                            orig_str = String::from("0");
-                            rs.sessions.set(username, HashMap::from([
-                                (name.to_string(), orig_str.to_string()),
-                            ])).await;
-                        }
+    expanded
+}
 
-                        // Cast the original to a number.
-                        if let Ok(mut orig_value) = orig_str.parse::<i64>() {
-                            // Cast the operand to a number.
-                            if let Ok(operand) = value_str.parse::<i64>() {
-
-                                // Do the needful.
-                                let mut math_ok = true;
-                                match tag.as_str() {
-                                    "add" => {
-                                        orig_value += operand;
-                                    },
-                                    "sub" => {
-                                        orig_value -= operand;
-                                    },
-                                    "mult" => {
-                                        orig_value *= operand;
-                                    },
-                                    "div" => {
-                                        if operand == 0 {
-                                            insert = format!("[ERR: Can't Divide By Zero]");
-                                            math_ok = false;
-                                        } else {
-                                            orig_value /= operand;
-                                        }
-                                    }
-                                    _ => (),
-                                }
-
-                                // Successful math? Save it back to their storage.
-                                if math_ok {
-                                    rs.sessions.set(username, HashMap::from([
-                                        (name.to_string(), format!("{orig_value}")),
-                                    ])).await;
-                                }
-
-                            } else {
-                                insert = format!("[ERR: Math can't '{tag}' a non-numeric value '{value_str}' to the user variable '{name}']");
-                            }
+// The second phase: run the variable tags, from the inside out. The {topic}, {@redirect} and
+// <call> tags are set aside with `defer`, leaving placeholders for their results; inside a
+// variable tag they're only text.
+#[async_recursion]
+async fn run_variables<'a>(ctx: &mut Context<'a>, template: &Template, defer: bool) -> String {
+    let rs = ctx.rs;
+    let username = ctx.username;
+    let mut reply = String::new();
+
+    for node in template.nodes.iter() {
+        match node {
+            Node::Bot(name, value) | Node::Env(name, value) => {
+                // <bot> and <env> work similarly.
+                let name = run_variables(ctx, name, false).await;
+                let is_bot = matches!(node, Node::Bot(..));
+                match value {
+                    // Doing an assignment.
+                    Some(value) => {
+                        let value = run_variables(ctx, value, false).await;
+                        if is_bot {
+                            rs.brain.set_bot_var(&name, &value);
                         } else {
-                            insert = format!("[ERR: The stored user variable '{name}' contains a non-numeric value '{orig_str}'; can not '{tag}' to it]");
+                            rs.brain.set_global(&name, &value);
                         }
                     },
-                    "get" => {
-                        // <get> a user variable.
-                        insert = rs.sessions.get(username, &data).await;
-                    }
-                    _ => {
-                        // Unrecognized tag; preserve it.
-                        insert = format!("\x00{tag_body}\x01");
-                    },
+                    None if is_bot => reply.push_str(&rs.brain.get_bot_var(&name)),
+                    None => reply.push_str(&rs.brain.get_global(&name)),
                 }
-
-                reply = reply.replacen(format!("<{tag_body}>").as_str(), &insert, 1);
             },
-            None => break,
+            Node::Get(name) => {
+                let name = run_variables(ctx, name, false).await;
+                reply.push_str(&rs.sessions.get(username, &name).await);
+            },
+            Node::Set(name, value) => {
+                let name = run_variables(ctx, name, false).await;
+                let value = run_variables(ctx, value, false).await;
+                rs.sessions.set(username, HashMap::from([(name, value)])).await;
+            },
+            Node::Math(op, name, value) => {
+                let name = run_variables(ctx, name, false).await;
+                let value = run_variables(ctx, value, false).await;
+                reply.push_str(&math(rs, username, *op, &name, &value).await);
+            },
+            Node::Topic(topic) => {
+                let topic = run_variables(ctx, topic, false).await;
+                if defer {
+                    ctx.deferred.topics.push(topic);
+                } else {
+                    reply.push_str(&format!("{{topic={topic}}}"));
+                }
+            },
+            Node::Redirect(pattern) => {
+                let pattern = run_variables(ctx, pattern, false).await;
+                if defer {
+                    reply.push_str(&placeholder('@', ctx.deferred.redirects.len()));
+                    ctx.deferred.redirects.push(pattern);
+                } else {
+                    reply.push_str(&format!("{{@{pattern}}}"));
+                }
+            },
+            Node::Call(body) => {
+                let body = run_variables(ctx, body, defer).await;
+                if defer {
+                    reply.push_str(&placeholder('c', ctx.deferred.calls.len()));
+                    ctx.deferred.calls.push(body);
+                } else {
+                    reply.push_str(&format!("<call>{body}</call>"));
+                }
+            },
+            // Everything else was filled in by the first phase.
+            node => reply.push_str(&Template { nodes: vec![node.clone()] }.source()),
         }
     }
 
-    // Recover mangled HTML-like tags from the above loop.
-    reply = reply.replace("\x00", "<");
-    reply = reply.replace("\x01", ">");
+    reply
+}
 
-    // Topic setter.
-    match rivescript_core::regex::TOPIC_TAG.captures(&reply) {
-        Some(caps) => {
-            let topic = caps.get(1).unwrap().as_str();
-            debug!("Change user topic to: {topic}");
-            rs.sessions.set(username, HashMap::from([
-                ("topic".to_string(), String::from(topic)),
-            ])).await;
-            reply = reply.replace(caps.get_match().as_str(), "");
-        },
-        None => (),
+// Run a math tag (<add>, <sub>, <mult> or <div>) on a user variable, returning the error
+// message if there was one.
+async fn math(rs: &crate::RiveScript, username: &str, op: Math, name: &str, value_str: &str) -> String {
+    let tag = match op {
+        Math::Add => "add",
+        Math::Sub => "sub",
+        Math::Mult => "mult",
+        Math::Div => "div",
+    };
+
+    // Initialize a numeric value?
+    let mut orig_str = rs.sessions.get(username, name).await;
+    if orig_str == rivescript_core::UNDEFINED {
+        orig_str = String::from("0");
+        rs.sessions.set(username, HashMap::from([
+            (name.to_string(), orig_str.to_string()),
+        ])).await;
     }
 
-    // Inline redirector.
-    for (m, [pattern]) in rivescript_core::regex::REDIRECT_TAG.captures_iter(&reply.clone()).map(|c| c.extract()) {
-        debug!("Inline redirection to: {pattern}");
-        let pattern = pattern.trim();
-        match crate::reply::get_reply(&rs, &username, &pattern.to_string(), false, step+1).await {
-            Ok(subreply) => {
-                reply = reply.replace(m, &subreply);
+    // Cast the original to a number.
+    let Ok(mut orig_value) = orig_str.parse::<i64>() else {
+        return format!("[ERR: The stored user variable '{name}' contains a non-numeric value '{orig_str}'; can not '{tag}' to it]");
+    };
+
+    // Cast the operand to a number.
+    let Ok(operand) = value_str.parse::<i64>() else {
+        return format!("[ERR: Math can't '{tag}' a non-numeric value '{value_str}' to the user variable '{name}']");
+    };
+
+    // Do the needful.
+    match op {
+        Math::Add => orig_value += operand,
+        Math::Sub => orig_value -= operand,
+        Math::Mult => orig_value *= operand,
+        Math::Div => {
+            if operand == 0 {
+                return String::from("[ERR: Can't Divide By Zero]");
             }
-            Err(_) => (),
-        };
+            orig_value /= operand;
+        },
     }
 
-    // Finally, handle object macros.
-    reply = reply.replace("{__call__}", "<call>");
-    reply = reply.replace("{/__call__}", "</call>");
-    {
-        let captures: Vec<_> = rivescript_core::regex::CALL_TAG
-            .captures_iter(&reply.clone())
-            .map(|c| {
-                let (full_match, [inner]) = c.extract();
-                (full_match.to_string(), inner.to_string())
-            }).collect();
-
-        for (full_tag, inner_text) in captures {
-            // Parse the arguments.
-            let mut parts = inner_text.splitn(2, ' ');
-            let name = parts.next().unwrap_or("");
-            let value_str = parts.next().unwrap_or("");
-
-            // Parse the arguments with shell-style quoting supported.
-            // If there are unbalanced quotes, split by whitespace instead.
-            let args = shell_words::split(value_str)
-                .unwrap_or_else(|_| value_str.split_whitespace().map(str::to_string).collect());
-
-            // Find the object macro handler/subroutine to call.
-            let sub_result = {
-                let mut proxy = Proxy::new(&rs, username.to_string());
-
-                // A Rust function?
-                if let Some(sub) = rs.subroutines.get(name) {
-                    sub(&mut proxy, args).await
-                } else {
-                    // It's in a foreign programming language. Look up whether
-                    // we have mapped this object name to a registered handler.
-                    if let Some(lang) = rs.object_langs.get(name) {
-                        let handler = rs.macro_handlers.get(lang).unwrap();
-                        handler.call(&mut proxy, name, args).await
-                    } else {
-                        Err(format!("[object {name} not found]"))
-                    }
-                }
-            };
-
-            let replacement = match sub_result {
-                Ok(finisher) => {
-                    if !finisher.staged_user_vars.is_empty() {
-                        rs.sessions.set(username, finisher.staged_user_vars).await;
-                    }
-                    if !finisher.staged_bot_vars.is_empty() {
-                        for (k, v) in finisher.staged_bot_vars {
-                            rs.brain.set_bot_var(&k, &v);
-                        }
-                    }
-                    finisher.output
-                },
-                Err(e) => e,
-            };
+    // Successful math: save it back to their storage.
+    rs.sessions.set(username, HashMap::from([
+        (name.to_string(), format!("{orig_value}")),
+    ])).await;
+    String::new()
+}
 
-            reply = reply.replace(&full_tag, &replacement);
+// Call an object macro, given the text inside its <call> tag, and return its output.
+async fn call(rs: &crate::RiveScript, username: &str, inner_text: &str) -> String {
+    // Parse the arguments.
+    let mut parts = inner_text.splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    let value_str = parts.next().unwrap_or("");
+
+    // Parse the arguments with shell-style quoting supported.
+    // If there are unbalanced quotes, split by whitespace instead.
+    let args = shell_words::split(value_str)
+        .unwrap_or_else(|_| value_str.split_whitespace().map(str::to_string).collect());
+
+    // Find the object macro handler/subroutine to call.
+    let sub_result = {
+        let mut proxy = Proxy::new(rs, username.to_string());
+
+        // A Rust function?
+        if let Some(sub) = rs.subroutines.get(name) {
+            sub(&mut proxy, args).await
+        } else {
+            // It's in a foreign programming language. Look up whether
+            // we have mapped this object name to a registered handler.
+            if let Some(lang) = rs.object_langs.get(name) {
+                let handler = rs.macro_handlers.get(lang).unwrap();
+                handler.call(&proxy, name, args).await
+            } else {
+                Err(format!("[object {name} not found]"))
+            }
         }
-    }
+    };
 
-    reply.clone()
+    match sub_result {
+        Ok(finisher) => {
+            if !finisher.staged_user_vars.is_empty() {
+                rs.sessions.set(username, finisher.staged_user_vars).await;
+            }
+            if !finisher.staged_bot_vars.is_empty() {
+                for (k, v) in finisher.staged_bot_vars {
+                    rs.brain.set_bot_var(&k, &v);
+                }
+            }
+            finisher.output
+        },
+        Err(e) => e,
+    }
 }
 
 // Star tags can hold an index from 1-9 corresponding to regex capture groups. Pad the unused values with 'undefined'.
@@ -332,83 +410,16 @@ fn pad_stars(stars: Vec<String>) -> Vec<String> {
     stars
 }
 
-/// Run substitutions or person substitutions on a string.
-pub fn substitute(map: HashMap<String, String>, sorted: Vec<String>, message: &String) -> String {
-    let mut message = message.clone();
-
-    // Safety check.
-    if map.is_empty() {
-        return message;
-    }
-
-    // Make placeholders each time we substitute something, so we don't process
-    // the same part of the string too many times.
-    let mut ph: Vec<String> = Vec::new();
-    let mut pi = 0;
-
-    for pattern in sorted {
-
-        if !message.contains(&pattern) {
-            continue;
-        }
-
-        let result = map.get(&pattern).unwrap();
-        let qm = regex::escape(&pattern);
-
-        // Make a placeholder.
-        ph.push(result.to_string());
-        let placeholder = format!("\x00{pi}\x00");
-        pi = pi + 1;
-
-        // Run substitutions.
-        message = Regex::new(&String::from(format!(r"^{qm}$")))
-            .unwrap()
-            .replace_all(&message, &placeholder)
-            .to_string();
-        message = Regex::new(&String::from(format!(r"^{qm}(\W+)")))
-            .unwrap()
-            .replace_all(&message, String::from(format!("{}$1", &placeholder)))
-            .to_string();
-        message = Regex::new(&String::from(format!(r"(\W+){qm}(\W+)")))
-            .unwrap()
-            .replace_all(&message, String::from(format!("$1{}$2", &placeholder)))
-            .to_string();
-        message = Regex::new(&String::from(format!(r"(\W+){qm}$")))
-            .unwrap()
-            .replace_all(&message, String::from(format!("$1{}", &placeholder)))
-            .to_string();
-    }
-
-    // Convert the placeholders back in.
-    for (m, [id]) in rivescript_core::regex::PLACEHOLDER.captures_iter(&message.clone()).map(|c| c.extract()) {
-        let id: usize = id.parse().unwrap();
-        if let Some(result) = ph.get(id) {
-            message = message.replace(
-                m,
-                result,
-            );
-        }
-    }
-
-    message
-}
-
-/// Process string format tags (uppercase, lowercase, formal, sentence).
-fn run_format_tag(rs: &crate::RiveScript, tag: &str, re: &regex::Regex, reply: &String) -> String {
-    let mut message = reply.clone();
-
-    for (m, [inner]) in re.captures_iter(&reply).map(|c| c.extract()) {
-        // Person substitutions?
-        if tag == "person" {
-            let result = substitute(rs.brain.person.clone(), rs.sorted_person.clone(), &String::from(inner));
-            message = message.replace(m, &result);
-            continue;
-        }
-
-        message = message.replace(m, &format_string(tag, &String::from(inner)));
+// Run a string formatting tag on its text.
+fn format_value(rs: &crate::RiveScript, format: Format, value: &str) -> String {
+    let value = value.to_string();
+    match format {
+        Format::Person => rs.person_matcher.apply(&value),
+        Format::Formal => format_string("formal", &value),
+        Format::Sentence => format_string("sentence", &value),
+        Format::Uppercase => format_string("uppercase", &value),
+        Format::Lowercase => format_string("lowercase", &value),
     }
-
-    message
 }
 
 /// Format a string (uppercase, lowercase, sentence, formal).
@@ -0,0 +1,450 @@
+// Reply templates, parsed once ahead of time.
+//
+// The text of each reply, condition and redirect is parsed into a tree of
+// nodes when the triggers are sorted, and `tags::process` evaluates the tree
+// each time the reply is given. Parsing doesn't depend on the brain or the
+// user: arrays, variables and stars are all looked up during evaluation.
+
+use std::collections::HashMap;
+
+use crate::ast::AST;
+
+/// A parsed reply template. Its nodes are evaluated in order, and their output joined.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Template {
+    pub nodes: Vec<Node>,
+}
+
+/// The string formatting tags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Person,
+    Formal,
+    Sentence,
+    Uppercase,
+    Lowercase,
+}
+
+/// The math tags for user variables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Math {
+    Add,
+    Sub,
+    Mult,
+    Div,
+}
+
+/// One piece of a reply template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+
+    /// `<star1>` through `<star9>`, and `<botstar1>` through `<botstar9>`. `<star>` is `<star1>`.
+    Star(usize),
+    BotStar(usize),
+
+    /// `<input1>` through `<input9>`, and `<reply1>` through `<reply9>`.
+    Input(usize),
+    Reply(usize),
+    Id,
+
+    /// `{ok}` in the BEGIN block, which stands for the reply to the user's message.
+    Ok,
+
+    /// `(@name)`, a random item from an array.
+    Array(String),
+
+    /// `{random}a|b{/random}`, holding each of the choices.
+    Random(Vec<Template>),
+    Format(Format, Template),
+
+    /// `<bot name>` gets a bot variable, and `<bot name=value>` sets one. `<env>` is the
+    /// same for global variables.
+    Bot(Template, Option<Template>),
+    Env(Template, Option<Template>),
+    Get(Template),
+    Set(Template, Template),
+    Math(Math, Template, Template),
+    Topic(Template),
+
+    /// `{@trigger}`, the reply to another trigger.
+    Redirect(Template),
+    Call(Template),
+}
+
+impl Template {
+    /// Parse the text of a reply. Anything that isn't a valid tag is kept as text.
+    pub fn parse(text: &str) -> Self {
+        let mut parser = TemplateParser { text, pos: 0 };
+        parser.parse_until(&[]).0
+    }
+
+    /// The text of the template, if it's nothing but text.
+    pub fn text(&self) -> Option<&str> {
+        match &self.nodes[..] {
+            [] => Some(""),
+            [Node::Text(text)] => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Write the template back out as the text of a reply.
+    pub fn source(&self) -> String {
+        let mut out = String::new();
+        for node in self.nodes.iter() {
+            node.write_source(&mut out);
+        }
+        out
+    }
+
+    pub(crate) fn push(&mut self, node: Node) {
+        match node {
+            Node::Text(text) => self.push_text(&text),
+            node => self.nodes.push(node),
+        }
+    }
+
+    pub(crate) fn push_text(&mut self, text: &str) {
+        if let Some(Node::Text(last)) = self.nodes.last_mut() {
+            last.push_str(text);
+        } else if !text.is_empty() {
+            self.nodes.push(Node::Text(text.to_string()));
+        }
+    }
+
+    pub(crate) fn append(&mut self, other: Template) {
+        for node in other.nodes {
+            self.push(node);
+        }
+    }
+
+    // Split the template at its first '=' that isn't inside a tag, for <set name=value>.
+    fn split_assignment(self) -> (Template, Option<Template>) {
+        let mut name = Template::default();
+        let mut nodes = self.nodes.into_iter();
+        while let Some(node) = nodes.next() {
+            if let Node::Text(text) = &node
+                && let Some((left, right)) = text.split_once('=')
+            {
+                name.push_text(left);
+                let mut value = Template::default();
+                value.push_text(right);
+                nodes.for_each(|node| value.push(node));
+                return (name, Some(value));
+            }
+            name.push(node);
+        }
+        (name, None)
+    }
+}
+
+impl Format {
+    fn tag(self) -> &'static str {
+        match self {
+            Format::Person => "person",
+            Format::Formal => "formal",
+            Format::Sentence => "sentence",
+            Format::Uppercase => "uppercase",
+            Format::Lowercase => "lowercase",
+        }
+    }
+}
+
+impl Node {
+    fn write_source(&self, out: &mut String) {
+        // A tag with data, like <set name=value>.
+        let tag = |out: &mut String, tag: &str, name: &Template, value: Option<&Template>| {
+            out.push_str(&format!("<{tag} {}", name.source()));
+            if let Some(value) = value {
+                out.push_str(&format!("={}", value.source()));
+            }
+            out.push('>');
+        };
+
+        match self {
+            Node::Text(text) => out.push_str(text),
+            Node::Star(i) => out.push_str(&format!("<star{i}>")),
+            Node::BotStar(i) => out.push_str(&format!("<botstar{i}>")),
+            Node::Input(i) => out.push_str(&format!("<input{i}>")),
+            Node::Reply(i) => out.push_str(&format!("<reply{i}>")),
+            Node::Id => out.push_str("<id>"),
+            Node::Ok => out.push_str("{ok}"),
+            Node::Array(name) => out.push_str(&format!("(@{name})")),
+            Node::Random(choices) => {
+                let choices: Vec<String> = choices.iter().map(|choice| choice.source()).collect();
+                out.push_str(&format!("{{random}}{}{{/random}}", choices.join("|")));
+            },
+            Node::Format(format, body) => {
+                out.push_str(&format!("{{{0}}}{1}{{/{0}}}", format.tag(), body.source()));
+            },
+            Node::Bot(name, value) => tag(out, "bot", name, value.as_ref()),
+            Node::Env(name, value) => tag(out, "env", name, value.as_ref()),
+            Node::Get(name) => tag(out, "get", name, None),
+            Node::Set(name, value) => tag(out, "set", name, Some(value)),
+            Node::Math(op, name, value) => {
+                let op = match op {
+                    Math::Add => "add",
+                    Math::Sub => "sub",
+                    Math::Mult => "mult",
+                    Math::Div => "div",
+                };
+                tag(out, op, name, Some(value));
+            },
+            Node::Topic(topic) => out.push_str(&format!("{{topic={}}}", topic.source())),
+            Node::Redirect(pattern) => out.push_str(&format!("{{@{}}}", pattern.source())),
+            Node::Call(body) => out.push_str(&format!("<call>{}</call>", body.source())),
+        }
+    }
+}
+
+/// Parse the templates for all the replies, conditions and redirects in the brain,
+/// keyed by their text.
+pub fn compile(brain: &AST) -> HashMap<String, Template> {
+    let mut templates = HashMap::new();
+    let mut add = |text: &String| {
+        if !templates.contains_key(text) {
+            templates.insert(text.clone(), Template::parse(text));
+        }
+    };
+
+    for trigger in brain.topics.values().flat_map(|topic| topic.triggers.iter()) {
+        trigger.reply.iter().for_each(&mut add);
+        for condition in trigger.condition.iter() {
+            add(&condition.left);
+            add(&condition.right);
+            add(&condition.reply);
+        }
+        if !trigger.redirect.is_empty() {
+            add(&trigger.redirect);
+        }
+    }
+
+    templates
+}
+
+struct TemplateParser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> TemplateParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    // Consume `prefix` if the remaining text starts with it.
+    fn eat(&mut self, prefix: &str) -> bool {
+        if self.rest().starts_with(prefix) {
+            self.pos += prefix.len();
+            return true;
+        }
+        false
+    }
+
+    // Parse until one of the terminators, which is consumed and returned, or else until
+    // the end of the text.
+    fn parse_until(&mut self, terminators: &[&'static str]) -> (Template, Option<&'static str>) {
+        let mut template = Template::default();
+        while let Some(c) = self.rest().chars().next() {
+            if let Some(terminator) = terminators.iter().find(|t| self.rest().starts_with(**t)) {
+                self.pos += terminator.len();
+                return (template, Some(terminator));
+            }
+
+            let start = self.pos;
+            let parsed = match c {
+                '<' => self.tag(&mut template),
+                '{' => self.brace(&mut template),
+                '(' => self.array(&mut template),
+                '\\' => self.escape(&mut template),
+                _ => false,
+            };
+            if !parsed {
+                self.pos = start + c.len_utf8();
+                template.push_text(&self.text[start..self.pos]);
+            }
+        }
+        (template, None)
+    }
+
+    // <tags>, with the parser just before the '<'.
+    fn tag(&mut self, template: &mut Template) -> bool {
+        self.pos += 1;
+        let rest = self.rest();
+        let end = rest.find([' ', '>']).unwrap_or(rest.len());
+        let name = &rest[..end];
+
+        // Tags without any data.
+        if rest[end..].starts_with('>') {
+            let star = || Template { nodes: vec![Node::Star(1)] };
+            let node = match name {
+                "star" => Some(Node::Star(1)),
+                "botstar" => Some(Node::BotStar(1)),
+                "input" => Some(Node::Input(1)),
+                "reply" => Some(Node::Reply(1)),
+                "id" => Some(Node::Id),
+                "@" => Some(Node::Redirect(star())),
+                "person" => Some(Node::Format(Format::Person, star())),
+                "formal" => Some(Node::Format(Format::Formal, star())),
+                "sentence" => Some(Node::Format(Format::Sentence, star())),
+                "uppercase" => Some(Node::Format(Format::Uppercase, star())),
+                "lowercase" => Some(Node::Format(Format::Lowercase, star())),
+                _ => numbered(name),
+            };
+            if let Some(node) = node {
+                self.pos += end + 1;
+                template.push(node);
+                return true;
+            }
+
+            if name == "call" {
+                self.pos += end + 1;
+                return match self.parse_until(&["</call>"]) {
+                    (body, Some(_)) => {
+                        template.push(Node::Call(body));
+                        true
+                    },
+                    _ => false,
+                };
+            }
+        }
+
+        // Tags with data, like <get name> or <set name=value>, which can have other tags inside.
+        if rest[end..].starts_with(' ') && matches!(name, "bot" | "env" | "get" | "set" | "add" | "sub" | "mult" | "div") {
+            self.pos += end + 1;
+            let (body, Some(_)) = self.parse_until(&[">"]) else {
+                return false;
+            };
+            let (variable, value) = body.split_assignment();
+            let node = match (name, value) {
+                ("bot", value) => Node::Bot(variable, value),
+                ("env", value) => Node::Env(variable, value),
+                ("get", None) => Node::Get(variable),
+                ("get", Some(value)) => {
+                    let mut name = variable;
+                    name.push_text("=");
+                    name.append(value);
+                    Node::Get(name)
+                },
+                ("set", value) => Node::Set(variable, value.unwrap_or_default()),
+                ("add", value) => Node::Math(Math::Add, variable, value.unwrap_or_default()),
+                ("sub", value) => Node::Math(Math::Sub, variable, value.unwrap_or_default()),
+                ("mult", value) => Node::Math(Math::Mult, variable, value.unwrap_or_default()),
+                (_, value) => Node::Math(Math::Div, variable, value.unwrap_or_default()),
+            };
+            template.push(node);
+            return true;
+        }
+
+        // Anything else (like HTML) is kept as it is, but the tags inside it still work.
+        match self.parse_until(&[">"]) {
+            (inner, Some(_)) => {
+                template.push_text("<");
+                template.append(inner);
+                template.push_text(">");
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // {tags}, with the parser just before the '{'.
+    fn brace(&mut self, template: &mut Template) -> bool {
+        if self.eat("{ok}") {
+            template.push(Node::Ok);
+            return true;
+        }
+
+        // {weight} is only used to pick the reply, so it's dropped.
+        if self.eat("{weight=") {
+            let digits = self.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(self.rest().len());
+            self.pos += digits;
+            return digits > 0 && self.eat("}");
+        }
+
+        if self.eat("{random}") {
+            let mut choices = Vec::new();
+            loop {
+                match self.parse_until(&["|", "{/random}"]) {
+                    (choice, Some("|")) => choices.push(choice),
+                    (choice, Some(_)) => {
+                        choices.push(choice);
+                        template.push(Node::Random(choices));
+                        return true;
+                    },
+                    (_, None) => return false,
+                }
+            }
+        }
+
+        for (format, open, close) in [
+            (Format::Person, "{person}", "{/person}"),
+            (Format::Formal, "{formal}", "{/formal}"),
+            (Format::Sentence, "{sentence}", "{/sentence}"),
+            (Format::Uppercase, "{uppercase}", "{/uppercase}"),
+            (Format::Lowercase, "{lowercase}", "{/lowercase}"),
+        ] {
+            if self.eat(open) {
+                return match self.parse_until(&[close]) {
+                    (body, Some(_)) => {
+                        template.push(Node::Format(format, body));
+                        true
+                    },
+                    _ => false,
+                };
+            }
+        }
+
+        let topic = self.eat("{topic=");
+        if topic || self.eat("{@") {
+            return match self.parse_until(&["}"]) {
+                (body, Some(_)) => {
+                    template.push(if topic { Node::Topic(body) } else { Node::Redirect(body) });
+                    true
+                },
+                _ => false,
+            };
+        }
+
+        false
+    }
+
+    // (@array), with the parser just before the '('.
+    fn array(&mut self, template: &mut Template) -> bool {
+        if !self.eat("(@") {
+            return false;
+        }
+        let rest = self.rest();
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if end == 0 || !rest[end..].starts_with(')') {
+            return false;
+        }
+        template.push(Node::Array(rest[..end].to_string()));
+        self.pos += end + 1;
+        true
+    }
+
+    // Escape codes, with the parser just before the backslash.
+    fn escape(&mut self, template: &mut Template) -> bool {
+        for (code, text) in [(r"\s", " "), (r"\n", "\n"), (r"\#", "#")] {
+            if self.eat(code) {
+                template.push_text(text);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// The numbered tags: <star2>, <botstar2>, <input2> and <reply2>.
+fn numbered(name: &str) -> Option<Node> {
+    let (kind, number) = name.split_at(name.find(|c: char| c.is_ascii_digit())?);
+    let number: usize = number.parse().ok()?;
+    match kind {
+        "star" if (1..=rivescript_core::MAX_STARS).contains(&number) => Some(Node::Star(number)),
+        "botstar" if (1..=rivescript_core::MAX_STARS).contains(&number) => Some(Node::BotStar(number)),
+        "input" if (1..=rivescript_core::MAX_HISTORY).contains(&number) => Some(Node::Input(number)),
+        "reply" if (1..=rivescript_core::MAX_HISTORY).contains(&number) => Some(Node::Reply(number)),
+        _ => None,
+    }
+}
@@ -63,4 +63,1648 @@ mod tests {
         assert!(expect_array.iter().all(|item| actual_array.contains(item)));
         assert!(actual_array.iter().all(|item| expect_array.contains(item)));
     }
+
+    #[test]
+    fn test_parse_error_location() {
+        use rivescript_core::errors::{ErrorCode, Severity};
+
+        let parser = Parser::new();
+        let source = String::from("+ hello bot\n- Hello human.\n\n  ! version = 3.0\n");
+        let err = parser.parse("brain.rive", source).unwrap_err();
+
+        let diagnostic = &err.diagnostics()[0];
+        assert_eq!(diagnostic.filename, "brain.rive");
+        assert_eq!(diagnostic.line, 4);
+        assert_eq!(diagnostic.column, 3);
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.code, ErrorCode::UnsupportedVersion);
+        assert_eq!(diagnostic.text, "! version = 3.0");
+
+        // It should surface through RiveScript too.
+        let mut bot = crate::RiveScript::new();
+        let err = bot.stream(String::from("! version = two")).unwrap_err();
+        let err = err.downcast_ref::<crate::ParseError>().unwrap();
+        assert_eq!(err.diagnostics()[0].code, ErrorCode::BadVersion);
+        assert_eq!(err.to_string(), "stream():1:1: error [bad-version]: Didn't parse version string; was it a properly formatted number?");
+    }
+
+    #[tokio::test]
+    async fn test_inline_comments() {
+        let parser = Parser::new();
+        let source = String::from(
+            "! var name = Aiden   // The bot's name.\n\
+             + hello bot // A greeting.\n\
+             - Hello, human!    // With spaces before the comment.\n\
+             - Hi!  // Shorter.\n",
+        );
+        let ast = parser.parse("comments.rive", source.clone()).unwrap();
+        assert_eq!(ast.get_bot_var("name"), "Aiden");
+        let trigger = &ast.topics.get("random").unwrap().triggers[0];
+        assert_eq!(trigger.trigger, "hello bot");
+        assert_eq!(trigger.reply, vec!["Hello, human!", "Hi!"]);
+
+        // The whitespace doesn't end up in the bot's replies.
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from("> begin\n+ request // Tested first.\n- {ok}    // Get a real reply.\n< begin\n\n"))
+            .unwrap();
+        bot.stream(source).unwrap();
+        bot.sort_triggers();
+        let reply = bot.reply("user", "hello bot").await.unwrap();
+        assert!(reply == "Hello, human!" || reply == "Hi!", "got {reply:?}");
+    }
+
+    #[test]
+    fn test_parser_strict_mode() {
+        use rivescript_core::errors::{ErrorCode, Severity};
+
+        let source = String::from(
+            r"
+            ! bogus name = value
+
+            + hello bot
+            * <get name> == => Missing the right side.
+            - Hello human.
+
+            > fish
+            < fish
+            ",
+        );
+
+        // In lenient mode, the warnings come back alongside the AST.
+        let mut parser = Parser::new();
+        let (ast, warnings) = parser.parse_with_warnings("lenient.rive", source.clone()).unwrap();
+        assert_eq!(ast.topics.get("random").unwrap().triggers.len(), 1);
+        let codes: Vec<ErrorCode> = warnings.iter().map(|w| w.code).collect();
+        assert_eq!(codes, vec![ErrorCode::UnknownDefinition, ErrorCode::MalformedCondition, ErrorCode::UnknownLabel]);
+        assert!(warnings.iter().all(|w| w.severity == Severity::Warning));
+        assert_eq!(warnings[1].line, 5);
+
+        // In strict mode, they reject the whole document.
+        parser.strict = true;
+        let err = parser.parse_with_warnings("strict.rive", source.clone()).unwrap_err();
+        assert_eq!(err.diagnostics().len(), 3);
+        assert!(err.diagnostics().iter().all(|d| d.severity == Severity::Error));
+
+        // And the same through RiveScript.
+        let mut bot = crate::RiveScript::new();
+        bot.stream(source.clone()).unwrap();
+        assert_eq!(bot.warnings().len(), 3);
+
+        let mut bot = crate::RiveScript::new();
+        bot.strict = true;
+        assert!(bot.stream(source).is_err());
+    }
+
+    #[test]
+    fn test_ast_locations() {
+        let parser = Parser::new();
+        let source = String::from(
+            "// A test brain.
++ hello bot
+- Hello,
+^ human!
+
+> topic sports
+  + *
+  * <get name> != undefined => Let's talk about sports, <get name>.
+  - Let's talk about sports.
+< topic
+
+> object echo javascript
+    return args.join(' ');
+< object
+",
+        );
+        let ast = parser.parse("brain.rive", source).unwrap();
+
+        let hello = &ast.topics.get("random").unwrap().triggers[0];
+        assert_eq!(hello.location.to_string(), "brain.rive:2");
+        assert_eq!((hello.location.start, hello.location.end), (2, 4));
+
+        let sports = ast.topics.get("sports").unwrap();
+        assert_eq!((sports.location.start, sports.location.end), (6, 10));
+        assert_eq!((sports.triggers[0].location.start, sports.triggers[0].location.end), (7, 9));
+        assert_eq!(sports.triggers[0].condition[0].location.start, 8);
+
+        let echo = ast.objects.get("echo").unwrap();
+        assert_eq!((echo.location.start, echo.location.end), (12, 14));
+
+        // Locations survive merging and sorting.
+        let mut bot = crate::RiveScript::new();
+        bot.load_file("../eg/brain/rpg.rive").unwrap();
+        bot.sort_triggers();
+        let sorted = bot.sorted_topics.get("puzzle1").unwrap();
+        assert!(sorted.iter().all(|t| t.location.filename == "../eg/brain/rpg.rive" && t.location.start > 0));
+    }
+
+    #[test]
+    fn test_object_macro_source() {
+        let parser = Parser::new();
+        let source = String::from(
+            "> object fib python\n    def fib(n):\n        if n < 2:\n            return n\n\n        return fib(n-1) + fib(n-2)\n    return str(fib(int(args[0])))\n< object\n\n> object cmp javascript\n\tif (args[0] < object.size) {\n\t\treturn '<object>';\n\t}\n\t< object // end of cmp\n\n+ fib *\n- <call>fib <star></call>\n",
+        );
+        let ast = parser.parse("test.rive", source).unwrap();
+
+        // Indentation and blank lines are kept.
+        let fib = ast.objects.get("fib").unwrap();
+        assert_eq!(fib.code, vec![
+            "    def fib(n):",
+            "        if n < 2:",
+            "            return n",
+            "",
+            "        return fib(n-1) + fib(n-2)",
+            "    return str(fib(int(args[0])))",
+        ]);
+        assert_eq!(fib.location.start, 1);
+        assert_eq!(fib.location.end, 8);
+        assert_eq!(fib.lines().nth(4), Some((6, "        return fib(n-1) + fib(n-2)")));
+
+        // Only a real terminator line ends the object.
+        let cmp = ast.objects.get("cmp").unwrap();
+        assert_eq!(cmp.code, vec!["\tif (args[0] < object.size) {", "\t\treturn '<object>';", "\t}"]);
+        assert_eq!(ast.topics.get("random").unwrap().triggers[0].trigger, "fib *");
+    }
+
+    #[test]
+    fn test_deparse_round_trip() {
+        use rivescript_core::deparse::deparse;
+
+        let parser = Parser::new();
+        let source = String::from(
+            r"
+            ! version = 2.0
+            ! local concat = newline
+            ! global depth = 64
+            ! var name = RiveScript
+            ^ Test Robot
+            ! sub what's = what is
+            ! person i am = you are
+            ! array colors = red blue light\sblue
+
+            > begin
+                + request
+                - {ok}
+            < begin
+
+            + hello bot
+            - Hello, human!
+            - Hi there!{weight=2}
+
+            + my name is *
+            % what is your name
+            * <get name> == <star> => I know.
+            - <set name=<formal>>Nice to meet you.
+            ^ How are you?
+
+            + hi
+            @ hello bot
+
+            > topic sports includes chatter inherits random
+                + *
+                - Let's talk about sports.
+            < topic
+
+            > topic chatter
+            < topic
+
+            > object reverse javascript
+                return args.join(' ').split('').reverse().join('');
+            < object
+            ",
+        );
+        let mut ast = parser.parse("test.rive", source).unwrap();
+
+        // Values with " // " in them, which mustn't be cut off as inline comments on the way
+        // back in, and an empty array, which can't be written and is left out.
+        ast.set_bot_var("url", "http:// example // com");
+        ast.arrays.insert("slashes".to_string(), vec!["a".to_string(), "//".to_string(), "b c".to_string()]);
+        ast.arrays.insert("empty".to_string(), Vec::new());
+        ast.topics.get_mut("random").unwrap().triggers[0].reply.push("Comments start with // in RiveScript.".to_string());
+
+        let code = deparse(&ast);
+
+        // Parsing the deparsed code again gives an equivalent AST.
+        let again = parser.parse("deparsed.rive", code.clone()).unwrap();
+        assert_eq!(deparse(&again), code);
+        assert_eq!(again.version, 2.0);
+        assert_eq!(again.get_bot_var("name"), "RiveScript\nTest Robot");
+        assert_eq!(again.arrays.get("colors").unwrap(), &vec!["red", "blue", "light blue"]);
+        assert_eq!(again.subs.get("what's").unwrap(), "what is");
+        assert_eq!(again.get_bot_var("url"), "http:// example // com");
+        assert_eq!(again.arrays.get("slashes").unwrap(), &vec!["a", "//", "b c"]);
+        assert!(!again.arrays.contains_key("empty"));
+        assert!(again.has_begin_block());
+        assert!(again.topics.get("chatter").unwrap().triggers.is_empty());
+
+        let sports = again.topics.get("sports").unwrap();
+        assert!(sports.includes.contains_key("chatter"));
+        assert!(sports.inherits.contains_key("random"));
+
+        let random = again.topics.get("random").unwrap();
+        assert_eq!(random.triggers.len(), 3);
+        assert_eq!(random.triggers[1].previous, "what is your name");
+        assert_eq!(random.triggers[1].condition[0].right, "<star>");
+        assert_eq!(random.triggers[1].reply[0], "<set name=<formal>>Nice to meet you.\nHow are you?");
+        assert_eq!(random.triggers[2].redirect, "hello bot");
+        assert_eq!(random.triggers[0].reply[2], "Comments start with // in RiveScript.");
+        assert_eq!(again.objects.get("reverse").unwrap().language, "javascript");
+
+        // The example brain survives a round trip too.
+        let mut bot = crate::RiveScript::new();
+        bot.load_directory("../eg/brain").unwrap();
+        let code = bot.deparse();
+        let again = parser.parse("brain.rive", code.clone()).unwrap();
+        assert_eq!(deparse(&again), code);
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn test_json_round_trip() {
+        let mut bot = crate::RiveScript::new();
+        bot.load_directory("../eg/brain").unwrap();
+        let json = bot.to_json().unwrap();
+
+        let mut copy = crate::RiveScript::new();
+        copy.load_json(&json).unwrap();
+        assert_eq!(copy.deparse(), bot.deparse());
+
+        // Source locations come along too.
+        let ast: rivescript_core::ast::AST = serde_json::from_str(&json).unwrap();
+        assert!(ast.topics.get("random").unwrap().triggers[0].location.is_known());
+
+        // The copy is a working bot.
+        copy.sort_triggers();
+        assert!(!copy.reply("user", "hello bot").await.unwrap().is_empty());
+
+        // Locations may be left out, e.g. by an authoring tool.
+        let mut bot = crate::RiveScript::new();
+        bot.load_json(
+            r#"{
+                "version": 2.0, "globals": {}, "vars": {"name": "Aiden"}, "subs": {}, "person": {},
+                "arrays": {}, "objects": {},
+                "topics": {"random": {"name": "random", "includes": {}, "inherits": {}, "triggers": [
+                    {"trigger": "who are you", "reply": ["I am <bot name>."], "condition": [],
+                     "redirect": "", "previous": ""}
+                ]}}
+            }"#,
+        )
+        .unwrap();
+        bot.sort_triggers();
+        assert_eq!(bot.reply("user", "who are you").await.unwrap(), "I am Aiden.");
+        assert!(bot.load_json("{not json").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn test_brain_cache() {
+        let dir = std::env::temp_dir().join(format!("rivescript-test-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("brain.bin");
+        let path = path.to_str().unwrap();
+
+        let mut bot = crate::RiveScript::new();
+        bot.load_directory("../eg/brain").unwrap();
+        bot.sort_triggers();
+        bot.save_cache(path).unwrap();
+        assert_eq!(bot.source_hash(), bot.hash_sources(&["../eg/brain"], &Default::default()).unwrap());
+
+        // The cached brain is ready to reply without sorting.
+        let mut copy = crate::RiveScript::new();
+        copy.load_cache(path).unwrap();
+        assert_eq!(copy.deparse(), bot.deparse());
+        assert!(!copy.reply("user", "hello bot").await.unwrap().is_empty());
+
+        // The cache is only used when it matches the sources.
+        let mut copy = crate::RiveScript::new();
+        assert!(copy.load_cache_if_fresh(path, &["../eg/brain"], &Default::default()).unwrap());
+        let mut copy = crate::RiveScript::new();
+        assert!(!copy.load_cache_if_fresh(path, &["../eg/brain/begin.rive"], &Default::default()).unwrap());
+        assert!(!copy.load_cache_if_fresh("/nonexistent/brain.bin", &["../eg/brain"], &Default::default()).unwrap());
+
+        // Directories are listed with the same options they were loaded with.
+        let sources = dir.join("sources");
+        std::fs::create_dir_all(sources.join("nested")).unwrap();
+        std::fs::write(sources.join("begin.rive"), "! var name = Aiden\n").unwrap();
+        std::fs::write(sources.join("nested/hello.rive"), "+ hello bot\n- Hello, human!\n").unwrap();
+        let sources = sources.to_str().unwrap();
+        let options = crate::DirectoryOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let recursive = dir.join("recursive.bin");
+        let recursive = recursive.to_str().unwrap();
+        let mut bot = crate::RiveScript::new();
+        bot.load_directory_with(sources, &options).unwrap();
+        bot.sort_triggers();
+        bot.save_cache(recursive).unwrap();
+        let mut copy = crate::RiveScript::new();
+        assert!(!copy.load_cache_if_fresh(recursive, &[sources], &Default::default()).unwrap());
+        assert!(copy.load_cache_if_fresh(recursive, &[sources], &options).unwrap());
+
+        // Other files are rejected.
+        let bogus = dir.join("bogus.bin");
+        std::fs::write(&bogus, "+ hello bot\n- Hello human!\n").unwrap();
+        let err = copy.load_cache(bogus.to_str().unwrap()).unwrap_err();
+        assert!(err.downcast_ref::<crate::CacheError>().is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_brain_cache_includes() {
+        let dir = std::env::temp_dir().join(format!("rivescript-test-cache-includes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.rive");
+        let main = main.to_str().unwrap();
+        let included = dir.join("greetings.rive");
+        std::fs::write(main, "! include greetings.rive\n+ bye\n- Goodbye!\n").unwrap();
+        std::fs::write(&included, "+ hello bot\n- Hello, human!\n").unwrap();
+        let path = dir.join("brain.bin");
+        let path = path.to_str().unwrap();
+
+        let mut bot = crate::RiveScript::new();
+        bot.includes = true;
+        bot.load_file(main).unwrap();
+        bot.sort_triggers();
+        bot.save_cache(path).unwrap();
+        assert_eq!(bot.source_hash(), bot.hash_sources(&[main], &Default::default()).unwrap());
+
+        // The parser settings are part of the hash.
+        let mut copy = crate::RiveScript::new();
+        assert_ne!(copy.hash_sources(&[main], &Default::default()).unwrap(), bot.source_hash());
+        assert!(!copy.load_cache_if_fresh(path, &[main], &Default::default()).unwrap());
+        copy.includes = true;
+        assert!(copy.load_cache_if_fresh(path, &[main], &Default::default()).unwrap());
+
+        // Changing only the included file makes the cache stale.
+        std::fs::write(&included, "+ hello bot\n- Hi there!\n").unwrap();
+        let mut copy = crate::RiveScript::new();
+        copy.includes = true;
+        assert_ne!(copy.hash_sources(&[main], &Default::default()).unwrap(), bot.source_hash());
+        assert!(!copy.load_cache_if_fresh(path, &[main], &Default::default()).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_parse_reader() {
+        use rivescript_core::deparse::deparse;
+        use rivescript_core::errors::ErrorCode;
+
+        let parser = Parser::new();
+        let source = std::fs::read_to_string("../eg/brain/eliza.rive").unwrap()
+            + "\n! local concat = space\n+ tell me a story\n- Once upon\n\n^ a time.\n";
+        let (expect, _) = parser.parse_with_warnings("eliza.rive", source.clone()).unwrap();
+
+        // Streaming from a reader gives the same AST.
+        let (ast, _) = parser.parse_reader("eliza.rive", source.as_bytes()).unwrap();
+        assert_eq!(deparse(&ast), deparse(&expect));
+        let (ast, _) = parser.parse_async_reader("eliza.rive", source.as_bytes()).await.unwrap();
+        assert_eq!(deparse(&ast), deparse(&expect));
+
+        let story = ast.topics.get("random").unwrap().triggers.last().unwrap();
+        assert_eq!(story.reply[0], "Once upon a time.");
+        assert_eq!(story.location, expect.topics.get("random").unwrap().triggers.last().unwrap().location);
+
+        // Read errors point to the line that couldn't be read.
+        let bad: &[u8] = b"+ hello\n- hi\n+ \xff\xfe\n- oops\n";
+        let err = parser.parse_reader("bad.rive", bad).unwrap_err();
+        assert_eq!(err.diagnostics()[0].code, ErrorCode::ReadError);
+        assert_eq!(err.diagnostics()[0].line, 3);
+
+        // Loading from a reader is the same as loading the file.
+        let mut bot = crate::RiveScript::new();
+        let file = std::fs::File::open("../eg/brain/eliza.rive").unwrap();
+        bot.load_reader("../eg/brain/eliza.rive", std::io::BufReader::new(file)).unwrap();
+        assert_eq!(bot.source_hash(), bot.hash_sources(&["../eg/brain/eliza.rive"], &Default::default()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_lint() {
+        use rivescript_core::errors::{ErrorCode, Severity};
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from(
+            r"
+            ! var name = Aiden
+            ! array colors = red blue green
+
+            + *{weight=10}
+            - I match everything.
+
+            + hello bot
+            - You'll never see this.
+
+            + what color is the sky
+            - It's (@colors), or maybe (@nocolors).
+
+            + my favorite color is @nocolors
+            - Me too!
+
+            + who are you
+            - I am <bot name>, and I'm <bot age>.
+
+            + set mood *
+            - <bot mood=<star>>Okay, I'm <bot mood>.
+
+            + go away
+            - {topic=nowhere}Bye.
+
+            + call it
+            - <call>nosuch</call>
+
+            + hi
+            @ hello (world
+
+            + hello (world
+            - Broken.
+        ",
+        ))
+        .unwrap();
+        bot.sort_triggers();
+
+        let found: Vec<(ErrorCode, String)> = bot.lint().into_iter().map(|d| (d.code, d.text)).collect();
+        let has = |code: ErrorCode, text: &str| found.iter().any(|(c, t)| *c == code && t == text);
+        assert!(has(ErrorCode::ShadowedTrigger, "hello bot"));
+        assert!(has(ErrorCode::UndefinedArray, "what color is the sky"));
+        assert!(has(ErrorCode::UndefinedArray, "my favorite color is @nocolors"));
+        assert!(has(ErrorCode::UndefinedBotVar, "who are you"));
+        assert!(has(ErrorCode::UnknownTopic, "go away"));
+        assert!(has(ErrorCode::UnknownMacro, "call it"));
+        assert!(has(ErrorCode::InvalidPattern, "hello (world"));
+
+        // Defined arrays and bot variables (including ones set by a reply) are fine.
+        assert!(!has(ErrorCode::UndefinedBotVar, "set mood *"));
+        assert!(!found.iter().any(|(c, t)| *c == ErrorCode::UndefinedArray && t == "who are you"));
+
+        // With no catch-all in the way, only the broken redirect is reported.
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from("+ hello\n- Hi!\n\n+ hey\n@ hello\n\n+ yo\n@ howdy\n\n+ hiya\n- {@ Hello }\n")).unwrap();
+        bot.sort_triggers();
+        let found = bot.lint();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].code, ErrorCode::UnmatchedRedirect);
+        assert_eq!(found[0].line, 7);
+
+        // Inline redirects are matched in lowercase, like `@` redirects.
+        assert_eq!(bot.reply("user", "hiya").await.unwrap(), "Hi!");
+
+        // The example brain has no broken patterns.
+        let mut bot = crate::RiveScript::new();
+        bot.load_directory("../eg/brain").unwrap();
+        bot.sort_triggers();
+        assert!(!bot.lint().iter().any(|d| d.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_includes() {
+        use rivescript_core::errors::ErrorCode;
+
+        let dir = std::env::temp_dir().join("rivescript-test-includes");
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(
+            dir.join("bot.rive"),
+            "! include shared/subs.rive\n! sub whats = what is\n\n+ hello bot\n- Hello human!\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("shared/subs.rive"),
+            "! sub whats = what's\n! sub im = i am\n! include greetings.rive\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("shared/greetings.rive"), "// Greetings\n\n+ hi\n- Hi there!\n").unwrap();
+        let entry = dir.join("bot.rive");
+        let entry = entry.to_str().unwrap();
+
+        // Without the option, includes are skipped with a warning.
+        let mut bot = crate::RiveScript::new();
+        bot.load_file(entry).unwrap();
+        assert_eq!(bot.warnings()[0].code, ErrorCode::UnknownDefinition);
+        assert!(!bot.brain.subs.contains_key("im"));
+
+        let mut bot = crate::RiveScript::new();
+        bot.includes = true;
+        bot.load_file(entry).unwrap();
+        assert!(bot.warnings().is_empty());
+
+        // Later definitions win over the included ones.
+        assert_eq!(bot.brain.subs["whats"], "what is");
+        assert_eq!(bot.brain.subs["im"], "i am");
+
+        // Nested includes keep their own source locations.
+        let hi = bot.brain.topics["random"].triggers.iter().find(|t| t.trigger == "hi").unwrap();
+        assert!(hi.location.filename.ends_with("greetings.rive"));
+        assert_eq!(hi.location.start, 3);
+        let hello = bot.brain.topics["random"].triggers.iter().find(|t| t.trigger == "hello bot").unwrap();
+        assert_eq!(hello.location.filename, entry);
+        assert_eq!(hello.location.start, 4);
+
+        // Cycles are rejected.
+        std::fs::write(dir.join("shared/greetings.rive"), "! include ../bot.rive\n").unwrap();
+        let mut bot = crate::RiveScript::new();
+        bot.includes = true;
+        let err = bot.load_file(entry).unwrap_err();
+        let err = err.downcast_ref::<crate::ParseError>().unwrap();
+        assert_eq!(err.diagnostics()[0].code, ErrorCode::IncludeCycle);
+        assert_eq!(err.diagnostics()[0].line, 1);
+
+        // And so are missing files.
+        std::fs::write(dir.join("shared/greetings.rive"), "! include nowhere.rive\n").unwrap();
+        let mut bot = crate::RiveScript::new();
+        bot.includes = true;
+        let err = bot.load_file(entry).unwrap_err();
+        let err = err.downcast_ref::<crate::ParseError>().unwrap();
+        assert_eq!(err.diagnostics()[0].code, ErrorCode::IncludeError);
+        assert!(err.diagnostics()[0].filename.ends_with("greetings.rive"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_directory() {
+        use crate::DirectoryOptions;
+
+        let dir = std::env::temp_dir().join("rivescript-test-load-directory");
+        std::fs::create_dir_all(dir.join("topics/drafts")).unwrap();
+        std::fs::write(dir.join("b.rive"), "! var name = Bravo\n+ hello\n- Hi!\n").unwrap();
+        std::fs::write(dir.join("a.rive"), "! var name = Alpha\n").unwrap();
+        std::fs::write(dir.join("legacy.rs"), "+ legacy\n- Still works.\n").unwrap();
+        std::fs::write(dir.join("notes.txt"), "Not RiveScript.\n").unwrap();
+        std::fs::write(dir.join("topics/games.rive"), "+ lets play\n- Okay!\n").unwrap();
+        std::fs::write(dir.join("topics/drafts/wip.rive"), "! var name = Draft\n").unwrap();
+        let root = dir.to_str().unwrap();
+        let names = |files: &[String]| -> Vec<String> {
+            files.iter().map(|f| f[root.len() + 1..].replace('\\', "/")).collect()
+        };
+
+        // One level, sorted by name, with the legacy extension; the last ! var wins.
+        let mut bot = crate::RiveScript::new();
+        let files = bot.load_directory(root).unwrap();
+        assert_eq!(names(&files), vec!["a.rive", "b.rive", "legacy.rs"]);
+        assert_eq!(bot.brain.get_bot_var("name"), "Bravo");
+
+        let mut bot = crate::RiveScript::new();
+        let options = DirectoryOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        let files = bot.load_directory_with(root, &options).unwrap();
+        assert_eq!(
+            names(&files),
+            vec!["a.rive", "b.rive", "legacy.rs", "topics/drafts/wip.rive", "topics/games.rive"],
+        );
+        assert_eq!(bot.brain.get_bot_var("name"), "Draft");
+
+        // Glob patterns to include and exclude files.
+        let mut bot = crate::RiveScript::new();
+        let options = DirectoryOptions {
+            recursive: true,
+            include: vec![String::from("**/*.rive")],
+            exclude: vec![String::from("**/drafts/**"), String::from("a.rive")],
+        };
+        let files = bot.load_directory_with(root, &options).unwrap();
+        assert_eq!(names(&files), vec!["b.rive", "topics/games.rive"]);
+
+        let mut bot = crate::RiveScript::new();
+        let options = DirectoryOptions {
+            recursive: true,
+            include: vec![String::from("*.rive")],
+            ..Default::default()
+        };
+        let files = bot.load_directory_with(root, &options).unwrap();
+        assert_eq!(names(&files), vec!["a.rive", "b.rive"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_embed_brain() {
+        use rivescript_core::deparse::deparse;
+
+        let mut embedded = crate::RiveScript::new();
+        embedded.load_ast(rivescript::embed_brain!("../eg/brain", strict)).unwrap();
+        embedded.sort_triggers();
+
+        let mut bot = crate::RiveScript::new();
+        bot.load_directory("../eg/brain").unwrap();
+        assert_eq!(deparse(&embedded.brain), deparse(&bot.brain));
+
+        // Locations point to the source files, relative to the crate.
+        let trigger = embedded.brain.topics["random"].triggers.iter().find(|t| t.trigger == "my name is *").unwrap();
+        assert_eq!(trigger.location.filename, "../eg/brain/clients.rive");
+        assert_eq!(trigger.location.start, 3);
+
+        bot.sort_triggers();
+        let reply = embedded.reply("local-user", "asl").await.unwrap();
+        assert_eq!(reply, bot.reply("local-user", "asl").await.unwrap());
+    }
+
+    #[test]
+    fn test_load_from() {
+        use crate::sources::{Memory, Tar, Zip};
+        use std::io::Write;
+
+        let documents = [
+            ("brain/begin.rive", "! var name = Aiden\n"),
+            ("brain/greetings.rive", "+ hello bot\n- Hello, I'm <bot name>.\n"),
+            ("brain/readme.txt", "Not RiveScript."),
+        ];
+
+        let mut memory = Memory::new();
+        for (name, source) in documents {
+            memory.insert(name, source);
+        }
+
+        let mut zip_bytes = std::io::Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut zip_bytes);
+        for (name, source) in documents.iter().rev() {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(source.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut tar_builder = tar::Builder::new(Vec::new());
+        for (name, source) in documents.iter().rev() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(source.len() as u64);
+            header.set_cksum();
+            tar_builder.append_data(&mut header, name, source.as_bytes()).unwrap();
+        }
+        let tar_bytes = tar_builder.into_inner().unwrap();
+
+        // Every provider lists the RiveScript documents in sorted order.
+        let expect = vec!["brain/begin.rive", "brain/greetings.rive", "brain/readme.txt"];
+        let mut bot = crate::RiveScript::new();
+        assert_eq!(bot.load_from(&mut memory).unwrap(), expect);
+
+        let expect = vec!["brain/begin.rive", "brain/greetings.rive"];
+        for loaded in [
+            bot.load_from(&mut Zip::new(std::io::Cursor::new(zip_bytes.get_ref().clone())).unwrap()),
+            bot.load_from(&mut Tar::new(tar_bytes.as_slice()).unwrap()),
+        ] {
+            assert_eq!(loaded.unwrap(), expect);
+        }
+
+        assert_eq!(bot.brain.get_bot_var("name"), "Aiden");
+        let triggers = &bot.brain.topics["random"].triggers;
+        assert_eq!(triggers.len(), 3);
+        assert!(triggers.iter().all(|t| t.location.filename == "brain/greetings.rive" && t.location.start == 1));
+
+        // Parse errors are reported with the document's name.
+        let mut memory = Memory::new();
+        memory.insert("bad.rive", "! version = 9.0\n");
+        let err = bot.load_from(&mut memory).unwrap_err();
+        let err = err.downcast_ref::<crate::ParseError>().unwrap();
+        assert_eq!(err.diagnostics()[0].filename, "bad.rive");
+    }
+
+    #[tokio::test]
+    async fn test_unload_reload() {
+        use rivescript_core::macros::{LanguageLoader, Proxy, SubroutineResult};
+        use std::sync::{Arc, Mutex};
+
+        // A language handler that remembers which objects it has been given.
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        #[async_trait::async_trait]
+        impl LanguageLoader for Recorder {
+            fn load(&mut self, name: &str, _code: Vec<String>) -> Result<bool, String> {
+                self.0.lock().unwrap().push(format!("load {name}"));
+                Ok(true)
+            }
+            async fn call(&self, _proxy: &dyn Proxy, name: &str, _args: Vec<String>) -> Result<SubroutineResult, String> {
+                Err(format!("can't call {name}"))
+            }
+            fn unload(&mut self, name: &str) {
+                self.0.lock().unwrap().push(format!("unload {name}"));
+            }
+        }
+
+        let dir = std::env::temp_dir().join("rivescript-test-unload-reload");
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("base.rive");
+        let extra = dir.join("extra.rive");
+        std::fs::write(&base, "! sub whats = what is\n+ what is up\n- Not much.\n+ hello\n- Hi!\n").unwrap();
+        std::fs::write(
+            &extra,
+            "! sub whats = what has\n+ what has up\n- Overridden.\n+ goodbye\n- Bye!\n\n\
+             > topic games\n+ play\n- Okay!\n< topic\n\n\
+             > object shout test\nreturn 1;\n< object\n",
+        )
+        .unwrap();
+        let (base, extra) = (base.to_str().unwrap(), extra.to_str().unwrap());
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut bot = crate::RiveScript::new();
+        bot.set_handler("test", Recorder(log.clone()));
+        bot.load_file(base).unwrap();
+        bot.load_file(extra).unwrap();
+        bot.sort_triggers();
+        assert_eq!(bot.loaded_files(), vec![base, extra]);
+        assert_eq!(bot.reply("user", "whats up").await.unwrap(), "Overridden.");
+        assert_eq!(bot.reply("user", "goodbye").await.unwrap(), "Bye!");
+
+        // Unloading restores the earlier ! sub and removes the triggers, topic and object.
+        assert!(bot.unload_file(extra).unwrap());
+        assert!(!bot.unload_file(extra).unwrap());
+        assert_eq!(bot.loaded_files(), vec![base]);
+        assert_eq!(bot.reply("user", "whats up").await.unwrap(), "Not much.");
+        assert_eq!(bot.reply("user", "goodbye").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        assert!(!bot.brain.has_topic("games"));
+        assert!(!bot.sorted_topics.contains_key("games"));
+        assert!(!bot.object_langs.contains_key("shout"));
+        assert_eq!(*log.lock().unwrap(), vec!["load shout", "unload shout"]);
+
+        // Reloading a changed file replaces its triggers and keeps its place in the load order,
+        // so the later file's ! sub still wins.
+        bot.load_file(extra).unwrap();
+        std::fs::write(base, "! sub whats = what was\n+ hello\n- Hello again!\n+ what was up\n- Earlier.\n").unwrap();
+        bot.reload_file(base).unwrap();
+        assert_eq!(bot.loaded_files(), vec![base, extra]);
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "Hello again!");
+        assert_eq!(bot.reply("user", "whats up").await.unwrap(), "Overridden.");
+        assert_eq!(bot.reply("user", "what was up").await.unwrap(), "Earlier.");
+
+        // A syntax error leaves the brain as it was.
+        std::fs::write(base, "! version = 3.0\n+ hello\n- Broken.\n").unwrap();
+        assert!(bot.reload_file(base).is_err());
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "Hello again!");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_trigger_order() {
+        let dir = std::env::temp_dir().join("rivescript-test-reload-order");
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.rive");
+        let b = dir.join("b.rive");
+        std::fs::write(&a, "+ hello\n- From a.\n\n+ *\n- Anything from a.\n").unwrap();
+        std::fs::write(&b, "+ hello\n- From b.\n\n+ *\n- Anything from b.\n").unwrap();
+        let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+        let mut bot = crate::RiveScript::new();
+        bot.load_file(a).unwrap();
+        bot.load_file(b).unwrap();
+        bot.sort_triggers();
+        let sorted = |bot: &crate::RiveScript| -> Vec<(String, Vec<String>)> {
+            bot.sorted_triggers("random").iter().map(|t| (t.trigger.clone(), t.reply.clone())).collect()
+        };
+        let before = sorted(&bot);
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "From a.");
+
+        // Reloading the earlier file, unchanged, puts its triggers back where they were.
+        bot.reload_file(a).unwrap();
+        assert_eq!(sorted(&bot), before);
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "From a.");
+        assert_eq!(bot.reply("user", "anything").await.unwrap(), "Anything from a.");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unload_keeps_runtime_changes() {
+        use crate::ast::{Topic, Trigger};
+        use crate::sources::Memory;
+
+        let mut documents = Memory::new();
+        documents.insert(
+            "a.rive",
+            "! var name = Aiden\n! array colors = red blue\n\
+             + hello\n- Hello from a.\n\n\
+             > topic a\n+ hi\n- Hi from a.\n< topic\n\n\
+             > topic b\n+ bye\n- Bye from b.\n< topic\n",
+        );
+        let mut bot = crate::RiveScript::new();
+        bot.load_from(&mut documents).unwrap();
+
+        // Triggers from elsewhere that claim to come from the same file.
+        let (ast, _) = Parser::new().parse_with_warnings("a.rive", String::from("+ goodbye\n- Bye from the AST.")).unwrap();
+        bot.load_ast(ast).unwrap();
+        let mut trigger = Trigger::new("thanks");
+        trigger.reply.push(String::from("You're welcome."));
+        trigger.location = crate::ast::Location::new("a.rive", 1, 2);
+        bot.add_trigger("random", trigger);
+
+        // Values that the document defined, replaced in code.
+        bot.set_variable("name", "Bob");
+        bot.set_array("colors", &["green"]);
+        let mut topic = Topic::new("a");
+        topic.set_includes(String::from("b"));
+        bot.set_topic(topic);
+        bot.sort_triggers();
+
+        assert!(bot.unload_file("a.rive").unwrap());
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        assert_eq!(bot.reply("user", "goodbye").await.unwrap(), "Bye from the AST.");
+        assert_eq!(bot.reply("user", "thanks").await.unwrap(), "You're welcome.");
+        assert_eq!(bot.brain.get_bot_var("name"), "Bob");
+        assert_eq!(bot.brain.arrays["colors"], vec!["green"]);
+        assert!(bot.brain.topics["a"].includes.contains_key("b"));
+    }
+
+    #[tokio::test]
+    async fn test_remove_then_unload() {
+        use crate::sources::Memory;
+
+        let mut documents = Memory::new();
+        documents.insert(
+            "a.rive",
+            "! var name = Aiden\n! sub what's = what is\n\
+             > topic a includes x\n+ hi\n- Hi from a.\n< topic\n\n\
+             > topic x\n+ joke\n- No.\n< topic\n",
+        );
+        documents.insert("b.rive", "! var name = Bravo\n! sub what's = what was\n\n> topic a\n+ hello\n- Hello from b.\n< topic\n");
+        let mut bot = crate::RiveScript::new();
+        bot.load_from(&mut documents).unwrap();
+        bot.sort_triggers();
+
+        assert!(bot.remove_topic("x"));
+        assert!(bot.remove_variable("name"));
+        assert!(bot.remove_substitution("what's"));
+
+        // Unloading a document that also defined them doesn't bring back the earlier ones.
+        assert!(bot.unload_file("b.rive").unwrap());
+        assert!(!bot.brain.has_topic("x"));
+        assert!(bot.brain.topics["a"].includes.is_empty());
+        assert!(!bot.brain.vars.read().unwrap().contains_key("name"));
+        assert!(!bot.brain.subs.contains_key("what's"));
+        bot.set_uservar("user", "topic", "a").await;
+        assert_eq!(bot.reply("user", "hi").await.unwrap(), "Hi from a.");
+
+        // Nor does loading it again.
+        let mut again = Memory::new();
+        again.insert("b.rive", "! var name = Bravo\n\n> topic a\n+ hello\n- Hi.\n< topic\n");
+        bot.load_from(&mut again).unwrap();
+        assert!(bot.unload_file("b.rive").unwrap());
+        assert!(!bot.brain.has_topic("x"));
+        assert!(!bot.brain.vars.read().unwrap().contains_key("name"));
+    }
+
+    #[cfg(feature = "watch")]
+    #[tokio::test]
+    async fn test_watcher() {
+        use crate::{Change, Watcher};
+        use std::time::{Duration, Instant};
+
+        // Wait for the watcher to see a change.
+        async fn changes(watcher: &mut Watcher, bot: &mut crate::RiveScript) -> Vec<Change> {
+            let start = Instant::now();
+            loop {
+                let changes = watcher.reload(bot);
+                if !changes.is_empty() || start.elapsed() > Duration::from_secs(5) {
+                    return changes;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+
+        let dir = std::env::temp_dir().join("rivescript-test-watcher");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("brain.rive");
+        std::fs::write(&path, "+ hello\n- Hi!\n\n+ my name is *\n- <set name=<star>>Nice to meet you.\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut bot = crate::RiveScript::new();
+        bot.load_file(path).unwrap();
+        bot.sort_triggers();
+        let mut watcher = Watcher::new(&bot).unwrap();
+        assert!(watcher.reload(&mut bot).is_empty());
+        bot.reply("user", "my name is alice").await.unwrap();
+
+        // An edit is swapped in, and user variables survive it.
+        std::fs::write(path, "+ hello\n- Hello, <get name>!\n").unwrap();
+        let changed = changes(&mut watcher, &mut bot).await;
+        assert!(matches!(&changed[..], [Change::Reloaded(name)] if name == path), "{changed:?}");
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "Hello, alice!");
+
+        // A broken edit keeps the old brain.
+        std::fs::write(path, "> topic broken includes nowhere\n+ hello\n- Broken.\n< topic\n").unwrap();
+        let changed = changes(&mut watcher, &mut bot).await;
+        assert!(matches!(&changed[..], [Change::Failed(name, _)] if name == path), "{changed:?}");
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "Hello, alice!");
+
+        // Changed documents are swapped in together, or not at all.
+        let other = dir.join("other.rive");
+        std::fs::write(&other, "+ goodbye\n- Bye!\n").unwrap();
+        let other = other.to_str().unwrap();
+        bot.load_file(other).unwrap();
+        bot.sort_triggers();
+        watcher.watch(&bot).unwrap();
+        std::fs::write(other, "+ goodbye\n- See you, <get name>!\n").unwrap();
+        let changed = changes(&mut watcher, &mut bot).await;
+        assert!(matches!(&changed[..], [Change::Failed(name, _)] if name == path), "{changed:?}");
+        assert_eq!(bot.reply("user", "goodbye").await.unwrap(), "Bye!");
+
+        std::fs::write(path, "+ hello\n- Hello again, <get name>!\n").unwrap();
+        let changed = changes(&mut watcher, &mut bot).await;
+        let names: Vec<&str> = changed.iter().map(|change| match change {
+            Change::Reloaded(name) => name.as_str(),
+            Change::Failed(name, e) => panic!("{name}: {e}"),
+        }).collect();
+        assert_eq!(names, vec![path, other]);
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "Hello again, alice!");
+        assert_eq!(bot.reply("user", "goodbye").await.unwrap(), "See you, alice!");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_all() {
+        use crate::LoadError;
+        use crate::sources::Memory;
+
+        let mut documents = Memory::new();
+        documents.insert("a.rive", "! sub hi = hello\n+ hello\n- Hi!\n\n> object shout javascript\nreturn 1;\n< object\n");
+        documents.insert("b.rive", "! version = two\n");
+        documents.insert("c.rive", "+ goodbye\n- Bye!\n");
+        documents.insert("d.rive", "  ! version = 3.0\n");
+
+        // Every failure is reported, and nothing is loaded.
+        let mut bot = crate::RiveScript::new();
+        let err = bot.load_all(&mut documents).unwrap_err();
+        let err = err.downcast_ref::<LoadError>().unwrap();
+        let failed: Vec<&str> = err.failures().iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(failed, vec!["b.rive", "d.rive"]);
+        assert!(err.to_string().contains("b.rive:1:1: error [bad-version]"));
+        assert!(bot.loaded_files().is_empty());
+        assert!(bot.brain.topics.is_empty());
+        assert!(bot.brain.subs.is_empty());
+        assert!(bot.brain.objects.is_empty());
+
+        // Once they're fixed, they all load.
+        documents.insert("b.rive", "! version = 2.0\n");
+        documents.insert("d.rive", "! var name = Aiden\n");
+        let names = bot.load_all(&mut documents).unwrap();
+        assert_eq!(names, vec!["a.rive", "b.rive", "c.rive", "d.rive"]);
+        assert_eq!(bot.loaded_files(), names);
+        bot.sort_triggers();
+        assert_eq!(bot.reply("user", "hi").await.unwrap(), "Hi!");
+        assert_eq!(bot.reply("user", "goodbye").await.unwrap(), "Bye!");
+
+        // A file that can't be read is a failure too.
+        let mut bot = crate::RiveScript::new();
+        let err = bot.load_files(&["../eg/brain/clients.rive", "../eg/brain/missing.rive"]).unwrap_err();
+        let err = err.downcast_ref::<LoadError>().unwrap();
+        assert_eq!(err.failures().len(), 1);
+        assert!(err.to_string().contains("../eg/brain/missing.rive: "));
+        assert!(bot.loaded_files().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_loading() {
+        use crate::{DirectoryOptions, sources::Memory};
+
+        let load = |parallel: bool| {
+            let mut bot = crate::RiveScript::new();
+            bot.parallel = parallel;
+            let options = DirectoryOptions {
+                recursive: true,
+                ..Default::default()
+            };
+            let files = bot.load_directory_with("../eg/brain", &options).unwrap();
+            (bot, files)
+        };
+        let (sequential, files) = load(false);
+        let (parallel, parallel_files) = load(true);
+
+        // The same documents are loaded in the same order, into the same brain.
+        assert_eq!(files, parallel_files);
+        assert_eq!(sequential.loaded_files(), parallel.loaded_files());
+        assert_eq!(
+            serde_json::to_value(&sequential.brain).unwrap(),
+            serde_json::to_value(&parallel.brain).unwrap(),
+        );
+        assert_eq!(sequential.warnings(), parallel.warnings());
+        assert_eq!(sequential.source_hash, parallel.source_hash);
+
+        // A failing document stops the load at the same place as loading one at a time.
+        let mut documents = Memory::new();
+        documents.insert("a.rive", "! var name = Alpha\n");
+        documents.insert("b.rive", "! version = two\n");
+        documents.insert("c.rive", "! var name = Charlie\n");
+        let mut bot = crate::RiveScript::new();
+        bot.parallel = true;
+        assert!(bot.load_from(&mut documents).is_err());
+        assert_eq!(bot.loaded_files(), vec!["a.rive"]);
+        assert_eq!(bot.brain.get_bot_var("name"), "Alpha");
+    }
+
+    #[tokio::test]
+    async fn test_brain_builder() {
+        use crate::ast::{Condition, Topic, Trigger};
+
+        let trigger = |pattern: &str, replies: &[&str]| {
+            let mut trigger = Trigger::new(pattern);
+            trigger.reply.extend(replies.iter().map(|r| r.to_string()));
+            trigger
+        };
+
+        let mut bot = crate::RiveScript::new();
+        bot.set_variable("name", "Aiden");
+        bot.set_global("depth", "25");
+        bot.set_substitution("what's", "what is");
+        bot.set_person("i am", "you are");
+        bot.set_array("colors", &["red", "light blue"]);
+
+        bot.add_trigger("random", trigger("what is your name", &["My name is <bot name>."]));
+        bot.add_trigger("random", trigger("i like (@colors)", &["<star> is a nice color."]));
+        bot.add_trigger("random", trigger("say *", &["Umm... \"<person>\""]));
+        bot.add_trigger("random", trigger("start shopping", &["Okay!{topic=sales}"]));
+
+        // Conditions and redirects.
+        let mut hello = trigger("hello", &["Hello, stranger."]);
+        hello.condition.push(Condition::new("<get name>", "!=", "undefined", "Hello, <get name>!"));
+        bot.add_trigger("random", hello);
+        bot.add_trigger("random", trigger("my name is *", &["<set name=<formal>>Nice to meet you."]));
+        let mut hi = Trigger::new("hi");
+        hi.redirect = String::from("hello");
+        bot.add_trigger("random", hi);
+
+        // %Previous.
+        bot.add_trigger("random", trigger("knock knock", &["Who is there?"]));
+        let mut who = trigger("*", &["<sentence> who?"]);
+        who.previous = String::from("who is there");
+        bot.add_trigger("random", who);
+
+        // A topic that inherits the default topic.
+        let mut sales = Topic::new("sales");
+        sales.set_inherits(String::from("random"));
+        sales.add_trigger(trigger("how much is *", &["The <star> is on sale."]));
+        sales.add_trigger(trigger("stop shopping", &["Bye!{topic=random}"]));
+        bot.set_topic(sales);
+
+        bot.sort_triggers();
+        assert_eq!(bot.depth, 25);
+        assert_eq!(bot.reply("user", "What's your name?").await.unwrap(), "My name is Aiden.");
+        assert_eq!(bot.reply("user", "I like light blue").await.unwrap(), "light blue is a nice color.");
+        assert_eq!(bot.reply("user", "say i am happy").await.unwrap(), "Umm... \"you are happy\"");
+        assert_eq!(bot.reply("user", "hi").await.unwrap(), "Hello, stranger.");
+        bot.reply("user", "my name is bob").await.unwrap();
+        assert_eq!(bot.reply("user", "hi").await.unwrap(), "Hello, Bob!");
+        assert_eq!(bot.reply("user", "knock knock").await.unwrap(), "Who is there?");
+        assert_eq!(bot.reply("user", "banana").await.unwrap(), "Banana who?");
+
+        bot.reply("user", "start shopping").await.unwrap();
+        assert_eq!(bot.reply("user", "how much is the hat").await.unwrap(), "The the hat is on sale.");
+        assert_eq!(bot.reply("user", "what is your name").await.unwrap(), "My name is Aiden.");
+        bot.reply("user", "stop shopping").await.unwrap();
+
+        // Adding a trigger with the same pattern replaces it, after re-sorting.
+        bot.add_trigger("random", trigger("what is your name", &["I'm <bot name>."]));
+        bot.set_variable("name", "Bravo");
+        assert_eq!(bot.brain.topics["random"].triggers.iter().filter(|t| t.trigger == "what is your name").count(), 1);
+        bot.sort_triggers();
+        assert_eq!(bot.reply("user", "what is your name").await.unwrap(), "I'm Bravo.");
+    }
+
+    #[tokio::test]
+    async fn test_remove_content() {
+        use futures::FutureExt;
+        use rivescript_core::macros::Proxy;
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from(
+            "! var name = Aiden\n\
+             ! sub what's = what is\n\
+             ! person i am = you are\n\
+             ! array colors = red blue\n\n\
+             + hello\n- Hi!\n\n\
+             + what is your name\n- <bot name>\n\n\
+             + say *\n- <person>\n\n\
+             + i like (@colors)\n- Me too.\n\n\
+             + call\n- <call>shout</call>\n\n\
+             + knock knock\n- Who is there?\n\n\
+             + *\n% who is there\n- Ha!\n\n\
+             + *\n- Fallback.\n\n\
+             > topic games includes jokes\n+ play\n- Okay!\n< topic\n\n\
+             > topic jokes\n+ tell me a joke\n- No.\n< topic\n\n\
+             > object upper javascript\nreturn 1;\n< object\n",
+        ))
+        .unwrap();
+        bot.set_subroutine("shout", |proxy, _args| async move { proxy.finish(String::from("HEY")) }.boxed());
+        bot.sort_triggers();
+
+        // Triggers, with and without a %Previous, are gone from the sorted buffers right away.
+        assert!(bot.remove_trigger("random", "hello"));
+        assert!(!bot.remove_trigger("random", "hello"));
+        assert!(!bot.remove_trigger("nowhere", "hello"));
+        assert!(bot.sorted_topics["random"].iter().all(|t| !t.trigger.ends_with("hello")));
+        assert_eq!(bot.reply("user", "hello").await.unwrap(), "Fallback.");
+        assert_eq!(bot.reply("user", "knock knock").await.unwrap(), "Who is there?");
+        assert!(bot.remove_trigger("random", "*"));
+        assert!(bot.sorted_thats["random"].is_empty());
+        assert_eq!(bot.reply("user", "banana").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+
+        // Removing a topic that another topic includes.
+        bot.set_uservar("user", "topic", "games").await;
+        assert_eq!(bot.reply("user", "tell me a joke").await.unwrap(), "No.");
+        assert!(bot.remove_topic("jokes"));
+        assert!(!bot.remove_topic("jokes"));
+        assert!(!bot.sorted_topics.contains_key("jokes") && !bot.sorted_thats.contains_key("jokes"));
+        assert!(bot.brain.topics["games"].includes.is_empty());
+        assert_eq!(bot.reply("user", "tell me a joke").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+
+        // Users in a removed topic go back to the default topic.
+        assert!(bot.remove_topic("games"));
+        assert_eq!(bot.reply("user", "what's your name").await.unwrap(), "Aiden");
+
+        // Substitutions, arrays and variables.
+        assert!(bot.remove_substitution("what's"));
+        assert!(!bot.sorted_subs.contains(&String::from("what's")));
+        assert_eq!(bot.reply("user", "what's your name").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        assert!(bot.remove_person("i am"));
+        assert!(bot.sorted_person.is_empty());
+        assert_eq!(bot.reply("user", "say i am here").await.unwrap(), "i am here");
+        assert!(bot.remove_variable("name"));
+        assert_eq!(bot.reply("user", "what is your name").await.unwrap(), "undefined");
+        assert!(bot.remove_array("colors"));
+        assert!(!bot.remove_array("colors"));
+
+        // Object macros, from documents and from code.
+        assert!(bot.remove_object("upper"));
+        assert!(!bot.brain.objects.contains_key("upper"));
+        assert_eq!(bot.reply("user", "call").await.unwrap(), "HEY");
+        assert!(bot.remove_object("shout"));
+        assert!(!bot.remove_object("shout"));
+        assert_ne!(bot.reply("user", "call").await.unwrap(), "HEY");
+
+        // The default topic is emptied, not removed.
+        assert!(bot.remove_topic("random"));
+        assert!(bot.brain.has_topic("random"));
+        assert!(bot.sorted_topics["random"].is_empty());
+    }
+
+    #[test]
+    fn test_introspection() {
+        use crate::{BrainStats, ObjectMacro};
+        use futures::FutureExt;
+        use rivescript_core::macros::Proxy;
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from(
+            "! global debug = false\n\
+             ! var name = Aiden\n\
+             ! sub what's = what is\n\
+             ! person i am = you are\n\
+             ! array colors = red blue\n\n\
+             + hello\n- Hi!\n- Hello!\n\n\
+             + hi\n@ hello\n\n\
+             + *\n% who is there\n* <get name> != undefined => <get name> who?\n- Who?\n\n\
+             > topic games inherits random\n+ play\n- Okay!\n< topic\n\n\
+             > object upper javascript\nreturn 1;\n< object\n",
+        ))
+        .unwrap();
+        bot.set_subroutine("shout", |proxy, _args| async move { proxy.finish(String::from("HEY")) }.boxed());
+
+        assert_eq!(bot.topics(), vec!["games", "random"]);
+        let games = bot.topic("games").unwrap();
+        assert!(games.inherits.contains_key("random"));
+        assert_eq!(games.triggers[0].trigger, "play");
+        assert!(bot.topic("nowhere").is_none());
+
+        // The sorted triggers are only there after sorting.
+        assert!(bot.sorted_triggers("random").is_empty());
+        bot.sort_triggers();
+        let sorted: Vec<&str> = bot.sorted_triggers("random").iter().map(|t| t.trigger.as_str()).collect();
+        assert_eq!(sorted, vec!["hello", "hi"]);
+        assert_eq!(bot.sorted_triggers("games")[0].trigger, "{inherits=0}play");
+        assert_eq!(bot.sorted_previous("random").len(), 1);
+        assert!(bot.sorted_triggers("nowhere").is_empty());
+
+        assert_eq!(bot.arrays()["colors"], vec!["red", "blue"]);
+        assert_eq!(bot.substitutions()["what's"], "what is");
+        assert_eq!(bot.person_substitutions()["i am"], "you are");
+        assert_eq!(bot.globals()["debug"], "false");
+        assert_eq!(bot.variables()["name"], "Aiden");
+        assert_eq!(
+            bot.objects(),
+            vec![
+                ObjectMacro { name: String::from("shout"), language: String::from("rust"), callable: true },
+                ObjectMacro { name: String::from("upper"), language: String::from("javascript"), callable: false },
+            ],
+        );
+
+        assert_eq!(
+            bot.stats(),
+            BrainStats {
+                files: 1,
+                topics: 2,
+                triggers: 4,
+                replies: 4,
+                conditions: 1,
+                redirects: 1,
+                previous: 1,
+                arrays: 1,
+                substitutions: 1,
+                person_substitutions: 1,
+                globals: 1,
+                variables: 1,
+                objects: 2,
+            },
+        );
+    }
+
+    #[test]
+    fn test_brain_diff() {
+        use crate::{BrainDiff, DefinitionKind, Difference, RiveScript, TriggerChange};
+
+        let load = |source: &str| {
+            let mut bot = RiveScript::new();
+            bot.stream(source.to_string()).unwrap();
+            bot.sort_triggers();
+            bot
+        };
+
+        let old = load("
+            ! var name = Aiden
+            ! sub what's = what is
+            ! array colors = red blue
+
+            + hello
+            - Hello!
+            - Hi!
+
+            + what is your name
+            * <get name> == undefined => I don't know yours.
+            - I'm <bot name>.
+
+            + hey
+            @ hello
+
+            > topic games inherits random
+              + play
+              - Let's play!
+            < topic
+        ");
+        let new = load("
+            ! var name = Bravo
+            ! array colors = red blue green
+
+            + hello
+            - Hello!
+            - Hello there!
+
+            + what is your name
+            * <get name> == undefined => I don't know yours.
+            * <get name> != undefined => Hi, <get name>.
+            - I'm <bot name>.
+
+            + hey
+            @ what is your name
+
+            + goodbye
+            - Bye!
+
+            > topic games includes random
+              + play
+              - Let's play!
+            < topic
+        ");
+
+        assert!(old.diff(&load("")).differences.contains(&Difference::TriggerRemoved {
+            topic: String::from("random"),
+            trigger: String::from("hello"),
+            previous: String::new(),
+        }));
+        assert!(old.diff(&old).is_empty());
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.to_string(),
+            "~ ! var name = Aiden -> Bravo
+- ! sub what's = what is
+~ ! array colors = red|blue -> red|blue|green
+~ > topic games includes: (none) -> random
+~ > topic games inherits: random -> (none)
+~ [random] + hello: removed reply \"Hi!\"
+~ [random] + hello: added reply \"Hello there!\"
+~ [random] + what is your name: added condition \"<get name> != undefined => Hi, <get name>.\"
+~ [random] + hey: redirect hello -> what is your name
++ [random] + goodbye
+> [games] + play moved in the match order from #1 to #4
+",
+        );
+        assert_eq!(
+            diff.differences[0],
+            Difference::Definition {
+                kind: DefinitionKind::Var,
+                name: String::from("name"),
+                before: Some(String::from("Aiden")),
+                after: Some(String::from("Bravo")),
+            },
+        );
+
+        // Without sorting, only the contents are compared.
+        let diff = BrainDiff::between(&old.brain, &new.brain);
+        assert!(!diff.differences.iter().any(|d| matches!(d, Difference::TriggerMoved { .. })));
+        assert!(diff.differences.contains(&Difference::TriggerChanged {
+            topic: String::from("random"),
+            trigger: String::from("hey"),
+            previous: String::new(),
+            change: TriggerChange::Redirect { before: String::from("hello"), after: String::from("what is your name") },
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_topic_include_order() {
+        // When included (or inherited) topics have the same trigger, the topic whose
+        // name sorts first wins, however the brain was loaded.
+        for _ in 0..10 {
+            let mut bot = crate::RiveScript::new();
+            bot.stream(String::from(
+                "> topic charlie\n+ hello\n- From charlie.\n< topic\n\n\
+                 > topic alpha\n+ hello\n- From alpha.\n< topic\n\n\
+                 > topic bravo\n+ hello\n- From bravo.\n< topic\n\n\
+                 > topic included includes charlie alpha bravo\n+ hi\n- Hi!\n< topic\n\n\
+                 > topic inherited inherits bravo charlie alpha\n+ hi\n- Hi!\n< topic\n",
+            ))
+            .unwrap();
+            bot.sort_triggers();
+
+            bot.set_uservar("user", "topic", "included").await;
+            assert_eq!(bot.reply("user", "hello").await.unwrap(), "From alpha.");
+            bot.set_uservar("user", "topic", "inherited").await;
+            assert_eq!(bot.reply("user", "hello").await.unwrap(), "From alpha.");
+
+            let tree = crate::inheritance::get_topic_tree(&bot.brain, &bot.brain.topics["included"], 0);
+            assert_eq!(tree, vec!["included", "alpha", "bravo", "charlie"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reply_templates() {
+        use crate::template::{Format, Node, Template};
+
+        let text = |text: &str| Node::Text(text.to_string());
+        let template = |nodes: Vec<Node>| Template { nodes };
+
+        // Tags are parsed into a tree, with their data parsed too.
+        assert_eq!(
+            Template::parse("{weight=2}<set name=<formal>>Nice to meet you, {random}<get name>|friend{/random}.\\n"),
+            template(vec![
+                Node::Set(template(vec![text("name")]), template(vec![Node::Format(Format::Formal, template(vec![Node::Star(1)]))])),
+                text("Nice to meet you, "),
+                Node::Random(vec![template(vec![Node::Get(template(vec![text("name")]))]), template(vec![text("friend")])]),
+                text(".\n"),
+            ]),
+        );
+
+        // Anything that isn't a tag is kept as text, with the tags inside it still parsed.
+        assert_eq!(
+            Template::parse("<b><bot name></b> says 2 < 3 {random}a|b"),
+            template(vec![
+                text("<b>"),
+                Node::Bot(template(vec![text("name")]), None),
+                text("</b> says 2 < 3 {random}a|b"),
+            ]),
+        );
+
+        let mut bot = crate::RiveScript::new();
+        bot.utf8 = true;
+        bot.stream(String::from("
+            ! var name = Aiden
+            ! array colors = red
+
+            + my name is *
+            - <set name=<formal>>{uppercase}<star>{/uppercase}, <get name> likes (@colors) and (@shapes).
+
+            + repeat *
+            - You said: <star>
+
+            + * * * * * * * * *
+            - <star9> <star1>
+
+            + count
+            - <add counter=2><mult counter=3><get counter> <div counter=0>
+        ")).unwrap();
+        bot.sort_triggers();
+
+        // Tags nest, and a missing array is left as it was written.
+        assert_eq!(bot.reply("user", "my name is alice").await.unwrap(), "ALICE, Alice likes red and (@shapes).");
+        assert_eq!(bot.reply("user", "a b c d e f g h i").await.unwrap(), "i a");
+        assert_eq!(bot.reply("user", "count").await.unwrap(), "6 [ERR: Can't Divide By Zero]");
+
+        // What the user said is never run as tags.
+        assert_eq!(
+            bot.reply("user", "repeat {random}a|b{/random} {@count}").await.unwrap(),
+            "You said: {random}a|b{/random} {@count}",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tag_order() {
+        use futures::FutureExt;
+        use rivescript_core::macros::Proxy;
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from("
+            ! var name = Aiden
+
+            + hello
+            - Hello.
+
+            + switch
+            - {@hello}{topic=other}
+
+            + call
+            - <call>setx</call>[<get x>]
+
+            + name
+            - {uppercase}<bot name>{/uppercase} is <bot name>.
+
+            > topic other
+                + hello
+                - Hello from other.
+            < topic
+        ")).unwrap();
+        bot.set_subroutine("setx", |proxy, _args| async move {
+            let username = proxy.current_username();
+            proxy.set_uservar(&username, "x", "1").await?;
+            proxy.finish(String::from("called"))
+        }.boxed());
+        bot.sort_triggers();
+
+        // The topic is set before the redirect runs.
+        assert_eq!(bot.reply("user", "switch").await.unwrap(), "Hello from other.");
+
+        // Object macros run after the other tags.
+        assert_eq!(bot.reply("user2", "call").await.unwrap(), "called[undefined]");
+        assert_eq!(bot.get_uservar("user2", "x").await, "1");
+
+        // String formatting runs on the text of the variable tags inside it.
+        assert_eq!(bot.reply("user2", "name").await.unwrap(), "<BOT NAME> is Aiden.");
+    }
+
+    #[tokio::test]
+    async fn test_trigger_patterns() {
+        use crate::patterns::Compiled;
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from("
+            ! var name = Aiden
+            ! array colors = red blue
+
+            + hello bot
+            - Hello, human!
+
+            + i like @colors
+            - Me too!
+
+            + are you <bot name>
+            - Yes, I am.
+
+            + my name is *
+            - <set name=<star>>Nice to meet you.
+
+            + i am <get name>
+            - I know.
+
+            + what did i say
+            % nice to meet you
+            - You said: <input1>
+        ")).unwrap();
+        bot.sort_triggers();
+
+        // Triggers without variables are compiled when they're sorted.
+        assert!(matches!(bot.patterns.get("hello bot"), Some(Compiled::Static(_))));
+        assert!(matches!(bot.patterns.get("i like @colors"), Some(Compiled::Static(_))));
+        assert!(matches!(bot.patterns.get("nice to meet you"), Some(Compiled::Static(_))));
+        assert!(matches!(bot.patterns.get("are you <bot name>"), Some(Compiled::Dynamic(_))));
+        assert!(matches!(bot.patterns.get("i am <get name>"), Some(Compiled::Dynamic(_))));
+
+        assert_eq!(bot.reply("alice", "i like blue").await.unwrap(), "Me too!");
+        assert_eq!(bot.reply("alice", "are you aiden").await.unwrap(), "Yes, I am.");
+
+        // The variables are filled in for each message.
+        bot.set_variable("name", "Bravo");
+        assert_eq!(bot.reply("alice", "are you bravo").await.unwrap(), "Yes, I am.");
+        assert_eq!(bot.reply("alice", "are you aiden").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        assert_eq!(bot.reply("alice", "my name is alice").await.unwrap(), "Nice to meet you.");
+        assert_eq!(bot.reply("bob", "my name is bob").await.unwrap(), "Nice to meet you.");
+        assert_eq!(bot.reply("alice", "i am alice").await.unwrap(), "I know.");
+        assert_eq!(bot.reply("bob", "i am alice").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        assert_eq!(bot.reply("bob", "i am bob").await.unwrap(), "I know.");
+        assert_eq!(bot.reply("alice", "my name is alice").await.unwrap(), "Nice to meet you.");
+        assert_eq!(bot.reply("alice", "what did i say").await.unwrap(), "You said: my name is alice");
+
+        // Changing the arrays compiles the patterns again.
+        assert!(bot.remove_array("colors"));
+        assert_eq!(bot.reply("alice", "i like blue").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        bot.set_array("colors", &["green"]);
+        bot.sort_triggers();
+        assert_eq!(bot.reply("alice", "i like green").await.unwrap(), "Me too!");
+
+        // Arrays and UTF-8 mode changed without sorting again take effect too.
+        bot.set_array("colors", &["purple"]);
+        assert!(bot.patterns.is_stale(&bot, "i like @colors"));
+        assert!(!bot.patterns.is_stale(&bot, "hello bot"));
+        assert_eq!(bot.reply("alice", "i like purple").await.unwrap(), "Me too!");
+        assert_eq!(bot.reply("alice", "i like green").await.unwrap(), rivescript_core::ERR_NO_MATCH);
+        bot.utf8 = true;
+        assert!(bot.patterns.is_stale(&bot, "hello bot"));
+        assert_eq!(bot.reply("alice", "i like purple").await.unwrap(), "Me too!");
+        assert_eq!(bot.reply("alice", "hello bot").await.unwrap(), "Hello, human!");
+    }
+
+    #[tokio::test]
+    async fn test_substitutions() {
+        use std::collections::HashMap;
+        use crate::substitutions::Substitutions;
+
+        let map: HashMap<String, String> = [
+            ("what's", "what is"),
+            ("what's up", "how are you"),
+            ("i", "you"),
+            ("i am", "you are"),
+            ("you", "me"),
+            ("c++", "c plus plus"),
+        ].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let sorted = crate::sorting::sort_list(map.clone());
+        let subs = Substitutions::new(&map, &sorted);
+
+        // Longer patterns win, and only whole words are substituted.
+        assert_eq!(subs.apply("what's up"), "how are you");
+        assert_eq!(subs.apply("what's this"), "what is this");
+        assert_eq!(subs.apply("hi, i am here"), "hi, you are here");
+        assert_eq!(subs.apply("i think i like c++!"), "you think you like c plus plus!");
+
+        // Substituted text isn't substituted again.
+        assert_eq!(subs.apply("i love you"), "you love me");
+        assert_eq!(subs.apply("i i i"), "you you you");
+        assert_eq!(subs.apply("nothing here"), "nothing here");
+        assert_eq!(Substitutions::default().apply("i am"), "i am");
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from("
+            ! sub i'm = i am
+            ! person i = you
+            ! person you = i
+
+            + i am *
+            - Why are you <star>?
+
+            + say *
+            - {person}<star>{/person}
+        ")).unwrap();
+        bot.sort_triggers();
+        assert_eq!(bot.reply("user", "I'm happy").await.unwrap(), "Why are you happy?");
+        assert_eq!(bot.reply("user", "say i like you").await.unwrap(), "you like i");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_replies() {
+        use std::sync::Arc;
+        use std::time::Duration;
+        use futures::FutureExt;
+        use rivescript_core::macros::Proxy;
+
+        let mut bot = crate::RiveScript::new();
+        bot.stream(String::from("
+            + my name is *
+            - <set name=<star>>Nice to meet you, <get name>.
+
+            + who am i
+            - You are <get name>, or <call>whoami</call>.
+
+            + wait
+            - <call>wait</call>
+        ")).unwrap();
+
+        // The subroutine yields partway, so the replies for different users interleave.
+        bot.set_subroutine("whoami", |proxy, _args| async move {
+            tokio::task::yield_now().await;
+            let username = crate::reply::CURRENT_USERNAME.try_with(|username| username.clone()).unwrap();
+            assert_eq!(username, proxy.current_username());
+            proxy.finish(username)
+        }.boxed());
+        bot.set_subroutine("wait", |proxy, _args| async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            proxy.finish(String::new())
+        }.boxed());
+        bot.sort_triggers();
+
+        let bot = Arc::new(bot);
+        let tasks: Vec<_> = (0..20).map(|i| {
+            let bot = Arc::clone(&bot);
+            tokio::spawn(async move {
+                let username = format!("user{i}");
+                let name = format!("name{i}");
+                assert_eq!(bot.reply(&username, &format!("my name is {name}")).await.unwrap(), format!("Nice to meet you, {name}."));
+                for _ in 0..5 {
+                    assert_eq!(bot.reply(&username, "who am i").await.unwrap(), format!("You are {name}, or {username}."));
+                }
+            })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // A reply that's dropped before it finishes doesn't leave its user behind.
+        assert!(bot.current_username().is_err());
+        assert!(tokio::time::timeout(Duration::from_millis(10), bot.reply("user0", "wait")).await.is_err());
+        assert!(bot.current_username().is_err());
+        assert_eq!(bot.reply("user1", "who am i").await.unwrap(), "You are name1, or user1.");
+    }
 }
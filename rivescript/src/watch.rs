@@ -0,0 +1,209 @@
+// Hot reloading of RiveScript documents when they change on disk.
+//
+// The watcher listens for filesystem events on the directories of the loaded
+// documents (rather than the files themselves, because many editors save a
+// file by writing a new one and renaming it over the old). The events are
+// queued up until `Watcher::reload` is called, which reloads the changed
+// documents together: all of them, or (if any fail) none of them.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, channel};
+
+use log::{debug, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+use crate::{ParseError, RiveScript};
+
+/// Watches the RiveScript documents that a bot has loaded, and reloads them when they change.
+///
+/// Changes are only applied when you call `reload`, so that you decide when the brain
+/// is swapped: for example, before each reply in a REPL, or on a timer in a server.
+/// The changed documents are swapped in together. If any of them fails to parse (or
+/// would break sorting), none of them are: the bot keeps its old brain, and every error
+/// is logged and returned. The documents are tried again, together, the next time any
+/// of them changes. User sessions aren't touched by a reload.
+///
+/// This needs the `watch` feature, which is enabled by default.
+///
+/// Example
+/// ```rust,no_run
+/// # use rivescript::{RiveScript, Watcher};
+/// # #[tokio::main]
+/// # async fn main() {
+///     let mut bot = RiveScript::new();
+///     bot.load_directory("./eg/brain").unwrap();
+///     bot.sort_triggers();
+///
+///     let mut watcher = Watcher::new(&bot).unwrap();
+///     loop {
+///         for change in watcher.reload(&mut bot) {
+///             println!("{change}");
+///         }
+///         // ...read a message and get a reply...
+///     }
+/// # }
+/// ```
+pub struct Watcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+
+    // The directories being watched.
+    directories: HashSet<PathBuf>,
+
+    // The files on disk that the loaded documents were read from, and the
+    // names (as given to `load_file`) of the documents that read them.
+    files: HashMap<PathBuf, Vec<String>>,
+
+    // The changed documents that couldn't be reloaded yet, because one of them failed.
+    pending: HashSet<String>,
+}
+
+/// The outcome of reloading one changed RiveScript document.
+#[derive(Debug)]
+pub enum Change {
+    /// The document was reloaded into the brain.
+    Reloaded(String),
+
+    /// The document couldn't be reloaded, so the bot kept its old brain.
+    Failed(String, Box<dyn Error>),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Reloaded(filename) => write!(f, "Reloaded {filename}"),
+            Change::Failed(filename, e) => write!(f, "Couldn't reload {filename}, keeping the old version: {e}"),
+        }
+    }
+}
+
+impl Watcher {
+    /// Start watching the documents that the bot has loaded so far.
+    pub fn new(bot: &RiveScript) -> notify::Result<Self> {
+        let (tx, events) = channel();
+        let mut watcher = Self {
+            watcher: notify::recommended_watcher(tx)?,
+            events,
+            directories: HashSet::new(),
+            files: HashMap::new(),
+            pending: HashSet::new(),
+        };
+        watcher.watch(bot)?;
+        Ok(watcher)
+    }
+
+    /// Also watch the documents that the bot has loaded since the watcher was created.
+    ///
+    /// `reload` does this for you, to pick up files that a reloaded document newly includes.
+    pub fn watch(&mut self, bot: &RiveScript) -> notify::Result<()> {
+        self.files.clear();
+        for (document, files) in bot.provenance.files() {
+            for file in files {
+                let Some(path) = absolute(Path::new(file)) else {
+                    continue;
+                };
+
+                if let Some(dir) = path.parent()
+                    && !self.directories.contains(dir)
+                {
+                    debug!("Watching {} for changes", dir.display());
+                    self.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+                    self.directories.insert(dir.to_path_buf());
+                }
+
+                let documents = self.files.entry(path).or_default();
+                if !documents.iter().any(|d| d == document) {
+                    documents.push(document.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reload the documents that have changed since the last call, returning what happened
+    /// to them: either every one was reloaded, or the errors of the ones that failed. This
+    /// doesn't block: if nothing has changed, it returns nothing.
+    pub fn reload(&mut self, bot: &mut RiveScript) -> Vec<Change> {
+        // Find the loaded documents that read any of the changed files.
+        let mut changed = false;
+        for event in self.events.try_iter() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Error watching RiveScript documents: {e}");
+                    continue;
+                },
+            };
+            if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Any) {
+                continue;
+            }
+
+            for path in event.paths.iter() {
+                if let Some(documents) = absolute(path).and_then(|path| self.files.get(&path)) {
+                    self.pending.extend(documents.iter().cloned());
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            return Vec::new();
+        }
+
+        // Reload them in load order. A file may be missing for a moment while an editor
+        // saves it: it'll be reloaded on the event for when it comes back.
+        let documents: Vec<String> = bot.loaded_files().into_iter().filter(|d| self.pending.contains(d)).collect();
+        self.pending = documents.iter().cloned().collect();
+        for document in documents.iter() {
+            if !Path::new(document).exists() {
+                debug!("{document} has gone missing; keeping the old brain for now");
+                return Vec::new();
+            }
+        }
+
+        let paths: Vec<&str> = documents.iter().map(String::as_str).collect();
+        let changes: Vec<Change> = match bot._reload_files(&paths) {
+            Ok(_) => {
+                self.pending.clear();
+                documents.into_iter().map(|document| {
+                    debug!("Reloaded {document}");
+                    Change::Reloaded(document)
+                }).collect()
+            },
+            Err(failures) => failures.into_iter().map(|(document, e)| {
+                match e.downcast_ref::<ParseError>() {
+                    Some(err) => {
+                        for diagnostic in err.diagnostics() {
+                            warn!("{diagnostic}");
+                        }
+                    },
+                    None => warn!("Couldn't reload {document}: {e}"),
+                };
+                Change::Failed(document, e)
+            }).collect(),
+        };
+
+        if !changes.is_empty()
+            && let Err(e) = self.watch(bot)
+        {
+            warn!("Error watching RiveScript documents: {e}");
+        }
+        changes
+    }
+}
+
+/// The absolute, canonical path of a file, even if the file itself doesn't exist right now.
+fn absolute(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Some(path);
+    }
+
+    let name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(name))
+}